@@ -5,13 +5,13 @@ set -ex
 rm -f *.csr *.key *.pem *.srl
 
 # Server CA
-openssl genrsa -out server_ca.key 3072
+openssl genrsa -traditional -out server_ca.key 3072
 openssl req -x509 -new -sha256 -days 1000 \
     -subj "/CN=Paas Server Certificate Authority" \
     -key server_ca.key -out server_ca.pem
 
 # Server, signed by server CA
-openssl genrsa -out server.key 3072
+openssl genrsa -traditional -out server.key 3072
 openssl req -new -sha256 \
     -subj "/CN=server" \
     -key server.key -out server.csr
@@ -22,17 +22,17 @@ openssl x509 -req -sha256 -days 1000 \
     -in server.csr -out server.pem \
 
 # Client CA
-openssl genrsa -out client_ca.key 3072
+openssl genrsa -traditional -out client_ca.key 3072
 openssl req -x509 -new -sha256 -days 1000 \
     -subj "/CN=Paas Client Certificate Authority" \
     -key client_ca.key -out client_ca.pem
 
 # Clients, signed by client CA
-openssl genrsa -out client1.key 3072
-openssl genrsa -out client2.key 3072
+openssl genrsa -traditional -out client1.key 3072
+openssl genrsa -traditional -out client2.key 3072
 
 openssl req -new -sha256 -days 1000 -subj "/CN=client1" -key client1.key -out client1.csr
-openssl req -new -sha256 -days 1000 -subj "/CN=client2" -key client2.key -out client2.csr
+openssl req -new -sha256 -days 1000 -subj "/CN=client2/OU=ops" -key client2.key -out client2.csr
 
 openssl x509 -req -sha256 -days 1000 \
     -CA client_ca.pem -CAkey client_ca.key -CAcreateserial \
@@ -44,14 +44,33 @@ openssl x509 -req -sha256 -days 1000 \
     -extfile client.ext \
     -in client2.csr -out client2.pem
 
+# Client with identities in subject alternative names (email, DNS, SPIFFE URI)
+openssl genrsa -traditional -out client3.key 3072
+openssl req -new -sha256 -days 1000 -subj "/CN=client3" -key client3.key -out client3.csr
+
+openssl x509 -req -sha256 -days 1000 \
+    -CA client_ca.pem -CAkey client_ca.key \
+    -extfile client_san.ext \
+    -in client3.csr -out client3.pem
+
+# Client with ambiguous identities (two CNs, two SPIFFE URIs)
+openssl genrsa -traditional -out ambiguous_client.key 3072
+openssl req -new -sha256 -days 1000 -subj "/CN=ambiguous1/CN=ambiguous2" \
+    -key ambiguous_client.key -out ambiguous_client.csr
+
+openssl x509 -req -sha256 -days 1000 \
+    -CA client_ca.pem -CAkey client_ca.key \
+    -extfile ambiguous_client.ext \
+    -in ambiguous_client.csr -out ambiguous_client.pem
+
 # Untrusted CA
-openssl genrsa -out untrusted_ca.key 3072
+openssl genrsa -traditional -out untrusted_ca.key 3072
 openssl req -x509 -new -sha256 -days 1000 \
     -subj "/CN=Untrusted Certificate Authority" \
     -key untrusted_ca.key -out untrusted_ca.pem
 
 # Untrusted client, signed by untrusted CA
-openssl genrsa -out untrusted_client.key 3072
+openssl genrsa -traditional -out untrusted_client.key 3072
 openssl req -new -sha256 -days 1000 -subj "/CN=untrusted_client" -key untrusted_client.key -out untrusted_client.csr
 
 openssl x509 -req -sha256 -days 1000 \
@@ -1,6 +1,6 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{fs::File, io::BufReader, path::Path, str::FromStr};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use rustls::{ciphersuite, internal::pemfile, ClientConfig, RootCertStore, SupportedCipherSuite};
 use tonic::transport::{Channel, ClientTlsConfig};
 
@@ -25,8 +25,8 @@ fn rustls_config(client: &str) -> Result<ClientConfig> {
         .add_pem_file(&mut buf_read(data_path.join("server_ca.pem"))?)
         .map_err(|_| anyhow!("could not add server CA to store"))?;
 
-    let cert_path = data_path.join(&format!("{}.pem", client));
-    let key_path = data_path.join(&format!("{}.key", client));
+    let cert_path = data_path.join(format!("{}.pem", client));
+    let key_path = data_path.join(format!("{}.key", client));
     let cert = pemfile::certs(&mut buf_read(&cert_path)?)
         .map_err(|_| anyhow!("could not parse the client certificate"))?;
     let keys = pemfile::rsa_private_keys(&mut buf_read(&key_path)?)
@@ -44,12 +44,43 @@ fn rustls_config(client: &str) -> Result<ClientConfig> {
     Ok(config)
 }
 
+/// Compression of gRPC messages, both sent and accepted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            _ => bail!("unknown compression '{}'", s),
+        }
+    }
+}
+
 pub async fn make_client(port: u16, client: &str) -> Result<ProcessServiceClient<Channel>> {
+    make_client_with_compression(port, client, Compression::None).await
+}
+
+pub async fn make_client_with_compression(
+    port: u16,
+    client: &str,
+    compression: Compression,
+) -> Result<ProcessServiceClient<Channel>> {
     let tls = ClientTlsConfig::new().rustls_client_config(rustls_config(client)?);
 
     let channel = Channel::from_shared(format!("https://localhost:{}", port))?
         .tls_config(tls)?
         .connect()
         .await?;
-    Ok(ProcessServiceClient::new(channel))
+    let client = ProcessServiceClient::new(channel);
+    Ok(match compression {
+        Compression::None => client,
+        Compression::Gzip => client.send_gzip().accept_gzip(),
+    })
 }
@@ -1,20 +1,48 @@
 use anyhow::{bail, Result};
-use structopt::{clap::AppSettings::AllowLeadingHyphen, StructOpt};
+use std::time::Duration;
+use structopt::{
+    clap::AppSettings::{AllowLeadingHyphen, TrailingVarArg},
+    StructOpt,
+};
+
 use uuid::Uuid;
 
-use paasc::make_client;
+use paasc::{make_client_with_compression, Compression};
 
 mod ops;
 
 #[derive(Debug, StructOpt)]
-enum Opt {
-    #[structopt(about = "Execute a process", setting = AllowLeadingHyphen)]
+struct Opt {
+    #[structopt(
+        long,
+        default_value = "none",
+        possible_values = &["none", "gzip"],
+        help = "Compression of the messages sent to the server, and accepted from it"
+    )]
+    compression: Compression,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(
+        about = "Execute a process",
+        setting = AllowLeadingHyphen,
+        setting = TrailingVarArg
+    )]
     Exec {
+        #[structopt(flatten)]
+        options: ops::ExecOptions,
+        #[structopt(flatten)]
+        dependencies: ops::DependencyOptions,
         #[structopt(help = "Argument list")]
         args: Vec<String>,
     },
     #[structopt(about = "Stream logs of the process with the given UUID")]
     Logs {
+        #[structopt(flatten)]
+        options: ops::LogOptions,
         #[structopt(help = "UUID of the process")]
         pid: Uuid,
     },
@@ -30,6 +58,66 @@ enum Opt {
         #[structopt(help = "UUID of the process")]
         pid: Uuid,
     },
+    #[structopt(about = "List the processes visible to the user, with their owners")]
+    List,
+    #[structopt(about = "Show the resource usage and limits of the user")]
+    Quota,
+    #[structopt(about = "Manage schedules that start processes periodically")]
+    Schedule(ScheduleCommand),
+    #[structopt(about = "Stream lifecycle events of the processes visible to the user")]
+    Events {
+        #[structopt(long, help = "UUID of a process to only stream the events of")]
+        pid: Option<Uuid>,
+    },
+}
+
+// Parsed once, so the size of the variants does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+enum ScheduleCommand {
+    #[structopt(
+        about = "Schedule a process, given a cron expression (in UTC) or an interval",
+        setting = AllowLeadingHyphen,
+        setting = TrailingVarArg
+    )]
+    Create {
+        #[structopt(
+            long,
+            required_unless = "every",
+            conflicts_with = "every",
+            help = "Cron expression, e.g. \"0 3 * * *\" for 03:00 UTC every day"
+        )]
+        cron: Option<String>,
+        #[structopt(
+            long,
+            parse(try_from_str = humantime::parse_duration),
+            help = "Interval between runs, e.g. \"1h 30m\""
+        )]
+        every: Option<Duration>,
+        #[structopt(flatten)]
+        options: ops::ExecOptions,
+        #[structopt(help = "Argument list")]
+        args: Vec<String>,
+    },
+    #[structopt(about = "List the schedules of the user")]
+    List,
+    #[structopt(about = "Pause the schedule with the given UUID")]
+    Pause {
+        #[structopt(help = "UUID of the schedule")]
+        id: Uuid,
+    },
+    #[structopt(about = "Resume the paused schedule with the given UUID")]
+    Resume {
+        #[structopt(help = "UUID of the schedule")]
+        id: Uuid,
+    },
+    #[structopt(
+        about = "Delete the schedule with the given UUID. Processes it has started are not affected."
+    )]
+    Delete {
+        #[structopt(help = "UUID of the schedule")]
+        id: Uuid,
+    },
 }
 
 #[tokio::main]
@@ -38,16 +126,40 @@ async fn main() -> Result<()> {
 
     let opt = Opt::from_args();
 
-    let client = make_client(8443, "client1").await?;
+    let client = make_client_with_compression(8443, "client1", opt.compression).await?;
 
-    match opt {
-        Opt::Exec { args } if args.is_empty() => {
+    match opt.command {
+        Command::Exec { args, .. } if args.is_empty() => {
+            bail!("empty process argument line");
+        }
+        Command::Exec {
+            options,
+            dependencies,
+            args,
+        } => ops::exec(client, args, options, dependencies).await,
+        Command::Logs { options, pid } => ops::logs(client, pid, options).await,
+        Command::Status { pid } => ops::status(client, pid).await,
+        Command::Stop { pid } => ops::stop(client, pid).await,
+        Command::List => ops::list(client).await,
+        Command::Quota => ops::quota(client).await,
+        Command::Schedule(ScheduleCommand::Create { args, .. }) if args.is_empty() => {
             bail!("empty process argument line");
         }
-        Opt::Exec { args } => ops::exec(client, args).await,
-        Opt::Logs { pid } => ops::logs(client, pid).await,
-        Opt::Status { pid } => ops::status(client, pid).await,
-        Opt::Stop { pid } => ops::stop(client, pid).await,
+        Command::Schedule(ScheduleCommand::Create {
+            cron,
+            every,
+            options,
+            args,
+        }) => ops::schedule(client, args, options, cron, every).await,
+        Command::Schedule(ScheduleCommand::List) => ops::list_schedules(client).await,
+        Command::Schedule(ScheduleCommand::Pause { id }) => {
+            ops::pause_schedule(client, id, true).await
+        }
+        Command::Schedule(ScheduleCommand::Resume { id }) => {
+            ops::pause_schedule(client, id, false).await
+        }
+        Command::Schedule(ScheduleCommand::Delete { id }) => ops::delete_schedule(client, id).await,
+        Command::Events { pid } => ops::events(client, pid).await,
     }?;
     Ok(())
 }
@@ -1,41 +1,481 @@
-use std::convert::TryInto;
+use std::{
+    convert::TryInto,
+    io::Write,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{anyhow, Result};
 use futures::{pin_mut, stream::StreamExt};
+use structopt::StructOpt;
 use tonic::transport::Channel;
 use uuid::Uuid;
 
 use paas_types::process_service_client::ProcessServiceClient;
 use paas_types::{
-    status_response::ExitStatus, ExecRequest, LogsRequest, StatusRequest, StopRequest,
+    completion_hook, dependencies, list_schedules_response, logs_request, probe, quota_response,
+    restart_policy, schedule_request,
+    status_response::{self, dependency, hook_delivery, ExitStatus, Health, Readiness, State},
+    watch_events_response::{self, Kind},
+    CompletionHook, DeleteScheduleRequest, Dependencies, ExecRequest, ListRequest,
+    ListSchedulesRequest, LogFilter, LogRateLimit, LogsRequest, PauseScheduleRequest,
+    PriorityClass, Probe, QuotaRequest, RestartPolicy, ScheduleRequest, StatusRequest, StopRequest,
+    WatchEventsRequest,
 };
 
-pub async fn exec(mut client: ProcessServiceClient<Channel>, args: Vec<String>) -> Result<()> {
-    let resp = client.exec(ExecRequest { args }).await?.into_inner();
+/// Options of `exec`, other than the argument list.
+#[derive(Debug, StructOpt)]
+pub struct ExecOptions {
+    #[structopt(long, help = "Group to share the process with")]
+    group: Option<String>,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "Memory to reserve for the process, in bytes"
+    )]
+    memory_bytes: u64,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "CPU to reserve for the process, in thousandths of a core"
+    )]
+    cpu_millis: u64,
+    #[structopt(
+        long,
+        default_value = "normal",
+        parse(try_from_str = parse_priority),
+        help = "Priority class of the process: interactive, normal or batch"
+    )]
+    priority: PriorityClass,
+    #[structopt(
+        long,
+        default_value = "never",
+        parse(try_from_str = parse_restart_mode),
+        help = "When to restart the process after it exits: never, on-failure or always"
+    )]
+    restart: restart_policy::Mode,
+    #[structopt(long, help = "Maximum number of restarts (unlimited if not given)")]
+    max_restarts: Option<u32>,
+    #[structopt(
+        long,
+        help = "Delay before the first restart in milliseconds, doubled after every restart"
+    )]
+    initial_backoff_ms: Option<u64>,
+    #[structopt(
+        long,
+        help = "Upper bound of the delay between restarts in milliseconds"
+    )]
+    max_backoff_ms: Option<u64>,
+    #[structopt(
+        long,
+        help = "Maximum lines logged per second, beyond which lines are suppressed"
+    )]
+    log_lines_per_sec: Option<u64>,
+    #[structopt(
+        long,
+        help = "Maximum bytes logged per second, beyond which lines are suppressed"
+    )]
+    log_bytes_per_sec: Option<u64>,
+    #[structopt(flatten)]
+    probes: ProbeOptions,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "URL to post a notification to once the process has finished (may be repeated)"
+    )]
+    notify_url: Vec<String>,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Command to run with a notification on stdin once the process has finished (may be repeated)"
+    )]
+    notify_command: Vec<String>,
+}
+
+/// Options of `exec` configuring health and readiness probes.
+#[derive(Debug, StructOpt)]
+pub struct ProbeOptions {
+    #[structopt(long, help = "Command whose success means the process is healthy")]
+    health_exec: Option<String>,
+    #[structopt(
+        long,
+        help = "TCP address that accepts connections while the process is healthy"
+    )]
+    health_tcp: Option<String>,
+    #[structopt(long, help = "Regex of a log line after which the process is healthy")]
+    health_log: Option<String>,
+    #[structopt(long, help = "Command whose success means the process is ready")]
+    ready_exec: Option<String>,
+    #[structopt(
+        long,
+        help = "TCP address that accepts connections once the process is ready"
+    )]
+    ready_tcp: Option<String>,
+    #[structopt(long, help = "Regex of a log line after which the process is ready")]
+    ready_log: Option<String>,
+    #[structopt(long, help = "Delay between probe checks in milliseconds")]
+    probe_interval_ms: Option<u64>,
+    #[structopt(long, help = "Kill and restart the process when it becomes unhealthy")]
+    restart_unhealthy: bool,
+}
+
+impl ProbeOptions {
+    fn probe(
+        &self,
+        exec: &Option<String>,
+        tcp: &Option<String>,
+        log: &Option<String>,
+    ) -> Option<Probe> {
+        let check = match (exec, tcp, log) {
+            (Some(command), _, _) => probe::Check::Exec(probe::Exec {
+                args: command.split_whitespace().map(Into::into).collect(),
+            }),
+            (None, Some(addr), _) => probe::Check::Tcp(addr.clone()),
+            (None, None, Some(regex)) => probe::Check::LogLine(regex.clone()),
+            (None, None, None) => return None,
+        };
+        Some(Probe {
+            check: Some(check),
+            interval_millis: self.probe_interval_ms.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Options of `exec` declaring the processes it depends on.
+#[derive(Debug, StructOpt)]
+pub struct DependencyOptions {
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "UUID of a process to wait for before starting (may be repeated)"
+    )]
+    after: Vec<Uuid>,
+    #[structopt(
+        long,
+        help = "Start once the processes given with --after exit, even if they fail"
+    )]
+    after_any_exit: bool,
+}
+
+/// How `logs` prints the logs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// The lines as they were logged.
+    Text,
+    /// JSON records exported by the server, one per line.
+    Json,
+}
+
+/// Options of `logs` choosing where the stream starts and whether it follows the logs.
+#[derive(Debug, StructOpt)]
+pub struct LogOptions {
+    #[structopt(
+        short,
+        long,
+        help = "Keep streaming the logs as they are produced, until the process finishes"
+    )]
+    follow: bool,
+    #[structopt(
+        long,
+        conflicts_with_all = &["tail", "since"],
+        help = "Position to resume the logs from, as printed when the stream is interrupted"
+    )]
+    offset: Option<u64>,
+    #[structopt(
+        long,
+        conflicts_with = "since",
+        help = "Start the given number of lines before the end"
+    )]
+    tail: Option<u64>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_since),
+        help = "Start at the lines logged since the given time (RFC 3339) or duration ago, e.g. \"10m\""
+    )]
+    since: Option<SystemTime>,
+    #[structopt(
+        long,
+        help = "Only print the lines matching the regex, with their line numbers"
+    )]
+    grep: Option<String>,
+    #[structopt(
+        short = "v",
+        long,
+        requires = "grep",
+        help = "Print the lines that do not match instead"
+    )]
+    invert_match: bool,
+    #[structopt(
+        short = "B",
+        long,
+        requires = "grep",
+        help = "Lines of context to print before each matching line"
+    )]
+    before_context: Option<u32>,
+    #[structopt(
+        short = "A",
+        long,
+        requires = "grep",
+        help = "Lines of context to print after each matching line"
+    )]
+    after_context: Option<u32>,
+    #[structopt(
+        short = "C",
+        long,
+        requires = "grep",
+        help = "Lines of context to print before and after each matching line"
+    )]
+    context: Option<u32>,
+    #[structopt(
+        long,
+        default_value = "text",
+        parse(try_from_str = parse_log_format),
+        help = "Output format: text, or json for records with the metadata of every line"
+    )]
+    output: LogFormat,
+}
+
+impl LogOptions {
+    fn start(&self) -> Option<logs_request::Start> {
+        match (self.offset, self.tail, self.since) {
+            (Some(offset), _, _) => Some(logs_request::Start::Offset(offset)),
+            (None, Some(lines), _) => Some(logs_request::Start::Tail(lines)),
+            (None, None, Some(time)) => Some(logs_request::Start::Since(
+                humantime::format_rfc3339(time).to_string(),
+            )),
+            (None, None, None) => None,
+        }
+    }
+
+    fn filter(&self) -> Option<LogFilter> {
+        self.grep.as_ref().map(|regex| LogFilter {
+            regex: regex.clone(),
+            invert: self.invert_match,
+            before_context: self.before_context.or(self.context).unwrap_or_default(),
+            after_context: self.after_context.or(self.context).unwrap_or_default(),
+        })
+    }
+}
+
+fn parse_since(s: &str) -> Result<SystemTime> {
+    match humantime::parse_rfc3339_weak(s) {
+        Ok(time) => Ok(time),
+        Err(_) => Ok(SystemTime::now() - humantime::parse_duration(s)?),
+    }
+}
+
+fn parse_log_format(s: &str) -> Result<LogFormat> {
+    match s {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(anyhow!("unknown output format '{}'", s)),
+    }
+}
+
+fn parse_priority(s: &str) -> Result<PriorityClass> {
+    match s {
+        "interactive" => Ok(PriorityClass::Interactive),
+        "normal" => Ok(PriorityClass::Normal),
+        "batch" => Ok(PriorityClass::Batch),
+        _ => Err(anyhow!("unknown priority class '{}'", s)),
+    }
+}
+
+fn parse_restart_mode(s: &str) -> Result<restart_policy::Mode> {
+    match s {
+        "never" => Ok(restart_policy::Mode::Never),
+        "on-failure" => Ok(restart_policy::Mode::OnFailure),
+        "always" => Ok(restart_policy::Mode::Always),
+        _ => Err(anyhow!("unknown restart policy '{}'", s)),
+    }
+}
+
+fn exec_request(args: Vec<String>, options: ExecOptions) -> ExecRequest {
+    ExecRequest {
+        args,
+        group: options.group.unwrap_or_default(),
+        memory_bytes: options.memory_bytes,
+        cpu_millis: options.cpu_millis,
+        priority: options.priority as i32,
+        restart: Some(RestartPolicy {
+            mode: options.restart as i32,
+            limit: options.max_restarts.map(restart_policy::Limit::MaxRestarts),
+            initial_backoff_millis: options.initial_backoff_ms.unwrap_or_default(),
+            max_backoff_millis: options.max_backoff_ms.unwrap_or_default(),
+        }),
+        dependencies: None,
+        log_rate_limit: Some(LogRateLimit {
+            lines_per_second: options.log_lines_per_sec.unwrap_or_default(),
+            bytes_per_second: options.log_bytes_per_sec.unwrap_or_default(),
+        }),
+        health_check: options.probes.probe(
+            &options.probes.health_exec,
+            &options.probes.health_tcp,
+            &options.probes.health_log,
+        ),
+        readiness_check: options.probes.probe(
+            &options.probes.ready_exec,
+            &options.probes.ready_tcp,
+            &options.probes.ready_log,
+        ),
+        restart_unhealthy: options.probes.restart_unhealthy,
+        completion_hooks: options
+            .notify_url
+            .into_iter()
+            .map(completion_hook::Action::WebhookUrl)
+            .chain(options.notify_command.iter().map(|command| {
+                completion_hook::Action::Command(completion_hook::Command {
+                    args: command.split_whitespace().map(Into::into).collect(),
+                })
+            }))
+            .map(|action| CompletionHook {
+                action: Some(action),
+            })
+            .collect(),
+    }
+}
+
+fn format_uuid(id: Option<paas_types::Uuid>) -> Result<String> {
+    let id = id.ok_or_else(|| anyhow!("expected an ID in the response"))?;
+    Ok(TryInto::<Uuid>::try_into(id)?.to_hyphenated().to_string())
+}
+
+pub async fn exec(
+    mut client: ProcessServiceClient<Channel>,
+    args: Vec<String>,
+    options: ExecOptions,
+    dependencies: DependencyOptions,
+) -> Result<()> {
+    let condition = match dependencies.after_any_exit {
+        true => dependencies::Condition::AnyExit,
+        false => dependencies::Condition::Success,
+    };
+    let resp = client
+        .exec(ExecRequest {
+            dependencies: Some(Dependencies {
+                processes: dependencies.after.into_iter().map(Into::into).collect(),
+                condition: condition as i32,
+            }),
+            ..exec_request(args, options)
+        })
+        .await?
+        .into_inner();
     let pid = resp
         .id
         .ok_or_else(|| anyhow!("expected process ID in the response"))?;
     println!("{}", TryInto::<Uuid>::try_into(pid)?.to_hyphenated());
+    if resp.queued {
+        eprintln!("Queued, will start once there is capacity");
+    }
+    if resp.pending {
+        eprintln!("Pending, will start once the processes it depends on exit");
+    }
     Ok(())
 }
 
-pub async fn logs(mut client: ProcessServiceClient<Channel>, id: Uuid) -> Result<()> {
-    let stream = client
-        .get_logs(LogsRequest {
-            id: Some(id.into()),
-        })
-        .await?
-        .into_inner();
+/// Marks lines that the server dropped because they were not received fast enough.
+fn print_skipped(lines: u64) {
+    if lines > 0 {
+        eprintln!("--- skipped {} lines, received too slowly ---", lines);
+    }
+}
 
-    pin_mut!(stream);
-    while let Some(resp) = stream.next().await {
-        let resp = resp?;
-        for l in resp.lines {
-            println!("{}", std::str::from_utf8(&l)?);
-        }
+/// Marks lines that the process logged beyond its rate limit.
+fn print_suppressed(lines: u64, bytes: u64) {
+    if lines > 0 {
+        eprintln!(
+            "--- {} lines ({} bytes) suppressed by the log rate limit ---",
+            lines, bytes
+        );
     }
+}
 
-    Ok(())
+/// Number of times in a row that `logs` reconnects after the stream is interrupted.
+const LOG_RECONNECTS: u32 = 3;
+
+pub async fn logs(
+    mut client: ProcessServiceClient<Channel>,
+    id: Uuid,
+    options: LogOptions,
+) -> Result<()> {
+    let mut start = options.start();
+    let filter = options.filter();
+    // Groups of lines that are not contiguous are separated, like `grep` does with context.
+    let separate = filter
+        .as_ref()
+        .is_some_and(|f| f.before_context > 0 || f.after_context > 0);
+    let mut last_number = None;
+    let mut reconnects = 0;
+    loop {
+        let request = LogsRequest {
+            id: Some(id.into()),
+            start: start.clone(),
+            follow: options.follow,
+            filter: filter.clone(),
+        };
+        let error = match options.output {
+            LogFormat::Text => {
+                let stream = client.get_logs(request).await?.into_inner();
+                pin_mut!(stream);
+                loop {
+                    let resp = match stream.next().await {
+                        None => return Ok(()),
+                        Some(Err(e)) => break e,
+                        Some(Ok(resp)) => resp,
+                    };
+                    reconnects = 0;
+                    // The stream is resumed after the last response received.
+                    start = Some(logs_request::Start::Offset(resp.cursor));
+                    print_skipped(resp.skipped_lines);
+                    if resp.lines.is_empty()
+                        && resp.skipped_lines == 0
+                        && resp.suppressed_lines == 0
+                    {
+                        eprintln!("--- restarted (run {}) ---", resp.run);
+                    }
+                    if resp.line_numbers.is_empty() {
+                        for l in resp.lines {
+                            println!("{}", std::str::from_utf8(&l)?);
+                        }
+                    } else {
+                        for (l, &number) in resp.lines.iter().zip(&resp.line_numbers) {
+                            if separate && last_number.is_some_and(|n| n + 1 != number) {
+                                println!("--");
+                            }
+                            last_number = Some(number);
+                            println!("{}:{}", number, std::str::from_utf8(l)?);
+                        }
+                    }
+                    print_suppressed(resp.suppressed_lines, resp.suppressed_bytes);
+                }
+            }
+            LogFormat::Json => {
+                let stream = client.export_logs(request).await?.into_inner();
+                pin_mut!(stream);
+                loop {
+                    let resp = match stream.next().await {
+                        None => return Ok(()),
+                        Some(Err(e)) => break e,
+                        Some(Ok(resp)) => resp,
+                    };
+                    reconnects = 0;
+                    start = Some(logs_request::Start::Offset(resp.cursor));
+                    print_skipped(resp.skipped_lines);
+                    std::io::stdout().write_all(&resp.records)?;
+                    print_suppressed(resp.suppressed_lines, resp.suppressed_bytes);
+                }
+            }
+        };
+        if reconnects == LOG_RECONNECTS {
+            if let Some(logs_request::Start::Offset(offset)) = start {
+                eprintln!("Resume with: paasc logs --offset {} {}", offset, id);
+            }
+            return Err(error.into());
+        }
+        reconnects += 1;
+        eprintln!("--- interrupted ({}), reconnecting ---", error.message());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
 }
 
 pub async fn status(mut client: ProcessServiceClient<Channel>, id: Uuid) -> Result<()> {
@@ -45,11 +485,86 @@ pub async fn status(mut client: ProcessServiceClient<Channel>, id: Uuid) -> Resu
         })
         .await?
         .into_inner();
-    match resp.exit_status {
-        None => println!("Status: running"),
-        Some(ExitStatus::Code(c)) => println!("Status: exited (code {})", c),
-        Some(ExitStatus::Signal(s)) => println!("Status: exited (signal {})", s),
+    match (resp.state(), resp.exit_status.clone()) {
+        (State::Pending, _) => println!("Status: pending"),
+        (State::Queued, _) => println!("Status: queued"),
+        (State::Cancelled, _) => println!("Status: cancelled ({})", resp.error),
+        (State::Failed, _) => println!("Status: failed to start ({})", resp.error),
+        (State::Started, None) => println!("Status: running"),
+        (State::Started, Some(ExitStatus::Code(c))) => println!("Status: exited (code {})", c),
+        (State::Started, Some(ExitStatus::Signal(s))) => {
+            println!("Status: exited (signal {})", s)
+        }
     };
+    match resp.health() {
+        Health::NotChecked => {}
+        Health::Unknown => println!("Health: unknown"),
+        Health::Healthy => println!("Health: healthy"),
+        Health::Unhealthy => println!("Health: unhealthy"),
+    }
+    match resp.readiness() {
+        Readiness::NotChecked => {}
+        Readiness::NotReady => println!("Ready: no"),
+        Readiness::Ready => println!("Ready: yes"),
+    }
+    if !resp.dependencies.is_empty() {
+        match resp.condition() {
+            dependencies::Condition::Success => println!("Depends on (success):"),
+            dependencies::Condition::AnyExit => println!("Depends on (any exit):"),
+        }
+        for d in &resp.dependencies {
+            let state = match d.state() {
+                dependency::State::Waiting => "waiting",
+                dependency::State::Satisfied => "satisfied",
+                dependency::State::Failed => "failed",
+            };
+            println!("  {} {}", format_uuid(d.id.clone())?, state);
+        }
+    }
+    if !resp.dependents.is_empty() {
+        println!("Dependents:");
+        for d in resp.dependents {
+            println!("  {}", format_uuid(Some(d))?);
+        }
+    }
+    if !resp.hooks.is_empty() {
+        println!("Completion hooks:");
+        for h in &resp.hooks {
+            let state = match h.state() {
+                hook_delivery::State::Waiting => "waiting",
+                hook_delivery::State::Delivering => "delivering",
+                hook_delivery::State::Delivered => "delivered",
+                hook_delivery::State::Undelivered => "undelivered",
+            };
+            println!("  {} {} (attempts: {})", h.target, state, h.attempts);
+            if !h.last_error.is_empty() {
+                println!("    Last error: {}", h.last_error);
+            }
+        }
+    }
+    if resp.schedule.is_some() {
+        println!("Schedule: {}", format_uuid(resp.schedule)?);
+    }
+    if resp.suppressed_log_lines > 0 {
+        println!(
+            "Suppressed logs: {} lines ({} bytes)",
+            resp.suppressed_log_lines, resp.suppressed_log_bytes
+        );
+    }
+    if resp.restarts > 0 {
+        println!("Restarts: {}", resp.restarts);
+        for (i, run) in resp.runs.iter().enumerate() {
+            match run.exit_status {
+                Some(status_response::run::ExitStatus::Code(c)) => {
+                    println!("Run {}: exited (code {})", i, c)
+                }
+                Some(status_response::run::ExitStatus::Signal(s)) => {
+                    println!("Run {}: exited (signal {})", i, s)
+                }
+                None => {}
+            }
+        }
+    }
     Ok(())
 }
 
@@ -61,3 +576,155 @@ pub async fn stop(mut client: ProcessServiceClient<Channel>, id: Uuid) -> Result
         .await?;
     Ok(())
 }
+
+pub async fn list(mut client: ProcessServiceClient<Channel>) -> Result<()> {
+    let resp = client.list(ListRequest {}).await?.into_inner();
+    for p in resp.processes {
+        let pid =
+            p.id.ok_or_else(|| anyhow!("expected process ID in the response"))?;
+        println!(
+            "{} {}",
+            TryInto::<Uuid>::try_into(pid)?.to_hyphenated(),
+            p.owner
+        );
+    }
+    Ok(())
+}
+
+pub async fn quota(mut client: ProcessServiceClient<Channel>) -> Result<()> {
+    let resp = client.get_quota(QuotaRequest {}).await?.into_inner();
+    let resources = [
+        ("Running processes", resp.running),
+        ("Reserved memory (bytes)", resp.memory_bytes),
+        ("Reserved CPU (millis)", resp.cpu_millis),
+        ("Retained logs (bytes)", resp.log_bytes),
+    ];
+    for (name, resource) in resources.iter() {
+        let resource = resource
+            .as_ref()
+            .ok_or_else(|| anyhow!("expected quota of each resource in the response"))?;
+        match resource.limit {
+            Some(quota_response::resource::Limit::Max(max)) => {
+                println!("{}: {} / {}", name, resource.used, max)
+            }
+            None => println!("{}: {} / unlimited", name, resource.used),
+        }
+    }
+    Ok(())
+}
+
+pub async fn schedule(
+    mut client: ProcessServiceClient<Channel>,
+    args: Vec<String>,
+    options: ExecOptions,
+    cron: Option<String>,
+    every: Option<Duration>,
+) -> Result<()> {
+    let trigger = match (cron, every) {
+        (Some(cron), _) => schedule_request::Trigger::Cron(cron),
+        (None, Some(every)) => schedule_request::Trigger::IntervalSeconds(every.as_secs()),
+        (None, None) => {
+            return Err(anyhow!(
+                "either a cron expression or an interval is required"
+            ))
+        }
+    };
+    let resp = client
+        .schedule(ScheduleRequest {
+            exec: Some(exec_request(args, options)),
+            trigger: Some(trigger),
+        })
+        .await?
+        .into_inner();
+    println!("{}", format_uuid(resp.id)?);
+    Ok(())
+}
+
+pub async fn list_schedules(mut client: ProcessServiceClient<Channel>) -> Result<()> {
+    let resp = client
+        .list_schedules(ListSchedulesRequest {})
+        .await?
+        .into_inner();
+    for s in resp.schedules {
+        let trigger = match s.trigger {
+            Some(list_schedules_response::schedule::Trigger::Cron(cron)) => {
+                format!("cron \"{}\"", cron)
+            }
+            Some(list_schedules_response::schedule::Trigger::IntervalSeconds(secs)) => format!(
+                "every {}",
+                humantime::format_duration(Duration::from_secs(secs))
+            ),
+            None => "never".into(),
+        };
+        println!("{} {}: {}", format_uuid(s.id)?, trigger, s.args.join(" "));
+        match s.paused {
+            true => println!("  Paused"),
+            false => println!("  Next run: {}", s.next_run),
+        }
+        if s.last_run.is_some() {
+            println!("  Runs: {}, last: {}", s.runs, format_uuid(s.last_run)?);
+        }
+        if !s.last_error.is_empty() {
+            println!("  Last run failed to start: {}", s.last_error);
+        }
+    }
+    Ok(())
+}
+
+pub async fn pause_schedule(
+    mut client: ProcessServiceClient<Channel>,
+    id: Uuid,
+    paused: bool,
+) -> Result<()> {
+    client
+        .pause_schedule(PauseScheduleRequest {
+            id: Some(id.into()),
+            paused,
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_schedule(mut client: ProcessServiceClient<Channel>, id: Uuid) -> Result<()> {
+    client
+        .delete_schedule(DeleteScheduleRequest {
+            id: Some(id.into()),
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn events(mut client: ProcessServiceClient<Channel>, id: Option<Uuid>) -> Result<()> {
+    let mut stream = client
+        .watch_events(WatchEventsRequest {
+            id: id.map(Into::into),
+        })
+        .await?
+        .into_inner();
+    while let Some(event) = stream.message().await? {
+        if event.missed > 0 {
+            eprintln!("--- missed up to {} events ---", event.missed);
+        }
+        let exit_status = match event.exit_status {
+            Some(watch_events_response::ExitStatus::Code(c)) => format!("code {}", c),
+            Some(watch_events_response::ExitStatus::Signal(s)) => format!("signal {}", s),
+            None => String::new(),
+        };
+        let details = match event.kind() {
+            Kind::Spawned => format!("spawned (pid {})", event.pid),
+            Kind::StdoutOpened => format!("stdout opened (run {})", event.run),
+            Kind::Stopping => format!("stopping (run {})", event.run),
+            Kind::Exited => format!("exited (run {}, {})", event.run, exit_status),
+            Kind::Restarted => format!("restarted (run {}, pid {})", event.run, event.pid),
+            Kind::Reaped => format!("reaped ({})", exit_status),
+        };
+        println!(
+            "{} {} {} {}",
+            event.time,
+            format_uuid(event.id)?,
+            event.owner,
+            details
+        );
+    }
+    Ok(())
+}
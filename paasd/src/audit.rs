@@ -0,0 +1,448 @@
+//! A tamper-evident audit log of the RPCs served by `paasd`.
+//!
+//! The log is a file of JSON lines, one entry per RPC.
+//! Every entry contains the hash of the previous entry,
+//! and its own hash covering all of its fields (including the previous hash),
+//! so that modifying, removing or reordering entries breaks the chain.
+//! The hashes are not keyed, so removing entries from the end of the log,
+//! or rewriting the whole log with a new chain, goes unnoticed.
+//!
+//! Entries are written by a thread of their own, so that RPCs do not wait for the disk.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tonic::Status;
+use uuid::Uuid;
+
+/// The hash preceding the first entry of the log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The RPC an audit log entry describes.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rpc {
+    Exec,
+    GetLogs,
+    ExportLogs,
+    GetStatus,
+    Stop,
+    List,
+    GetQuota,
+    Schedule,
+    ListSchedules,
+    PauseSchedule,
+    DeleteSchedule,
+    WatchEvents,
+}
+
+impl Rpc {
+    /// Returns the RPC with the given gRPC method name, e.g. `GetStatus`.
+    pub fn from_method(method: &str) -> Option<Self> {
+        match method {
+            "Exec" => Some(Rpc::Exec),
+            "GetLogs" => Some(Rpc::GetLogs),
+            "ExportLogs" => Some(Rpc::ExportLogs),
+            "GetStatus" => Some(Rpc::GetStatus),
+            "Stop" => Some(Rpc::Stop),
+            "List" => Some(Rpc::List),
+            "GetQuota" => Some(Rpc::GetQuota),
+            "Schedule" => Some(Rpc::Schedule),
+            "ListSchedules" => Some(Rpc::ListSchedules),
+            "PauseSchedule" => Some(Rpc::PauseSchedule),
+            "DeleteSchedule" => Some(Rpc::DeleteSchedule),
+            "WatchEvents" => Some(Rpc::WatchEvents),
+            _ => None,
+        }
+    }
+}
+
+/// The details of an RPC that are recorded in the audit log.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub rpc: Rpc,
+    pub caller: Option<String>,
+    pub peer: Option<SocketAddr>,
+    pub process: Option<Uuid>,
+    pub schedule: Option<Uuid>,
+    pub argv: Option<Vec<String>>,
+}
+
+impl Event {
+    pub fn new(rpc: Rpc) -> Self {
+        Self {
+            rpc,
+            caller: None,
+            peer: None,
+            process: None,
+            schedule: None,
+            argv: None,
+        }
+    }
+}
+
+/// A single line of the audit log.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub rpc: Rpc,
+    pub caller: Option<String>,
+    pub peer: Option<String>,
+    pub process: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    pub argv: Option<Vec<String>>,
+    /// The gRPC status code of the response, e.g. `Ok` or `NotFound`.
+    pub status: String,
+    pub message: Option<String>,
+    pub prev_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Entry {
+    /// Computes the hash of the entry, i.e. the hash of its JSON representation without `hash`.
+    fn compute_hash(&self) -> String {
+        let unhashed = Entry {
+            hash: None,
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed).expect("entry should always be serializable");
+        hex::encode(Sha256::digest(&json))
+    }
+}
+
+/// The error produced when verifying an audit log fails.
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {0}: malformed entry: {1}")]
+    Malformed(usize, serde_json::Error),
+    #[error("line {0}: expected sequence number {1}, found {2}")]
+    Sequence(usize, u64, u64),
+    #[error("line {0}: previous hash does not match the hash of the preceding entry")]
+    BrokenChain(usize),
+    #[error("line {0}: hash does not match the contents of the entry")]
+    BadHash(usize),
+}
+
+/// Verifies the integrity of the hash chain of an audit log.
+/// Returns the number of entries in the log.
+pub fn verify(reader: impl BufRead) -> Result<u64, VerifyError> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let lineno = i + 1;
+        let entry: Entry =
+            serde_json::from_str(&line).map_err(|e| VerifyError::Malformed(lineno, e))?;
+        if entry.seq != count {
+            return Err(VerifyError::Sequence(lineno, count, entry.seq));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(VerifyError::BrokenChain(lineno));
+        }
+        let hash = entry.compute_hash();
+        if entry.hash.as_ref() != Some(&hash) {
+            return Err(VerifyError::BadHash(lineno));
+        }
+        prev_hash = hash;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// An event waiting to be written, with its outcome.
+struct Record {
+    event: Event,
+    time: SystemTime,
+    status: tonic::Code,
+    message: Option<String>,
+}
+
+/// The end of the log, which new entries are chained to.
+struct Chain {
+    file: File,
+    /// The length of the entries written successfully.
+    len: u64,
+    seq: u64,
+    prev_hash: String,
+}
+
+impl Chain {
+    /// Opens the log at the given path, creating it if it does not exist.
+    /// The chain continues from the last complete entry, and anything after it,
+    /// e.g. an entry written part of the way before a crash, is truncated.
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut chain = Chain {
+            len: 0,
+            file,
+            seq: 0,
+            prev_hash: GENESIS_HASH.into(),
+        };
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut line = vec![];
+        let mut offset = 0;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            offset += read as u64;
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            if let Ok(Entry {
+                seq,
+                hash: Some(hash),
+                ..
+            }) = serde_json::from_slice(&line)
+            {
+                chain.seq = seq + 1;
+                chain.prev_hash = hash;
+                chain.len = offset;
+            }
+        }
+        if offset > chain.len {
+            warn!(
+                "truncating {} bytes after the last complete entry of the audit log {}",
+                offset - chain.len,
+                path.display()
+            );
+            chain.file.set_len(chain.len)?;
+        }
+        Ok(chain)
+    }
+
+    /// Returns the entry of the record, chained to the given previous entry.
+    fn entry(record: Record, seq: u64, prev_hash: String) -> Entry {
+        let event = record.event;
+        let mut entry = Entry {
+            seq,
+            timestamp: humantime::format_rfc3339_millis(record.time).to_string(),
+            rpc: event.rpc,
+            caller: event.caller,
+            peer: event.peer.map(|p| p.to_string()),
+            process: event.process.map(|p| p.to_hyphenated().to_string()),
+            schedule: event.schedule.map(|s| s.to_hyphenated().to_string()),
+            argv: event.argv,
+            status: format!("{:?}", record.status),
+            message: record.message,
+            prev_hash,
+            hash: None,
+        };
+        entry.hash = Some(entry.compute_hash());
+        entry
+    }
+
+    /// Appends the entries of the records to the file, and syncs it.
+    /// The chain only continues from the entries once they are written,
+    /// and the file is truncated back to the previous entries if writing fails part of the way.
+    fn append(&mut self, records: Vec<Record>) -> io::Result<()> {
+        let mut seq = self.seq;
+        let mut prev_hash = self.prev_hash.clone();
+        let mut lines = vec![];
+        for record in records {
+            let entry = Self::entry(record, seq, prev_hash);
+            serde_json::to_writer(&mut lines, &entry).expect("entry should always be serializable");
+            lines.push(b'\n');
+            seq += 1;
+            prev_hash = entry.hash.expect("entries are hashed");
+        }
+
+        if self.file.metadata()?.len() != self.len {
+            self.file.set_len(self.len)?;
+        }
+        if let Err(e) = self
+            .file
+            .write_all(&lines)
+            .and_then(|()| self.file.sync_data())
+        {
+            // Ignore error: the file is truncated again before the next write.
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += lines.len() as u64;
+        self.seq = seq;
+        self.prev_hash = prev_hash;
+        Ok(())
+    }
+
+    /// Writes the records received until the log is closed.
+    /// Records that arrive while the file is written are written together.
+    fn run(mut self, records: mpsc::Receiver<Record>) {
+        while let Ok(record) = records.recv() {
+            let mut batch = vec![record];
+            batch.extend(records.try_iter());
+            let count = batch.len();
+            if let Err(e) = self.append(batch) {
+                error!(
+                    "could not write {} entries to the audit log: {:?}",
+                    count, e
+                );
+            }
+        }
+    }
+}
+
+/// An append-only audit log file.
+pub struct AuditLog {
+    records: Option<mpsc::Sender<Record>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the given path, creating it if it does not exist.
+    /// New entries continue the hash chain of the existing ones.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let chain = Chain::open(path.as_ref())?;
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("audit-log".into())
+            .spawn(move || chain.run(rx))?;
+        Ok(Self {
+            records: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Queues an entry describing the event and its outcome, to be appended to the log.
+    /// Errors writing it are logged, but otherwise ignored.
+    pub fn record(&self, event: Event, error: Option<&Status>) {
+        let record = Record {
+            event,
+            time: SystemTime::now(),
+            status: error.map_or(tonic::Code::Ok, Status::code),
+            message: error.map(|e| e.message().to_string()),
+        };
+        let records = self
+            .records
+            .as_ref()
+            .expect("the log is open until dropped");
+        // Ignore error: the writer only stops once the log is dropped.
+        let _ = records.send(record);
+    }
+}
+
+impl Drop for AuditLog {
+    /// Waits for the queued entries to be written.
+    fn drop(&mut self) {
+        self.records.take();
+        if let Some(writer) = self.writer.take() {
+            // Ignore error: a panic of the writer has already been reported.
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("paasd-audit-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn event() -> Event {
+        let mut event = Event::new(Rpc::Stop);
+        event.caller = Some("alice".into());
+        event.process = Some(Uuid::new_v4());
+        event
+    }
+
+    fn write_entries(path: &Path, n: usize) {
+        let log = AuditLog::open(path).unwrap();
+        for _ in 0..n {
+            log.record(event(), Some(&Status::not_found("not found")));
+        }
+    }
+
+    #[test]
+    fn test_audit_log_verify() {
+        let path = temp_path("verify");
+        write_entries(&path, 3);
+        // Reopening continues the chain
+        write_entries(&path, 2);
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(verify(Cursor::new(&contents)).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_audit_log_tampered() {
+        let path = temp_path("tampered");
+        write_entries(&path, 3);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let modified = contents.replacen("alice", "mallory", 1);
+        assert!(matches!(
+            verify(Cursor::new(&modified)),
+            Err(VerifyError::BadHash(1))
+        ));
+
+        let mut lines: Vec<_> = contents.lines().collect();
+        lines.remove(1);
+        assert!(matches!(
+            verify(Cursor::new(lines.join("\n"))),
+            Err(VerifyError::Sequence(2, 1, 2))
+        ));
+    }
+
+    #[test]
+    fn test_audit_log_partial_write() {
+        let path = temp_path("partial");
+        let record = || Record {
+            event: event(),
+            time: SystemTime::now(),
+            status: tonic::Code::Ok,
+            message: None,
+        };
+        let mut chain = Chain::open(&path).unwrap();
+        chain.append(vec![record(), record()]).unwrap();
+        // An entry written part of the way is replaced by the next one
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"seq":2,"timest"#).unwrap();
+        chain.append(vec![record()]).unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(verify(Cursor::new(&contents)).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_audit_log_open_truncated() {
+        let path = temp_path("truncated");
+        write_entries(&path, 2);
+        // The daemon crashed while writing an entry
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"seq":2,"timest"#).unwrap();
+        // Reopening drops the partial entry, and continues the chain from the previous one
+        write_entries(&path, 1);
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(verify(Cursor::new(&contents)).unwrap(), 3);
+    }
+}
@@ -0,0 +1,383 @@
+//! Backpressure on the log streams of clients that read slower than processes log.
+//!
+//! A task reads the batches of every stream and queues them in a bounded buffer,
+//! from which they are sent as fast as the client receives them.
+//! When the buffer is full, the policy for slow consumers decides whether the task waits for the client,
+//! drops batches and tells the client how many lines it missed, or ends the stream.
+//! A stream that can not queue anything for the idle timeout is ended,
+//! so that clients that are connected but no longer reading do not hold on to the stream forever,
+//! and the task ends as soon as the client goes away, which HTTP/2 keepalive pings detect.
+
+use std::{mem, time::Duration};
+
+use futures::{pin_mut, Stream, StreamExt};
+use serde::Deserialize;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
+use tonic::Status;
+use worker::LogBatch;
+
+/// What a stream does when its send buffer is full.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlowConsumer {
+    /// Wait until the client has received enough, up to the idle timeout.
+    #[default]
+    Block,
+    /// Drop batches until there is room again, and mark the gap in the next batch sent.
+    /// The starts of new runs and the markers of suppressed lines are not dropped,
+    /// but wait for room up to the idle timeout.
+    Drop,
+    /// End the stream with `RESOURCE_EXHAUSTED`.
+    Disconnect,
+}
+
+/// Settings of streams of logs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamConfig {
+    /// Number of responses buffered per stream, waiting to be sent.
+    pub send_buffer: usize,
+    pub slow_consumer: SlowConsumer,
+    /// A stream that can not queue a response for this long is ended, in seconds.
+    pub idle_timeout_secs: u64,
+    /// Interval of HTTP/2 pings checking that clients are still connected, in seconds.
+    /// 0 disables the pings.
+    pub keepalive_interval_secs: u64,
+    /// Connections that do not answer a ping within this time are closed, in seconds.
+    pub keepalive_timeout_secs: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            send_buffer: 16,
+            slow_consumer: SlowConsumer::Block,
+            idle_timeout_secs: 300,
+            keepalive_interval_secs: 60,
+            keepalive_timeout_secs: 20,
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.keepalive_interval_secs)).filter(|d| !d.is_zero())
+    }
+
+    pub fn keepalive_timeout(&self) -> Duration {
+        Duration::from_secs(self.keepalive_timeout_secs)
+    }
+}
+
+/// A batch of a stream, with the number of lines dropped before it.
+/// A batch without lines after dropped lines marks the end of the gap, rather than a new run.
+#[derive(Debug)]
+pub struct Queued {
+    pub batch: LogBatch,
+    pub skipped: usize,
+}
+
+/// Returns the stream of batches, read ahead into a bounded buffer by a task.
+pub fn bounded<S>(
+    batches: S,
+    config: &StreamConfig,
+) -> impl Stream<Item = Result<Queued, Status>> + Send + Sync + 'static
+where
+    S: Stream<Item = Result<LogBatch, Status>> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel(config.send_buffer.max(1));
+    // How the stream ends, if not with the last batch.
+    let (end_tx, end_rx) = oneshot::channel();
+    let policy = config.slow_consumer;
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    tokio::spawn(async move {
+        if let Err(status) = feed(batches, &tx, policy, idle_timeout).await {
+            // Ignore error: the client may be gone.
+            let _ = end_tx.send(status);
+        }
+    });
+    async_stream::stream! {
+        while let Some(queued) = rx.recv().await {
+            yield Ok(queued);
+        }
+        if let Ok(status) = end_rx.await {
+            yield Err(status);
+        }
+    }
+}
+
+fn idle(idle_timeout: Duration) -> Status {
+    Status::deadline_exceeded(format!(
+        "The client has not received the logs for {} seconds",
+        idle_timeout.as_secs()
+    ))
+}
+
+/// Queues the batch, waiting for room in the buffer until the idle timeout,
+/// counted from when the buffer was found full.
+/// Returns `false` if the client has gone away.
+async fn send(
+    tx: &mpsc::Sender<Queued>,
+    queued: Queued,
+    full_since: &mut Option<Instant>,
+    idle_timeout: Duration,
+) -> Result<bool, Status> {
+    let permit = match tx.try_reserve() {
+        Ok(permit) => permit,
+        Err(mpsc::error::TrySendError::Closed(_)) => return Ok(false),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            let since = *full_since.get_or_insert_with(Instant::now);
+            match time::timeout_at(since + idle_timeout, tx.reserve()).await {
+                Ok(Ok(permit)) => permit,
+                Ok(Err(_)) => return Ok(false),
+                Err(_) => return Err(idle(idle_timeout)),
+            }
+        }
+    };
+    permit.send(queued);
+    *full_since = None;
+    Ok(true)
+}
+
+/// Queues the batches, according to the policy when the buffer is full.
+/// Returns an error if the stream should end with one, and `Ok` once the client has gone away.
+async fn feed<S>(
+    batches: S,
+    tx: &mpsc::Sender<Queued>,
+    policy: SlowConsumer,
+    idle_timeout: Duration,
+) -> Result<(), Status>
+where
+    S: Stream<Item = Result<LogBatch, Status>>,
+{
+    pin_mut!(batches);
+    let mut skipped = 0;
+    // The position and run after the dropped lines, for the end of the gap.
+    let mut gap = LogBatch::default();
+    // When the buffer was found full, unless there has been room since.
+    let mut full_since = None;
+    loop {
+        let batch = tokio::select! {
+            batch = batches.next() => batch,
+            _ = tx.closed() => return Ok(()),
+        };
+        let batch = match batch {
+            Some(batch) => batch?,
+            None => break,
+        };
+        // The starts of new runs are never dropped, and are kept apart from the end of a gap,
+        // which is also a batch without lines.
+        let new_run = batch.starts_run();
+        if new_run && skipped > 0 {
+            let end_of_gap = Queued {
+                batch: mem::take(&mut gap),
+                skipped: mem::take(&mut skipped),
+            };
+            if !send(tx, end_of_gap, &mut full_since, idle_timeout).await? {
+                return Ok(());
+            }
+        }
+        // Neither are the markers of lines suppressed by the rate limit,
+        // which readers would otherwise not learn about.
+        let marker = new_run || batch.suppressed.is_some();
+        let queued = Queued { batch, skipped };
+        let wait = match policy {
+            SlowConsumer::Block => true,
+            SlowConsumer::Drop => marker,
+            SlowConsumer::Disconnect => false,
+        };
+        if wait {
+            if !send(tx, queued, &mut full_since, idle_timeout).await? {
+                return Ok(());
+            }
+            skipped = 0;
+            continue;
+        }
+        match tx.try_reserve() {
+            Ok(permit) => {
+                permit.send(queued);
+                skipped = 0;
+                full_since = None;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                let since = *full_since.get_or_insert_with(Instant::now);
+                if policy == SlowConsumer::Disconnect {
+                    return Err(Status::resource_exhausted(
+                        "The client is not receiving the logs as fast as they are sent",
+                    ));
+                }
+                if since.elapsed() >= idle_timeout {
+                    return Err(idle(idle_timeout));
+                }
+                skipped += queued.batch.lines.len();
+                gap = LogBatch {
+                    run: queued.batch.run,
+                    cursor: queued.batch.cursor,
+                    ..Default::default()
+                };
+            }
+        }
+    }
+    if skipped > 0 {
+        // The client is told about lines dropped after the last batch queued.
+        let end_of_gap = Queued {
+            batch: gap,
+            skipped,
+        };
+        send(tx, end_of_gap, &mut full_since, idle_timeout).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tonic::Code;
+    use worker::Suppressed;
+
+    use super::*;
+
+    #[allow(clippy::result_large_err)]
+    fn batches(n: usize) -> impl Stream<Item = Result<LogBatch, Status>> + Send + 'static {
+        futures::stream::iter((1..=n).map(|i| {
+            Ok(LogBatch {
+                lines: vec!["line".into(); 10],
+                cursor: i * 10,
+                ..Default::default()
+            })
+        }))
+    }
+
+    fn config(slow_consumer: SlowConsumer, idle_timeout_secs: u64) -> StreamConfig {
+        StreamConfig {
+            send_buffer: 2,
+            slow_consumer,
+            idle_timeout_secs,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the stream after giving the task time to fill the buffer.
+    async fn read_slowly(
+        stream: impl Stream<Item = Result<Queued, Status>>,
+    ) -> Vec<Result<Queued, Status>> {
+        time::sleep(Duration::from_millis(100)).await;
+        stream.collect().await
+    }
+
+    #[tokio::test]
+    async fn test_bounded_block() {
+        let stream = bounded(batches(100), &config(SlowConsumer::Block, 10));
+        let queued = read_slowly(stream).await;
+        assert_eq!(queued.len(), 100);
+        assert!(queued.iter().all(|q| q.as_ref().unwrap().skipped == 0));
+    }
+
+    #[tokio::test]
+    async fn test_bounded_drop() {
+        let stream = bounded(batches(100), &config(SlowConsumer::Drop, 10));
+        let queued: Vec<Queued> = read_slowly(stream)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        // The buffer is filled, and the lines of the other batches are dropped
+        assert_eq!(queued.len(), 3);
+        assert_eq!(queued[0].batch.cursor, 10);
+        assert_eq!(queued[1].batch.cursor, 20);
+        let gap = &queued[2];
+        assert_eq!(gap.skipped, 980);
+        assert!(gap.batch.lines.is_empty());
+        assert_eq!(gap.batch.cursor, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_drop_restart() {
+        let restart = LogBatch {
+            run: 1,
+            cursor: 1000,
+            ..Default::default()
+        };
+        let batches = batches(100).chain(futures::stream::iter(vec![Ok(restart)]));
+        let stream = bounded(batches, &config(SlowConsumer::Drop, 10));
+        let queued: Vec<Queued> = read_slowly(stream)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        // The start of the new run is kept apart from the end of the gap
+        assert_eq!(queued.len(), 4);
+        let gap = &queued[2];
+        assert_eq!(gap.skipped, 980);
+        assert_eq!(gap.batch.run, 0);
+        let restart = &queued[3];
+        assert_eq!(restart.skipped, 0);
+        assert!(restart.batch.lines.is_empty());
+        assert_eq!(restart.batch.run, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_drop_suppressed() {
+        // The process goes quiet, and the end of the window marks the lines it suppressed
+        let marker = LogBatch {
+            cursor: 1001,
+            suppressed: Some(Suppressed {
+                lines: 90,
+                bytes: 900,
+            }),
+            ..Default::default()
+        };
+        let batches = batches(100).chain(futures::stream::iter(vec![Ok(marker)]));
+        let stream = bounded(batches, &config(SlowConsumer::Drop, 10));
+        let queued: Vec<Queued> = read_slowly(stream)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(queued.len(), 3);
+        let marker = &queued[2];
+        assert_eq!(marker.skipped, 980);
+        assert_eq!(marker.batch.cursor, 1001);
+        assert_eq!(marker.batch.suppressed.unwrap().lines, 90);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_drop_idle_timeout() {
+        // The process logs nothing for longer than the idle timeout before the buffer fills up
+        let batches = batches(2).chain(
+            futures::stream::once(time::sleep(Duration::from_millis(1500)))
+                .flat_map(|_| batches(1)),
+        );
+        let stream = bounded(batches, &config(SlowConsumer::Drop, 1));
+        time::sleep(Duration::from_millis(1700)).await;
+        let queued: Vec<Queued> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(queued.len(), 3);
+        assert_eq!(queued[2].skipped, 10);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_disconnect() {
+        let stream = bounded(batches(100), &config(SlowConsumer::Disconnect, 10));
+        let queued = read_slowly(stream).await;
+        assert_eq!(queued.len(), 3);
+        assert!(queued[..2].iter().all(Result::is_ok));
+        let error = queued[2].as_ref().unwrap_err();
+        assert_eq!(error.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_idle_timeout() {
+        let stream = bounded(batches(100), &config(SlowConsumer::Block, 1));
+        pin_mut!(stream);
+        assert!(stream.next().await.unwrap().is_ok());
+        time::sleep(Duration::from_millis(1500)).await;
+        let queued: Vec<_> = stream.collect().await;
+        // The batches queued before the timeout are still received
+        assert_eq!(queued.len(), 3);
+        let error = queued[2].as_ref().unwrap_err();
+        assert_eq!(error.code(), Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,23 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+
+use paasd::audit;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Verify the integrity of the hash chain of a paasd audit log")]
+struct Opt {
+    #[structopt(help = "Path to the audit log")]
+    path: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let file =
+        File::open(&opt.path).with_context(|| format!("could not open {}", opt.path.display()))?;
+    let count = audit::verify(BufReader::new(file))
+        .with_context(|| format!("{} failed verification", opt.path.display()))?;
+    println!("OK: {} entries", count);
+    Ok(())
+}
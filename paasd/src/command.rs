@@ -0,0 +1,302 @@
+use std::{
+    env, fmt, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{de, Deserialize, Deserializer};
+
+/// Whether a matching command may be executed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+fn deserialize_pattern<'de, D: Deserializer<'de>>(d: D) -> Result<Pattern, D::Error> {
+    let s = String::deserialize(d)?;
+    Pattern::new(&s).map_err(de::Error::custom)
+}
+
+fn deserialize_opt_pattern<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Pattern>, D::Error> {
+    deserialize_pattern(d).map(Some)
+}
+
+/// A rule of the command policy.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandRule {
+    /// Name of the rule, reported to users when the rule denies a command.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub action: Action,
+    /// Glob pattern matched against the absolute path of the executable,
+    /// e.g. `/usr/bin/*`.
+    #[serde(deserialize_with = "deserialize_pattern")]
+    pub executable: Pattern,
+    /// Glob pattern matched against the arguments joined by single spaces,
+    /// e.g. `-rf *`. If not given, any arguments match.
+    #[serde(default, deserialize_with = "deserialize_opt_pattern")]
+    pub args: Option<Pattern>,
+    /// Users the rule applies to.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Roles the rule applies to.
+    /// If neither users nor roles are given, the rule applies to everyone.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl CommandRule {
+    fn matches(&self, executable: &Path, args: &[&str]) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        self.executable.matches_path_with(executable, options)
+            && self
+                .args
+                .as_ref()
+                .is_none_or(|p| p.matches(&args.join(" ")))
+    }
+}
+
+/// Describes the rule that decided whether a command may be executed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// The rule with the given index and optional name.
+    Rule(usize, Option<String>),
+    /// No rule matched, the default action was taken.
+    Default,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Rule(_, Some(name)) => write!(f, "rule '{}'", name),
+            Decision::Rule(i, None) => write!(f, "rule #{}", i + 1),
+            Decision::Default => f.write_str("default command policy"),
+        }
+    }
+}
+
+/// The error produced when the command policy does not allow a command.
+#[derive(Debug, thiserror::Error)]
+#[error("Not permitted to execute '{executable}': denied by {decision}")]
+pub struct CommandDenied {
+    pub executable: String,
+    pub decision: Decision,
+}
+
+/// Restricts which commands users may execute.
+///
+/// Rules are evaluated in order, and the first rule matching the user and the command decides.
+/// If no rule matches, the `default` action is taken.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandPolicy {
+    #[serde(default)]
+    pub default: Action,
+    #[serde(default)]
+    pub rules: Vec<CommandRule>,
+}
+
+impl CommandPolicy {
+    /// Reads the command policy from the TOML file at the given path.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("could not read command policy {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("could not parse command policy {}", path.display()))
+    }
+
+    /// Checks whether a command may be executed by a user.
+    /// `applies_to` decides whether a rule, given its users and roles, applies to the user.
+    pub fn check(
+        &self,
+        argv0: &str,
+        args: &[&str],
+        mut applies_to: impl FnMut(&CommandRule) -> bool,
+    ) -> Result<(), CommandDenied> {
+        let executable = resolve_executable(argv0);
+        let matched = self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, r)| applies_to(r) && r.matches(&executable, args));
+        let (action, decision) = match matched {
+            Some((i, r)) => (r.action, Decision::Rule(i, r.name.clone())),
+            None => (self.default, Decision::Default),
+        };
+        match action {
+            Action::Allow => Ok(()),
+            Action::Deny => Err(CommandDenied {
+                executable: executable.display().to_string(),
+                decision,
+            }),
+        }
+    }
+}
+
+/// Resolves the executable the same way as spawning a process does:
+/// names without a slash are looked up in `PATH`,
+/// and other paths are made absolute and normalized,
+/// so that e.g. `/usr/bin/../bin/rm` matches the rules for `/usr/bin/rm`.
+/// If the executable can not be found, the name is returned unchanged.
+fn resolve_executable(argv0: &str) -> PathBuf {
+    if argv0.contains('/') {
+        let path = Path::new(argv0);
+        // Processes are spawned in the working directory of the daemon.
+        return match env::current_dir() {
+            Ok(cwd) => normalize(&cwd.join(path)),
+            Err(_) => normalize(path),
+        };
+    }
+    env::var_os("PATH")
+        .and_then(|paths| {
+            env::split_paths(&paths)
+                .map(|dir| dir.join(argv0))
+                .find(|path| is_executable(path))
+        })
+        .unwrap_or_else(|| PathBuf::from(argv0))
+}
+
+/// Removes `.` and `..` components and repeated separators from the path, lexically.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy() -> CommandPolicy {
+        toml::from_str(
+            r#"
+            default = "deny"
+
+            [[rules]]
+            name = "no-rm-rf"
+            action = "deny"
+            executable = "/*/rm"
+            args = "-rf *"
+
+            [[rules]]
+            action = "allow"
+            executable = "/*/rm"
+            users = ["alice"]
+
+            [[rules]]
+            action = "allow"
+            executable = "/**/echo"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn for_user(uid: &'static str) -> impl FnMut(&CommandRule) -> bool {
+        move |r| r.users.is_empty() || r.users.iter().any(|u| u == uid)
+    }
+
+    #[test]
+    fn test_command_policy_default() {
+        assert!(CommandPolicy::default()
+            .check("rm", &["-rf", "/"], |_| true)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_command_policy_named_rule() {
+        let err = policy()
+            .check("/bin/rm", &["-rf", "/"], for_user("alice"))
+            .unwrap_err();
+        assert_eq!(err.decision, Decision::Rule(0, Some("no-rm-rf".into())));
+        assert_eq!(
+            err.to_string(),
+            "Not permitted to execute '/bin/rm': denied by rule 'no-rm-rf'"
+        );
+    }
+
+    #[test]
+    fn test_command_policy_user_rule() {
+        assert!(policy()
+            .check("/bin/rm", &["foo"], for_user("alice"))
+            .is_ok());
+        let err = policy()
+            .check("/bin/rm", &["foo"], for_user("eve"))
+            .unwrap_err();
+        assert_eq!(err.decision, Decision::Default);
+    }
+
+    #[test]
+    fn test_command_policy_path_lookup() {
+        assert!(policy().check("echo", &["hi"], for_user("eve")).is_ok());
+        assert!(policy()
+            .check("this_command_does_not_exist", &[], for_user("eve"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_command_policy_glob_separator() {
+        let policy: CommandPolicy = toml::from_str(
+            r#"
+            default = "deny"
+
+            [[rules]]
+            action = "allow"
+            executable = "/usr/*"
+            "#,
+        )
+        .unwrap();
+        // `*` does not match `/`
+        assert!(policy.check("/usr/echo", &[], |_| true).is_ok());
+        assert!(policy.check("/usr/bin/echo", &[], |_| true).is_err());
+    }
+
+    #[test]
+    fn test_command_policy_normalized_path() {
+        let policy: CommandPolicy = toml::from_str(
+            r#"
+            [[rules]]
+            action = "deny"
+            executable = "/usr/bin/rm"
+            "#,
+        )
+        .unwrap();
+        let up = "../".repeat(env::current_dir().unwrap().components().count());
+        let relative = format!("{}usr/bin/rm", up);
+        for argv0 in &[
+            "/usr/bin/rm",
+            "/usr/bin/../bin/rm",
+            "/usr//bin/rm",
+            "/usr/bin/./rm",
+            "/../usr/bin/rm",
+            &relative,
+        ] {
+            let err = policy.check(argv0, &[], |_| true).unwrap_err();
+            assert_eq!(err.executable, "/usr/bin/rm");
+        }
+        assert!(policy.check("/usr/bin/rmdir", &[], |_| true).is_ok());
+    }
+}
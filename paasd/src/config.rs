@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tonic::Status;
+
+use crate::{
+    backpressure::StreamConfig, group::GroupConfig, hook::HookConfig, logfile::LogConfig,
+    policy::Role, quota::QuotaConfig, ratelimit::RateLimitConfig, scheduler::SchedulerConfig,
+    user::IdentitySource,
+};
+
+/// Settings of the daemon, read from a TOML file.
+/// Every setting is optional, falling back to its default value.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The part of the client certificate used to identify the user.
+    pub identity: IdentitySource,
+    /// Roles granting permissions on processes of other users, by role name.
+    pub roles: HashMap<String, Role>,
+    /// Group ownership of processes.
+    pub groups: GroupConfig,
+    /// Path to a TOML file restricting which commands users may execute.
+    pub command_policy_file: Option<PathBuf>,
+    /// Path to the audit log file. If not given, RPCs are not audited.
+    pub audit_log: Option<PathBuf>,
+    /// Per-user limits on processes and their resources.
+    pub quotas: QuotaConfig,
+    /// Admission of processes when the host is at capacity.
+    pub scheduler: SchedulerConfig,
+    /// Rate limits of RPCs, per user.
+    pub rate_limits: RateLimitConfig,
+    /// Delivery of completion hooks.
+    pub hooks: HookConfig,
+    /// Checks of health and readiness probes.
+    pub probes: ProbeConfig,
+    /// Persistence of logs to files.
+    pub logs: LogConfig,
+    /// Backpressure and timeouts of log streams.
+    pub streams: StreamConfig,
+    /// Compression of the messages sent to clients.
+    pub compression: Compression,
+}
+
+/// Compression of gRPC messages.
+/// Messages are only compressed for clients that accept the compression,
+/// and compressed messages of clients are accepted whatever the setting.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// Settings of health and readiness probes.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// Hosts that TCP probes may connect to. TCP probes are not allowed unless hosts are listed,
+    /// as they connect from the host of `paasd`.
+    pub tcp_hosts: Vec<String>,
+}
+
+impl ProbeConfig {
+    /// Validates the `host:port` address of a TCP probe.
+    #[allow(clippy::result_large_err)]
+    pub fn tcp(&self, addr: &str) -> Result<(), Status> {
+        let host = match addr.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid TCP probe address '{}', expected host:port",
+                    addr
+                )))
+            }
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if !self.tcp_hosts.iter().any(|h| h == host) {
+            return Err(Status::permission_denied(format!(
+                "TCP probes of '{}' are not allowed",
+                host
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Reads the configuration from the TOML file at the given path.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("could not read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("could not parse config file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_empty() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.identity, IdentitySource::CommonName);
+    }
+
+    #[test]
+    fn test_config_identity() {
+        let config: Config = toml::from_str(r#"identity = "spiffe""#).unwrap();
+        assert_eq!(config.identity, IdentitySource::Spiffe);
+        let config: Config = toml::from_str(r#"identity = "common-name""#).unwrap();
+        assert_eq!(config.identity, IdentitySource::CommonName);
+    }
+
+    #[test]
+    fn test_config_roles() {
+        let config: Config = toml::from_str(
+            r#"
+            [roles.auditor]
+            permissions = ["list", "get-logs", "get-status"]
+            organizational_units = ["audit"]
+            "#,
+        )
+        .unwrap();
+        let auditor = &config.roles["auditor"];
+        assert_eq!(auditor.permissions.len(), 3);
+        assert!(auditor.users.is_empty());
+        assert_eq!(auditor.organizational_units, vec!["audit".to_string()]);
+    }
+
+    #[test]
+    fn test_config_compression() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.compression, Compression::None);
+        let config: Config = toml::from_str(r#"compression = "gzip""#).unwrap();
+        assert_eq!(config.compression, Compression::Gzip);
+        assert!(toml::from_str::<Config>(r#"compression = "brotli""#).is_err());
+    }
+
+    #[test]
+    fn test_config_probes() {
+        let config: Config = toml::from_str(
+            r#"
+            [probes]
+            tcp_hosts = ["localhost", "::1"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.probes.tcp("localhost:8080").is_ok());
+        assert!(config.probes.tcp("[::1]:8080").is_ok());
+        let err = config.probes.tcp("10.0.0.1:22").unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = config.probes.tcp("localhost").unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        assert!(ProbeConfig::default().tcp("localhost:8080").is_err());
+    }
+
+    #[test]
+    fn test_config_unknown_field() {
+        assert!(toml::from_str::<Config>("foo = 1").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Cron expressions, as used by schedules.
+//!
+//! Expressions have the standard five fields: minute, hour, day of month, month and day of week.
+//! Each field is `*`, a value, a range (`1-5`) or a list of those (`1,3,10-12`),
+//! optionally with a step (`*/15`, `0-30/10`). Days of week are 0-7, where both 0 and 7 are Sunday.
+//! As in most cron implementations, if both the day of month and the day of week are restricted,
+//! a day matching either of them matches.
+//! The `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` shorthands are also accepted.
+//!
+//! Times are in UTC.
+
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tonic::Status;
+
+/// How far ahead the next matching time is searched for, in days.
+/// Covers the longest gap between leap days (8 years, e.g. 2096 to 2104), with margin.
+const MAX_DAYS: i64 = 366 * 10;
+
+/// The error produced when parsing an invalid cron expression.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid cron expression '{expression}': {reason}")]
+pub struct ParseError {
+    expression: String,
+    reason: String,
+}
+
+impl From<ParseError> for Status {
+    fn from(e: ParseError) -> Self {
+        Status::invalid_argument(format!("{}", e))
+    }
+}
+
+/// A parsed cron expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cron {
+    expression: String,
+    /// Bit sets of the matching values of each field.
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Whether the day fields were given as `*`, and so do not restrict the day.
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+/// Parses a single field into a bit set of the values it matches.
+fn parse_field(field: &str, range: RangeInclusive<u32>) -> Result<u64, String> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (base, step) = match part.split_once('/') {
+            Some((base, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("step must be positive".into());
+                }
+                (base, Some(step))
+            }
+            None => (part, None),
+        };
+        let value = |s: &str| -> Result<u32, String> {
+            match s.parse() {
+                Ok(v) if range.contains(&v) => Ok(v),
+                _ => Err(format!(
+                    "'{}' is not a number between {} and {}",
+                    s,
+                    range.start(),
+                    range.end()
+                )),
+            }
+        };
+        let (start, end) = match base.split_once('-') {
+            _ if base == "*" => (*range.start(), *range.end()),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // A single value with a step, e.g. `5/10`, continues until the end of the range.
+            None if step.is_some() => (value(base)?, *range.end()),
+            None => {
+                let v = value(base)?;
+                (v, v)
+            }
+        };
+        if start > end {
+            return Err(format!("range '{}' is empty", base));
+        }
+        for v in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl Cron {
+    fn matches_day(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4).rem_euclid(7);
+        let dom = self.days_of_month & (1 << day) != 0;
+        let dow = self.days_of_week & (1 << weekday) != 0;
+        let day_matches = match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        };
+        self.months & (1 << month) != 0 && day_matches
+    }
+
+    /// Returns the first time matching the expression that is strictly after `after`,
+    /// or `None` if there is no such time in the foreseeable future (e.g. `0 0 30 2 *`).
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let next_minute = secs / 60 + 1;
+        let first_day = next_minute / 1440;
+        for days in first_day..first_day + MAX_DAYS {
+            if !self.matches_day(days) {
+                continue;
+            }
+            let start = match days == first_day {
+                true => next_minute % 1440,
+                false => 0,
+            };
+            let minute = (start..1440)
+                .find(|m| self.hours & (1 << (m / 60)) != 0 && self.minutes & (1 << (m % 60)) != 0);
+            if let Some(m) = minute {
+                let secs = (days * 1440 + m) * 60;
+                return Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for Cron {
+    type Err = ParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| ParseError {
+            expression: expression.into(),
+            reason,
+        };
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            e => e,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let (minute, hour, dom, month, dow) = match fields[..] {
+            [minute, hour, dom, month, dow] => (minute, hour, dom, month, dow),
+            _ => return Err(error(format!("expected 5 fields, found {}", fields.len()))),
+        };
+        let mut days_of_week = parse_field(dow, 0..=7).map_err(error)?;
+        // Sunday is both 0 and 7
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            expression: expression.into(),
+            minutes: parse_field(minute, 0..=59).map_err(error)?,
+            hours: parse_field(hour, 0..=23).map_err(error)?,
+            days_of_month: parse_field(dom, 1..=31).map_err(error)?,
+            months: parse_field(month, 1..=12).map_err(error)?,
+            days_of_week,
+            any_day_of_month: dom.starts_with('*'),
+            any_day_of_week: dow.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(s: &str) -> SystemTime {
+        humantime::parse_rfc3339(s).unwrap()
+    }
+
+    fn next(expression: &str, after: &str) -> Option<String> {
+        let cron: Cron = expression.parse().unwrap();
+        cron.next_after(time(after))
+            .map(|t| humantime::format_rfc3339_seconds(t).to_string())
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let now = "2021-03-15T10:20:30Z";
+        assert_eq!(next("* * * * *", now).unwrap(), "2021-03-15T10:21:00Z");
+        assert_eq!(next("*/15 * * * *", now).unwrap(), "2021-03-15T10:30:00Z");
+        assert_eq!(next("0 3 * * *", now).unwrap(), "2021-03-16T03:00:00Z");
+        assert_eq!(next("@monthly", now).unwrap(), "2021-04-01T00:00:00Z");
+        // 2021-03-15 is a Monday
+        assert_eq!(next("0 0 * * 7", now).unwrap(), "2021-03-21T00:00:00Z");
+        assert_eq!(next("0 0 * * 1-5", now).unwrap(), "2021-03-16T00:00:00Z");
+        // Either the day of month or the day of week
+        assert_eq!(next("0 0 20 * 3", now).unwrap(), "2021-03-17T00:00:00Z");
+        assert_eq!(next("30 12 29 2 *", now).unwrap(), "2024-02-29T12:30:00Z");
+        // Strictly after the given time
+        assert_eq!(
+            next("20 10 15 3 *", "2021-03-15T10:20:00Z").unwrap(),
+            "2022-03-15T10:20:00Z"
+        );
+        assert_eq!(next("0 0 30 2 *", now), None);
+    }
+
+    #[test]
+    fn test_cron_parse_errors() {
+        for expression in &[
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "@often",
+        ] {
+            assert!(expression.parse::<Cron>().is_err(), "{}", expression);
+        }
+    }
+}
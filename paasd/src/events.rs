@@ -0,0 +1,52 @@
+//! Lifecycle events of processes, streamed by `WatchEvents`.
+//!
+//! `worker` reports the events of every process to a sink,
+//! which tags them with the ID and owner of the process
+//! and publishes them on a broadcast channel that every `WatchEvents` stream subscribes to.
+//! A stream that falls behind misses events rather than holding up the others,
+//! and is told how many it missed.
+
+use std::{sync::Arc, time::SystemTime};
+
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use worker::{EventSink, ProcessEvent};
+
+use crate::store::Owner;
+
+/// Number of events kept for streams that fall behind.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub struct Event {
+    pub id: Uuid,
+    pub owner: Owner,
+    pub time: SystemTime,
+    pub event: ProcessEvent,
+}
+
+pub struct EventBus(broadcast::Sender<Arc<Event>>);
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+
+    /// Returns a sink that publishes the events of the process with the given ID and owner.
+    pub fn sink(&self, id: Uuid, owner: Owner) -> EventSink {
+        let tx = self.0.clone();
+        EventSink::new(move |event| {
+            // Ignore error: there may be no subscribers.
+            let _ = tx.send(Arc::new(Event {
+                id,
+                owner: owner.clone(),
+                time: SystemTime::now(),
+                event,
+            }));
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.0.subscribe()
+    }
+}
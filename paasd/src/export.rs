@@ -0,0 +1,162 @@
+//! Export of the logs of processes as JSON lines, streamed by `ExportLogs`.
+//!
+//! Every line of the logs becomes a record, which describes where the line comes from,
+//! so that records of many processes can be ingested into the same log pipeline.
+//! Processes that log JSON objects have them embedded in the record,
+//! so that their fields can be queried rather than being encoded in a string.
+
+use std::{borrow::Cow, time::SystemTime};
+
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+use worker::{LogBatch, Output};
+
+use crate::store::Owner;
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    uuid: &'a str,
+    owner: &'a str,
+    /// The number of the line, starting at 1 and counting the lines of every run.
+    seq: usize,
+    /// `stdout` or `stderr`, unknown for logs read back from files.
+    stream: Option<&'static str>,
+    /// When the line was captured, unknown for logs read back from files.
+    timestamp: Option<String>,
+    line: Line<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Line<'a> {
+    Json(Value),
+    Text(Cow<'a, str>),
+}
+
+impl<'a> Line<'a> {
+    /// Lines that are JSON objects are embedded as they are, other lines as strings.
+    fn new(line: &'a [u8]) -> Self {
+        if line.first() == Some(&b'{') {
+            if let Ok(value @ Value::Object(_)) = serde_json::from_slice(line) {
+                return Line::Json(value);
+            }
+        }
+        Line::Text(String::from_utf8_lossy(line))
+    }
+}
+
+/// Encodes the logs of a process as records.
+pub struct Exporter {
+    uuid: String,
+    owner: String,
+}
+
+impl Exporter {
+    pub fn new(id: Uuid, owner: &Owner) -> Self {
+        Self {
+            uuid: id.to_hyphenated().to_string(),
+            owner: owner.user.0.clone(),
+        }
+    }
+
+    /// Returns the records of the lines of a batch with line numbers, each terminated by a newline.
+    pub fn records(&self, batch: &LogBatch) -> Vec<u8> {
+        let mut records = vec![];
+        for (i, (line, &seq)) in batch.lines.iter().zip(&batch.line_numbers).enumerate() {
+            let metadata = batch.metadata.get(i);
+            let record = Record {
+                uuid: &self.uuid,
+                owner: &self.owner,
+                seq,
+                stream: metadata.map(|m| match m.output {
+                    Output::Stdout => "stdout",
+                    Output::Stderr => "stderr",
+                }),
+                timestamp: metadata.map(|m| timestamp(m.time)),
+                line: Line::new(line),
+            };
+            serde_json::to_writer(&mut records, &record).expect("records should serialize");
+            records.push(b'\n');
+        }
+        records
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_micros(time).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use worker::LineMetadata;
+
+    use super::*;
+    use crate::user::UserId;
+
+    #[test]
+    fn test_records() {
+        let owner = Owner::new(UserId("alice".into()), None);
+        let exporter = Exporter::new(Uuid::nil(), &owner);
+        let time = UNIX_EPOCH + Duration::from_micros(1_500_000);
+        let batch = LogBatch {
+            lines: vec![
+                "plain \"text\"".into(),
+                r#"{"level": "info", "n": 1}"#.into(),
+                "{not json".into(),
+            ],
+            line_numbers: vec![1, 2, 3],
+            metadata: vec![
+                LineMetadata {
+                    time,
+                    output: Output::Stdout,
+                },
+                LineMetadata {
+                    time,
+                    output: Output::Stderr,
+                },
+                LineMetadata {
+                    time,
+                    output: Output::Stdout,
+                },
+            ],
+            ..Default::default()
+        };
+        let records = String::from_utf8(exporter.records(&batch)).unwrap();
+        let records: Vec<Value> = records
+            .lines()
+            .map(|r| serde_json::from_str(r).unwrap())
+            .collect();
+        let record = |seq, stream, line| {
+            serde_json::json!({
+                "uuid": "00000000-0000-0000-0000-000000000000",
+                "owner": "alice",
+                "seq": seq,
+                "stream": stream,
+                "timestamp": "1970-01-01T00:00:01.500000Z",
+                "line": line,
+            })
+        };
+        assert_eq!(
+            records,
+            vec![
+                record(1, "stdout", "plain \"text\"".into()),
+                record(2, "stderr", serde_json::json!({"level": "info", "n": 1})),
+                record(3, "stdout", "{not json".into()),
+            ]
+        );
+
+        // Logs read back from files have no metadata
+        let batch = LogBatch {
+            lines: vec!["a".into()],
+            line_numbers: vec![7],
+            ..Default::default()
+        };
+        let records: Value = serde_json::from_slice(&exporter.records(&batch)).unwrap();
+        assert_eq!(records["stream"], Value::Null);
+        assert_eq!(records["timestamp"], Value::Null);
+        assert_eq!(records["seq"], 7);
+    }
+}
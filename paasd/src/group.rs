@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{policy::Permission, user::Caller};
+
+/// Settings of group ownership of processes.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupConfig {
+    /// Whether the Organizational Units of a caller's certificate
+    /// are treated as groups the caller belongs to.
+    pub organizational_units: bool,
+    /// Path to a TOML file mapping group names to lists of their members.
+    pub membership_file: Option<PathBuf>,
+    /// Permissions that group members have on processes owned by the group.
+    pub permissions: Vec<Permission>,
+}
+
+impl Default for GroupConfig {
+    fn default() -> Self {
+        use Permission::*;
+        Self {
+            organizational_units: false,
+            membership_file: None,
+            permissions: vec![List, GetLogs, GetStatus, Stop],
+        }
+    }
+}
+
+/// Resolves group membership of callers.
+#[derive(Clone, Debug, Default)]
+pub struct Groups {
+    organizational_units: bool,
+    members: HashMap<String, HashSet<String>>,
+    permissions: Vec<Permission>,
+}
+
+impl Groups {
+    /// Constructs the group membership from the configuration,
+    /// reading the membership file, if any.
+    pub fn from_config(config: &GroupConfig) -> Result<Self> {
+        let members = match &config.membership_file {
+            Some(path) => {
+                let contents = fs::read_to_string(path).with_context(|| {
+                    format!("could not read group membership file {}", path.display())
+                })?;
+                toml::from_str(&contents).with_context(|| {
+                    format!("could not parse group membership file {}", path.display())
+                })?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            organizational_units: config.organizational_units,
+            members,
+            permissions: config.permissions.clone(),
+        })
+    }
+
+    /// Whether the caller is a member of the given group.
+    pub fn is_member(&self, caller: &Caller, group: &str) -> bool {
+        let by_ou =
+            self.organizational_units && caller.organizational_units.iter().any(|ou| ou == group);
+        let by_file = self
+            .members
+            .get(group)
+            .is_some_and(|m| m.contains(&caller.uid.0));
+        by_ou || by_file
+    }
+
+    /// Whether group members have the given permission on processes owned by the group.
+    pub fn grants(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::user::UserId;
+
+    use super::*;
+
+    fn caller(uid: &str, ous: &[&str]) -> Caller {
+        Caller {
+            uid: UserId(uid.into()),
+            organizational_units: ous.iter().map(|&ou| ou.into()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_groups_organizational_units() {
+        let config = GroupConfig {
+            organizational_units: true,
+            ..Default::default()
+        };
+        let groups = Groups::from_config(&config).unwrap();
+        assert!(groups.is_member(&caller("alice", &["ops"]), "ops"));
+        assert!(!groups.is_member(&caller("alice", &["ops"]), "dev"));
+
+        let groups = Groups::from_config(&GroupConfig::default()).unwrap();
+        assert!(!groups.is_member(&caller("alice", &["ops"]), "ops"));
+    }
+
+    #[test]
+    fn test_groups_membership_file() {
+        let path = std::env::temp_dir().join(format!("paasd-groups-{}.toml", std::process::id()));
+        fs::write(&path, r#"ops = ["alice", "bob"]"#).unwrap();
+        let config = GroupConfig {
+            membership_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let groups = Groups::from_config(&config).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(groups.is_member(&caller("alice", &[]), "ops"));
+        assert!(groups.is_member(&caller("bob", &[]), "ops"));
+        assert!(!groups.is_member(&caller("eve", &[]), "ops"));
+        assert!(!groups.is_member(&caller("alice", &[]), "dev"));
+    }
+}
@@ -0,0 +1,342 @@
+//! Completion hooks: notifications sent once a process has finished,
+//! so that clients need not hold a stream open to find out.
+//!
+//! The hooks of a job are delivered by a task that waits for the job to finish.
+//! Failed attempts are retried with exponential backoff,
+//! and the outcome of every hook is kept as its delivery log, reported by `GetStatus`.
+
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future;
+use hyper::{header, Body, Client, Request, Uri};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::job::{Job, JobState};
+
+/// Settings of completion hooks.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    /// Maximum number of attempts to deliver a notification.
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds, doubled after every retry.
+    pub initial_backoff_millis: u64,
+    /// Attempts that take longer than this fail, in milliseconds.
+    pub timeout_millis: u64,
+    /// Hosts that webhooks may be sent to. Webhooks are not allowed unless hosts are listed,
+    /// as they are sent from the host of `paasd`.
+    pub webhook_hosts: Vec<String>,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_millis: 1000,
+            timeout_millis: 10_000,
+            webhook_hosts: vec![],
+        }
+    }
+}
+
+impl HookConfig {
+    /// Validates a webhook URL.
+    #[allow(clippy::result_large_err)]
+    pub fn webhook(&self, url: &str) -> Result<Action, Status> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|e| Status::invalid_argument(format!("Invalid webhook URL: {}", e)))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(Status::invalid_argument(
+                "Only http webhook URLs are supported",
+            ));
+        }
+        let host = uri.host().unwrap_or_default();
+        if !self.webhook_hosts.iter().any(|h| h == host) {
+            return Err(Status::permission_denied(format!(
+                "Webhooks to '{}' are not allowed",
+                host
+            )));
+        }
+        Ok(Action::Webhook(uri))
+    }
+}
+
+/// What a hook does with the notification.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Posts it to the URL.
+    Webhook(Uri),
+    /// Executes the command with the notification on stdin.
+    Command(Vec<String>),
+}
+
+impl Action {
+    /// The URL or the command line, for display.
+    pub fn target(&self) -> String {
+        match self {
+            Action::Webhook(uri) => uri.to_string(),
+            Action::Command(argv) => argv.join(" "),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeliveryState {
+    /// The job has not finished yet.
+    Waiting,
+    /// The notification is being sent, or will be retried.
+    Delivering,
+    Delivered,
+    /// Every attempt failed.
+    Undelivered,
+}
+
+/// The delivery log of a hook.
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    pub state: DeliveryState,
+    pub attempts: u32,
+    /// Why the last attempt failed, if it did.
+    pub last_error: Option<String>,
+}
+
+pub struct Hook {
+    pub action: Action,
+    delivery: Mutex<Delivery>,
+}
+
+/// The notification sent by hooks.
+#[derive(Debug, Serialize)]
+pub struct Completion {
+    pub id: String,
+    pub owner: String,
+    /// `exited`, `cancelled` or `failed`.
+    pub state: &'static str,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// Why the process was cancelled or could not be started.
+    pub error: Option<String>,
+    /// Time from the start of the process until it exited, including restarts.
+    pub duration_millis: Option<u64>,
+}
+
+impl Completion {
+    /// Waits until the job has finished, and describes how it did.
+    pub async fn wait(id: Uuid, owner: String, job: &Job) -> Self {
+        let mut completion = Self {
+            id: id.to_hyphenated().to_string(),
+            owner,
+            state: "exited",
+            exit_code: None,
+            signal: None,
+            error: None,
+            duration_millis: None,
+        };
+        match job.started().await {
+            Some(p) => {
+                let status = p.wait().await;
+                completion.exit_code = status.code();
+                completion.signal = status.signal();
+                completion.duration_millis = Some(p.spawned_at().elapsed().as_millis() as u64);
+            }
+            None => {
+                let (state, error) = match job.state() {
+                    JobState::Cancelled(reason) => ("cancelled", reason),
+                    JobState::Failed(e) => ("failed", e),
+                    _ => unreachable!("a job that was not started has been cancelled or failed"),
+                };
+                completion.state = state;
+                completion.error = Some(error);
+            }
+        }
+        completion
+    }
+}
+
+impl Hook {
+    pub fn new(action: Action) -> Self {
+        Self {
+            action,
+            delivery: Mutex::new(Delivery {
+                state: DeliveryState::Waiting,
+                attempts: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    pub fn delivery(&self) -> Delivery {
+        self.delivery.lock().unwrap().clone()
+    }
+
+    async fn attempt(&self, payload: &[u8], timeout: Duration) -> Result<(), String> {
+        match &self.action {
+            Action::Webhook(uri) => {
+                let req = Request::post(uri.clone())
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(payload.to_vec()))
+                    .map_err(|e| format!("{}", e))?;
+                let resp = tokio::time::timeout(timeout, Client::new().request(req))
+                    .await
+                    .map_err(|_| "timed out".to_owned())?
+                    .map_err(|e| format!("{}", e))?;
+                match resp.status().is_success() {
+                    true => Ok(()),
+                    false => Err(format!("HTTP status {}", resp.status())),
+                }
+            }
+            Action::Command(argv) => {
+                let mut child = Command::new(&argv[0])
+                    .args(&argv[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| format!("{}", e))?;
+                let mut stdin = child.stdin.take().expect("stdin should be piped");
+                let run = async {
+                    // Ignore error: the command need not read the notification.
+                    let _ = stdin.write_all(payload).await;
+                    drop(stdin);
+                    child.wait().await
+                };
+                let status = tokio::time::timeout(timeout, run)
+                    .await
+                    .map_err(|_| "timed out".to_owned())?
+                    .map_err(|e| format!("{}", e))?;
+                match status.success() {
+                    true => Ok(()),
+                    false => Err(format!("command failed: {}", status)),
+                }
+            }
+        }
+    }
+
+    /// Delivers the notification, retrying failed attempts as configured.
+    async fn deliver(&self, payload: &[u8], config: &HookConfig) {
+        let max_attempts = config.max_attempts.max(1);
+        let timeout = Duration::from_millis(config.timeout_millis);
+        let mut backoff = Duration::from_millis(config.initial_backoff_millis);
+        for attempt in 1..=max_attempts {
+            {
+                let mut delivery = self.delivery.lock().unwrap();
+                delivery.state = DeliveryState::Delivering;
+                delivery.attempts = attempt;
+            }
+            match self.attempt(payload, timeout).await {
+                Ok(()) => {
+                    let mut delivery = self.delivery.lock().unwrap();
+                    delivery.state = DeliveryState::Delivered;
+                    delivery.last_error = None;
+                    return;
+                }
+                Err(e) => {
+                    warn!("completion hook '{}' failed: {}", self.action.target(), e);
+                    self.delivery.lock().unwrap().last_error = Some(e);
+                }
+            }
+            if attempt < max_attempts {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        self.delivery.lock().unwrap().state = DeliveryState::Undelivered;
+    }
+}
+
+/// Waits for the job to finish, then delivers the notifications of its hooks.
+pub async fn notify(id: Uuid, owner: String, job: Arc<Job>, config: Arc<HookConfig>) {
+    let completion = Completion::wait(id, owner, &job).await;
+    let payload = serde_json::to_vec(&completion).expect("notification should serialize");
+    future::join_all(job.hooks.iter().map(|h| h.deliver(&payload, &config))).await;
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    fn config() -> HookConfig {
+        HookConfig {
+            max_attempts: 3,
+            initial_backoff_millis: 10,
+            webhook_hosts: vec!["127.0.0.1".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hook_config_webhook() {
+        let config = HookConfig {
+            webhook_hosts: vec!["localhost".into()],
+            ..Default::default()
+        };
+        assert!(config.webhook("http://localhost:8080/done").is_ok());
+        assert!(HookConfig::default()
+            .webhook("http://localhost:8080/done")
+            .is_err());
+        assert!(config.webhook("https://localhost/done").is_err());
+        assert!(config.webhook("not a url").is_err());
+        assert!(config.webhook("http://example.com/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hook_webhook_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/done", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut bodies = vec![];
+            for status in &["500 Internal Server Error", "200 OK"] {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let n = conn.read(&mut request).await.unwrap();
+                bodies.push(String::from_utf8_lossy(&request[..n]).into_owned());
+                let resp = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                conn.write_all(resp.as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+
+        let hook = Hook::new(config().webhook(&url).unwrap());
+        hook.deliver(b"{}", &config()).await;
+        let delivery = hook.delivery();
+        assert_eq!(delivery.state, DeliveryState::Delivered);
+        assert_eq!(delivery.attempts, 2);
+        let requests = server.await.unwrap();
+        assert!(requests[1].starts_with("POST /done"));
+        assert!(requests[1].ends_with("{}"));
+    }
+
+    #[tokio::test]
+    async fn test_hook_command() {
+        let path = std::env::temp_dir().join(format!("paasd-hook-{}", std::process::id()));
+        let hook = Hook::new(Action::Command(vec![
+            "bash".into(),
+            "-c".into(),
+            format!("cat > {}", path.display()),
+        ]));
+        hook.deliver(b"{\"id\":1}", &config()).await;
+        assert_eq!(hook.delivery().state, DeliveryState::Delivered);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"id\":1}");
+        std::fs::remove_file(path).unwrap();
+
+        let hook = Hook::new(Action::Command(vec!["false".into()]));
+        hook.deliver(b"{}", &config()).await;
+        let delivery = hook.delivery();
+        assert_eq!(delivery.state, DeliveryState::Undelivered);
+        assert_eq!(delivery.attempts, 3);
+        assert!(delivery.last_error.is_some());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    io,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use futures::future;
+use tokio::sync::watch;
+use uuid::Uuid;
+use worker::{
+    EventSink, LogBudget, LogRateLimit, Priority, Probes, Process, RestartPolicy, SpawnOptions,
+};
+
+use crate::{
+    hook::{self, Hook},
+    quota::Reservation,
+    scheduler::PriorityClass,
+};
+
+/// The lifecycle state of a job.
+#[derive(Clone)]
+pub enum JobState {
+    /// Waiting for the jobs it depends on to finish.
+    Pending,
+    /// Waiting for capacity to start the process.
+    Queued,
+    /// The process has been started. It may have finished since.
+    Started(Process),
+    /// The process was never started, for the given reason:
+    /// the job was stopped, or its dependencies failed.
+    Cancelled(String),
+    /// The process could not be started.
+    Failed(String),
+}
+
+/// What to run and how, as requested by a user: everything needed to create a job.
+#[derive(Clone, Debug)]
+pub struct JobSpec {
+    pub argv: Vec<String>,
+    /// The group the process is shared with.
+    pub group: Option<String>,
+    pub reservation: Reservation,
+    pub class: PriorityClass,
+    pub restart: RestartPolicy,
+    pub probes: Probes,
+    pub log_rate_limit: LogRateLimit,
+    /// Notifications to send once the job has finished.
+    pub hooks: Vec<hook::Action>,
+}
+
+/// When a dependency counts as satisfied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    /// The process exited successfully.
+    Success,
+    /// The process exited, however it did.
+    AnyExit,
+}
+
+impl Condition {
+    /// Whether a job that finished with the given status satisfies the condition.
+    /// `None` means that the process of the job was never started.
+    fn satisfied_by(self, status: Option<ExitStatus>) -> bool {
+        match (self, status) {
+            (_, None) => false,
+            (Condition::Success, Some(status)) => status.success(),
+            (Condition::AnyExit, Some(_)) => true,
+        }
+    }
+
+    /// Whether the job satisfies the condition,
+    /// or `None` if that is not known yet because the job has not finished.
+    pub async fn check(self, job: &Job) -> Option<bool> {
+        match job.state() {
+            JobState::Pending | JobState::Queued => None,
+            JobState::Started(p) => Some(self.satisfied_by(Some(p.status().await?))),
+            JobState::Cancelled(_) | JobState::Failed(_) => Some(false),
+        }
+    }
+}
+
+/// The jobs that must finish before a job is started, and how.
+#[derive(Clone)]
+pub struct Dependencies {
+    pub jobs: Vec<(Uuid, Arc<Job>)>,
+    pub condition: Condition,
+}
+
+impl Default for Dependencies {
+    fn default() -> Self {
+        Self {
+            jobs: vec![],
+            condition: Condition::Success,
+        }
+    }
+}
+
+impl Dependencies {
+    /// Waits until every job has finished satisfying the condition.
+    /// Returns early with the reason if one of them fails to.
+    pub async fn wait(&self) -> Result<(), String> {
+        let condition = self.condition;
+        let waits = self.jobs.iter().map(|(id, job)| async move {
+            match condition.satisfied_by(job.finished().await) {
+                true => Ok(()),
+                false => Err(format!(
+                    "Dependency {} did not {}",
+                    id.to_hyphenated(),
+                    match condition {
+                        Condition::Success => "succeed",
+                        Condition::AnyExit => "run",
+                    }
+                )),
+            }
+        });
+        future::try_join_all(waits).await.map(|_| ())
+    }
+}
+
+/// A process managed by `paasd`,
+/// together with the data about it that `worker` does not know about.
+pub struct Job {
+    pub argv: Vec<String>,
+    pub reservation: Reservation,
+    pub class: PriorityClass,
+    pub restart: RestartPolicy,
+    pub probes: Probes,
+    pub log_rate_limit: LogRateLimit,
+    pub hooks: Vec<Hook>,
+    /// Receives the lifecycle events of the process.
+    events: Option<EventSink>,
+    /// The budget the logs of the process are held in memory within.
+    log_budget: Option<LogBudget>,
+    /// The schedule that created the job, if any.
+    pub schedule: Option<Uuid>,
+    pub dependencies: Dependencies,
+    /// The jobs that depend on this one.
+    dependents: Mutex<Vec<Uuid>>,
+    state_tx: watch::Sender<JobState>,
+    state_rx: watch::Receiver<JobState>,
+}
+
+impl Job {
+    /// Creates a job in the `Pending` state if it has dependencies,
+    /// or the `Queued` state otherwise.
+    /// The `argv` of the spec must not be empty.
+    pub fn new(spec: JobSpec, schedule: Option<Uuid>, dependencies: Dependencies) -> Self {
+        let state = match dependencies.jobs.is_empty() {
+            true => JobState::Queued,
+            false => JobState::Pending,
+        };
+        let (state_tx, state_rx) = watch::channel(state);
+        Self {
+            argv: spec.argv,
+            reservation: spec.reservation,
+            class: spec.class,
+            restart: spec.restart,
+            probes: spec.probes,
+            log_rate_limit: spec.log_rate_limit,
+            hooks: spec.hooks.into_iter().map(Hook::new).collect(),
+            events: None,
+            log_budget: None,
+            schedule,
+            dependencies,
+            dependents: Default::default(),
+            state_tx,
+            state_rx,
+        }
+    }
+
+    /// Sets the sink that receives the lifecycle events of the process once it is started.
+    pub fn with_events(self, events: EventSink) -> Self {
+        Self {
+            events: Some(events),
+            ..self
+        }
+    }
+
+    /// Sets the budget the logs of the process are held in memory within once it is started.
+    pub fn with_log_budget(self, log_budget: LogBudget) -> Self {
+        Self {
+            log_budget: Some(log_budget),
+            ..self
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state_rx.borrow().clone()
+    }
+
+    fn set_state(&self, state: JobState) {
+        // Can not fail, the job holds a receiver.
+        let _ = self.state_tx.send(state);
+    }
+
+    /// Returns the process, if it has been started.
+    pub fn process(&self) -> Option<Process> {
+        match self.state() {
+            JobState::Started(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Records that the job with the given ID depends on this one.
+    pub fn add_dependent(&self, id: Uuid) {
+        self.dependents.lock().unwrap().push(id);
+    }
+
+    /// The IDs of the jobs that depend on this one.
+    pub fn dependents(&self) -> Vec<Uuid> {
+        self.dependents.lock().unwrap().clone()
+    }
+
+    /// Waits until the job leaves the `Pending` and `Queued` states.
+    /// Returns the process, or `None` if it was not started.
+    pub async fn started(&self) -> Option<Process> {
+        let mut state_rx = self.state_rx.clone();
+        loop {
+            match &*state_rx.borrow() {
+                JobState::Pending | JobState::Queued => {}
+                JobState::Started(p) => return Some(p.clone()),
+                JobState::Cancelled(_) | JobState::Failed(_) => return None,
+            }
+            if state_rx.changed().await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    /// Waits until the process of the job finishes.
+    /// Returns its exit status, or `None` if it was not started.
+    pub async fn finished(&self) -> Option<ExitStatus> {
+        Some(self.started().await?.wait().await)
+    }
+
+    /// Whether the job is pending, queued or its process is running.
+    pub async fn is_active(&self) -> bool {
+        match self.state() {
+            JobState::Pending | JobState::Queued => true,
+            JobState::Started(p) => p.status().await.is_none(),
+            JobState::Cancelled(_) | JobState::Failed(_) => false,
+        }
+    }
+
+    /// Spawns the process of a queued job with the given priority.
+    /// Only the scheduler may start jobs, to keep its accounting correct.
+    pub(crate) fn start(&self, priority: &Priority) -> io::Result<Process> {
+        let mut args = self.argv.iter().map(AsRef::as_ref);
+        let argv0 = args.next().expect("argv should never be empty");
+        let options = SpawnOptions {
+            priority: priority.clone(),
+            restart: self.restart.clone(),
+            probes: self.probes.clone(),
+            log_rate_limit: self.log_rate_limit,
+            log_budget: self.log_budget.clone(),
+            events: self.events.clone(),
+        };
+        match Process::spawn_with(argv0, args, &options) {
+            Ok(p) => {
+                self.set_state(JobState::Started(p.clone()));
+                Ok(p)
+            }
+            Err(e) => {
+                self.set_state(JobState::Failed(format!("{}", e)));
+                Err(e)
+            }
+        }
+    }
+
+    /// Moves a pending job to the `Queued` state, once its dependencies are satisfied.
+    /// Only the scheduler may do so, to keep its queue consistent.
+    pub(crate) fn unblock(&self) {
+        self.set_state(JobState::Queued);
+    }
+
+    /// Cancels a pending or queued job.
+    /// Only the scheduler may cancel jobs, to keep its queue consistent.
+    pub(crate) fn cancel(&self, reason: String) {
+        self.set_state(JobState::Cancelled(reason));
+    }
+}
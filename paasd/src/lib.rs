@@ -5,18 +5,50 @@ use rustls::{
     ciphersuite, internal::pemfile, AllowAnyAuthenticatedClient, RootCertStore, ServerConfig,
     SupportedCipherSuite,
 };
-use tonic::transport::server::{Router, Server as TonicServer, ServerTlsConfig, Unimplemented};
+use tonic::{
+    service::interceptor::InterceptedService,
+    transport::server::{Router, Server as TonicServer, ServerTlsConfig, Unimplemented},
+};
 
 use paas_types::process_service_server::ProcessServiceServer;
 
+pub mod audit;
+mod backpressure;
+mod command;
+mod config;
+mod cron;
+mod events;
+mod export;
+mod group;
+mod hook;
+mod job;
+mod logfile;
+mod policy;
+mod quota;
+mod ratelimit;
+mod schedule;
+mod scheduler;
 mod service;
 mod store;
 mod user;
 
+pub use backpressure::{SlowConsumer, StreamConfig};
+pub use config::{Compression, Config, ProbeConfig};
+pub use group::GroupConfig;
+pub use hook::HookConfig;
+pub use logfile::LogConfig;
+pub use policy::{Permission, Role};
+pub use quota::{Limits, QuotaConfig};
+pub use ratelimit::{Rate, RateLimitConfig, RETRY_AFTER};
+pub use scheduler::{ClassConfig, SchedulerConfig};
+pub use user::IdentitySource;
+
+use ratelimit::{Interceptor, RateLimited, RateLimiter};
 use service::ProcessService;
 use store::ProcessStore;
 
-pub type Server = Router<ProcessServiceServer<ProcessService>, Unimplemented>;
+pub type Server = Router<Service, Unimplemented>;
+type Service = RateLimited<InterceptedService<ProcessServiceServer<ProcessService>, Interceptor>>;
 
 static CIPHERSUITES: &[&SupportedCipherSuite; 5] = &[
     &ciphersuite::TLS13_AES_256_GCM_SHA384,
@@ -52,15 +84,26 @@ fn rustls_config() -> Result<ServerConfig> {
     Ok(config)
 }
 
-fn make_service() -> ProcessServiceServer<ProcessService> {
-    ProcessServiceServer::new(ProcessService::new(Arc::new(ProcessStore::new())))
+fn make_service(config: &Config) -> Result<Service> {
+    let service = ProcessService::new(Arc::new(ProcessStore::new()), config)?;
+    let mut server = ProcessServiceServer::new(service).accept_gzip();
+    if config.compression == Compression::Gzip {
+        server = server.send_gzip();
+    }
+    let limiter = RateLimiter::new(config)?;
+    Ok(RateLimited(InterceptedService::new(
+        server,
+        Interceptor(limiter),
+    )))
 }
 
-pub fn make_server() -> Result<Server> {
+pub fn make_server(config: &Config) -> Result<Server> {
     let mut tls = ServerTlsConfig::new();
     tls.rustls_server_config(rustls_config()?);
 
     Ok(TonicServer::builder()
         .tls_config(tls)?
-        .add_service(make_service()))
+        .http2_keepalive_interval(config.streams.keepalive_interval())
+        .http2_keepalive_timeout(Some(config.streams.keepalive_timeout()))
+        .add_service(make_service(config)?))
 }
@@ -0,0 +1,311 @@
+//! Persistence of the logs of processes to files,
+//! so that they outlive the daemon and can be inspected with standard tools.
+//!
+//! While a process runs, a task follows its logs and appends the entries to `<dir>/<id>.log`,
+//! one per line: the time, then `stdout` or `stderr` followed by the line,
+//! `restart` followed by the number of the run,
+//! or `suppressed` followed by the number and size of the lines suppressed by the rate limit.
+//! Once the process has finished, the file is compressed with zstd into `<dir>/<id>.log.zst`,
+//! which replaces it.
+//! After a while, the logs are evicted from memory, and `GetLogs` then reads them from the file.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_compression::tokio::{bufread::ZstdDecoder, write::ZstdEncoder};
+use futures::{pin_mut, Stream, StreamExt};
+use log::warn;
+use serde::Deserialize;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter},
+};
+use uuid::Uuid;
+use worker::{BatchLimits, EntryMetadata, LogEntry, LogQuery, Output, Process, Suppressed};
+
+use crate::job::Job;
+
+/// Extension of compressed files, appended to the path of the file.
+const COMPRESSED_EXTENSION: &str = ".zst";
+
+/// Settings of the persistence and rate limits of logs.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Directory to write the logs to. If not given, logs are only kept in memory.
+    pub dir: Option<PathBuf>,
+    /// Whether files are compressed once the process has finished.
+    pub compress: bool,
+    /// Time after a process has finished after which its logs are only kept in the file,
+    /// in seconds.
+    pub evict_after_secs: u64,
+    /// Upper bounds on the log rate limits of processes, per second,
+    /// which also apply to processes that request no limit.
+    pub max_lines_per_sec: Option<u64>,
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            compress: true,
+            evict_after_secs: 300,
+            max_lines_per_sec: None,
+            max_bytes_per_sec: None,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The path of the uncompressed logs of the process.
+    fn path(&self, id: Uuid) -> Option<PathBuf> {
+        let name = format!("{}.log", id.to_hyphenated());
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+}
+
+/// Appends an extension to the path.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(extension);
+    path.into()
+}
+
+/// Formats an entry of the logs as a line of the file, with its line terminator.
+fn format_entry(entry: &LogEntry, time: SystemTime, output: Option<Output>) -> Vec<u8> {
+    let mut formatted = humantime::format_rfc3339_micros(time)
+        .to_string()
+        .into_bytes();
+    match (entry, output) {
+        (LogEntry::Line(line), output) => {
+            formatted.extend_from_slice(match output {
+                Some(Output::Stdout) | None => b" stdout ",
+                Some(Output::Stderr) => b" stderr ",
+            });
+            formatted.extend_from_slice(line);
+        }
+        (LogEntry::Restart(run), _) => {
+            formatted.extend_from_slice(format!(" restart {}", run).as_bytes());
+        }
+        (LogEntry::Suppressed(suppressed), _) => {
+            let fields = format!(" suppressed {} {}", suppressed.lines, suppressed.bytes);
+            formatted.extend_from_slice(fields.as_bytes());
+        }
+    }
+    formatted.push(b'\n');
+    formatted
+}
+
+/// Parses a line of the file, without its line terminator, into an entry of the logs,
+/// along with its metadata.
+fn parse_entry(line: &[u8]) -> io::Result<(LogEntry, Option<EntryMetadata>)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed log file");
+    let mut fields = line.splitn(3, |&b| b == b' ');
+    let mut field = || fields.next().ok_or_else(invalid);
+    let (time, kind, rest) = (field()?, field()?, field()?);
+    let time = std::str::from_utf8(time).map_err(|_| invalid())?;
+    let time = humantime::parse_rfc3339(time).map_err(|_| invalid())?;
+    let metadata = |output| Some(EntryMetadata { time, output });
+    let output = match kind {
+        b"stdout" => Output::Stdout,
+        b"stderr" => Output::Stderr,
+        b"restart" => {
+            let run = std::str::from_utf8(rest).map_err(|_| invalid())?;
+            let run = run.parse().map_err(|_| invalid())?;
+            return Ok((LogEntry::Restart(run), metadata(None)));
+        }
+        b"suppressed" => {
+            let rest = std::str::from_utf8(rest).map_err(|_| invalid())?;
+            let (lines, bytes) = rest.split_once(' ').ok_or_else(invalid)?;
+            let suppressed = Suppressed {
+                lines: lines.parse().map_err(|_| invalid())?,
+                bytes: bytes.parse().map_err(|_| invalid())?,
+            };
+            return Ok((LogEntry::Suppressed(suppressed), metadata(None)));
+        }
+        _ => return Err(invalid()),
+    };
+    let entry = LogEntry::Line(rest.to_vec().into());
+    Ok((entry, metadata(Some(output))))
+}
+
+/// Appends the entries of the logs to the file as they are produced, until the process finishes.
+async fn write(p: &Process, path: &Path) -> io::Result<()> {
+    let query = LogQuery {
+        follow: true,
+        metadata: true,
+        ..Default::default()
+    };
+    // The logs are only evicted once they have been written, unless a previous attempt failed.
+    let batches = p
+        .log_batches(query, BatchLimits::default())
+        .map_err(io::Error::other)?;
+    pin_mut!(batches);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut file = BufWriter::new(File::create(path).await?);
+    let mut run = 0;
+    while let Some(batch) = batches.next().await {
+        // Restarts and suppressed lines are stored with the time they were logged.
+        let marker_time = batch.marker_time.unwrap_or_else(SystemTime::now);
+        // Batches of a new run start with one without lines.
+        while run < batch.run {
+            run += 1;
+            let restart = LogEntry::Restart(run);
+            file.write_all(&format_entry(&restart, marker_time, None))
+                .await?;
+        }
+        for (line, metadata) in batch.lines.into_iter().zip(batch.metadata) {
+            let line = LogEntry::Line(line);
+            file.write_all(&format_entry(&line, metadata.time, Some(metadata.output)))
+                .await?;
+        }
+        if let Some(suppressed) = batch.suppressed {
+            let marker = LogEntry::Suppressed(suppressed);
+            file.write_all(&format_entry(&marker, marker_time, None))
+                .await?;
+        }
+        file.flush().await?;
+    }
+    file.into_inner().sync_all().await
+}
+
+/// Compresses the file into `<path>.zst`, and removes it.
+/// The compressed file only appears once it is complete.
+async fn compress(path: &Path) -> io::Result<()> {
+    let compressed = with_extension(path, COMPRESSED_EXTENSION);
+    let partial = with_extension(&compressed, ".tmp");
+    let mut encoder = ZstdEncoder::new(File::create(&partial).await?);
+    tokio::io::copy(&mut File::open(path).await?, &mut encoder).await?;
+    encoder.shutdown().await?;
+    encoder.into_inner().sync_all().await?;
+    fs::rename(&partial, &compressed).await?;
+    fs::remove_file(path).await
+}
+
+/// Waits for the job to start, writes its logs to a file, compresses it once the job has finished,
+/// and evicts the logs from memory after the configured time.
+/// If the logs can not be written, they are kept in memory.
+pub async fn persist(id: Uuid, job: Arc<Job>, config: Arc<LogConfig>) {
+    let (p, path) = match (job.started().await, config.path(id)) {
+        (Some(p), Some(path)) => (p, path),
+        _ => return,
+    };
+    if let Err(e) = write(&p, &path).await {
+        warn!("could not write logs to {}: {}", path.display(), e);
+        return;
+    }
+    if config.compress {
+        if let Err(e) = compress(&path).await {
+            // The uncompressed file is read instead.
+            warn!("could not compress {}: {}", path.display(), e);
+        }
+    }
+    tokio::time::sleep(Duration::from_secs(config.evict_after_secs)).await;
+    p.evict_logs();
+}
+
+/// Returns a stream of the entries of the stored logs of the process,
+/// decompressing them if they have been compressed.
+pub fn read(
+    id: Uuid,
+    config: &LogConfig,
+) -> impl Stream<Item = io::Result<(LogEntry, Option<EntryMetadata>)>> {
+    let path = config.path(id);
+    async_stream::try_stream! {
+        let path = path.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "logs are not stored"))?;
+        let compressed = with_extension(&path, COMPRESSED_EXTENSION);
+        let reader: Box<dyn AsyncRead + Send + Sync + Unpin> = match File::open(&compressed).await {
+            Ok(file) => Box::new(ZstdDecoder::new(BufReader::new(file))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Box::new(File::open(&path).await?),
+            Err(e) => Err(e)?,
+        };
+        let mut lines = BufReader::new(reader).split(b'\n');
+        while let Some(line) = lines.next_segment().await? {
+            let entry = parse_entry(&line)?;
+            yield entry;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_file_entries() {
+        let time = humantime::parse_rfc3339("2021-03-14T12:00:04.102345Z").unwrap();
+        let line = LogEntry::Line("a  b ".into());
+        let formatted = format_entry(&line, time, Some(Output::Stderr));
+        assert_eq!(formatted, b"2021-03-14T12:00:04.102345Z stderr a  b \n");
+        let metadata = EntryMetadata {
+            time,
+            output: Some(Output::Stderr),
+        };
+        let parsed = parse_entry(&formatted[..formatted.len() - 1]).unwrap();
+        assert_eq!(parsed, (line, Some(metadata)));
+
+        let formatted = format_entry(&LogEntry::Restart(2), time, None);
+        assert_eq!(formatted, b"2021-03-14T12:00:04.102345Z restart 2\n");
+        let parsed = parse_entry(&formatted[..formatted.len() - 1]).unwrap();
+        // Other entries keep the time they were logged at too
+        let marker = Some(EntryMetadata { time, output: None });
+        assert_eq!(parsed, (LogEntry::Restart(2), marker));
+
+        let suppressed = LogEntry::Suppressed(Suppressed {
+            lines: 90,
+            bytes: 191,
+        });
+        let formatted = format_entry(&suppressed, time, None);
+        assert_eq!(
+            formatted,
+            b"2021-03-14T12:00:04.102345Z suppressed 90 191\n"
+        );
+        let parsed = parse_entry(&formatted[..formatted.len() - 1]).unwrap();
+        assert_eq!(parsed, (suppressed, marker));
+
+        let empty = format_entry(&LogEntry::Line("".into()), time, Some(Output::Stdout));
+        let parsed = parse_entry(&empty[..empty.len() - 1]).unwrap();
+        assert_eq!(parsed.0, LogEntry::Line("".into()));
+
+        assert!(parse_entry(b"").is_err());
+        assert!(parse_entry(b"yesterday stdout a").is_err());
+        assert!(parse_entry(b"2021-03-14T12:00:04Z restart a").is_err());
+        assert!(parse_entry(b"2021-03-14T12:00:04Z stdin a").is_err());
+        assert!(parse_entry(b"2021-03-14T12:00:04Z suppressed 90").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_log_file_compress() {
+        let dir = std::env::temp_dir().join(format!("paasd-logfile-{}", std::process::id()));
+        let config = LogConfig {
+            dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let id = Uuid::new_v4();
+        let path = config.path(id).unwrap();
+        fs::create_dir_all(&dir).await.unwrap();
+        let time = SystemTime::now();
+        let mut contents = vec![];
+        for i in 0..1000 {
+            let line = LogEntry::Line(i.to_string().into());
+            contents.extend(format_entry(&line, time, Some(Output::Stdout)));
+        }
+        fs::write(&path, contents).await.unwrap();
+
+        compress(&path).await.unwrap();
+        assert!(!path.exists());
+        let entries: Vec<_> = read(id, &config).collect().await;
+        assert_eq!(entries.len(), 1000);
+        let (entry, metadata) = entries.into_iter().last().unwrap().unwrap();
+        assert_eq!(entry, LogEntry::Line("999".into()));
+        assert_eq!(metadata.unwrap().output, Some(Output::Stdout));
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -1,14 +1,29 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use log::info;
+use structopt::StructOpt;
+
+use paasd::{make_server, Config};
 
-use paasd::make_server;
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(short, long, help = "Path to the TOML configuration file")]
+    config: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     pretty_env_logger::init();
 
+    let opt = Opt::from_args();
+    let config = match opt.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+
     let addr = "127.0.0.1:8443".parse()?;
     info!("starting on {}", addr);
-    make_server()?.serve(addr).await?;
+    make_server(&config)?.serve(addr).await?;
     Ok(())
 }
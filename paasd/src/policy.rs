@@ -0,0 +1,291 @@
+use std::{collections::HashMap, fmt};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::{
+    command::{CommandDenied, CommandPolicy, CommandRule},
+    config::Config,
+    group::Groups,
+    store::{GetError, Owner},
+    user::Caller,
+};
+
+/// An operation on a process, which may be granted to a role.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    List,
+    GetLogs,
+    GetStatus,
+    Stop,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Permission::*;
+        f.write_str(match self {
+            List => "list",
+            GetLogs => "get-logs",
+            GetStatus => "get-status",
+            Stop => "stop",
+        })
+    }
+}
+
+/// A set of permissions on processes of *any* owner,
+/// granted to the listed users and members of the listed organizational units.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Role {
+    pub permissions: Vec<Permission>,
+    pub users: Vec<String>,
+    pub organizational_units: Vec<String>,
+}
+
+impl Role {
+    fn applies_to(&self, caller: &Caller) -> bool {
+        self.users.contains(&caller.uid.0)
+            || caller
+                .organizational_units
+                .iter()
+                .any(|ou| self.organizational_units.contains(ou))
+    }
+}
+
+/// Decides which operations a caller may perform on a process.
+///
+/// The owner of a process is always allowed to perform every operation on it.
+/// Members of the group owning the process are allowed the operations
+/// configured for groups.
+/// Other callers need a role that grants the permission for the operation.
+///
+/// Which commands a caller may execute is decided by the command policy.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    roles: HashMap<String, Role>,
+    groups: Groups,
+    commands: CommandPolicy,
+}
+
+impl Policy {
+    /// Constructs the policy from the configuration,
+    /// reading the group membership and command policy files, if any.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let commands = match &config.command_policy_file {
+            Some(path) => CommandPolicy::from_file(path)?,
+            None => CommandPolicy::default(),
+        };
+        Ok(Self {
+            roles: config.roles.clone(),
+            groups: Groups::from_config(&config.groups)?,
+            commands,
+        })
+    }
+
+    /// Whether the caller has been assigned the role with the given name.
+    fn has_role(&self, caller: &Caller, role: &str) -> bool {
+        self.roles.get(role).is_some_and(|r| r.applies_to(caller))
+    }
+
+    fn command_rule_applies(&self, caller: &Caller, rule: &CommandRule) -> bool {
+        (rule.users.is_empty() && rule.roles.is_empty())
+            || rule.users.contains(&caller.uid.0)
+            || rule.roles.iter().any(|r| self.has_role(caller, r))
+    }
+
+    /// Checks whether the caller may execute the given command.
+    pub fn authorize_exec(
+        &self,
+        caller: &Caller,
+        argv0: &str,
+        args: &[&str],
+    ) -> Result<(), CommandDenied> {
+        self.commands
+            .check(argv0, args, |rule| self.command_rule_applies(caller, rule))
+    }
+
+    /// Whether the caller is a member of the given group,
+    /// and so may create processes owned by it.
+    pub fn is_member(&self, caller: &Caller, group: &str) -> bool {
+        self.groups.is_member(caller, group)
+    }
+
+    fn has_role_permission(&self, caller: &Caller, permission: Permission) -> bool {
+        self.roles
+            .values()
+            .any(|r| r.applies_to(caller) && r.permissions.contains(&permission))
+    }
+
+    /// Checks whether `caller` may perform the operation on a process owned by `owner`.
+    ///
+    /// Processes that the caller can not access at all are reported as not found,
+    /// so that their existence is not revealed.
+    /// If the caller can access the process, but not with this permission,
+    /// `GetError::PermissionDenied` is returned instead.
+    pub fn authorize(
+        &self,
+        caller: &Caller,
+        owner: &Owner,
+        permission: Permission,
+    ) -> Result<(), GetError> {
+        let in_group = owner
+            .group
+            .as_ref()
+            .is_some_and(|g| self.groups.is_member(caller, g));
+        // TODO: does this need constant-time EQ?
+        if caller.uid == owner.user
+            || (in_group && self.groups.grants(permission))
+            || self.has_role_permission(caller, permission)
+        {
+            Ok(())
+        } else if in_group || self.roles.values().any(|r| r.applies_to(caller)) {
+            Err(GetError::PermissionDenied(permission))
+        } else {
+            Err(GetError::NotFound)
+        }
+    }
+
+    /// Whether `caller` may see processes owned by `owner` when listing processes.
+    pub fn can_list(&self, caller: &Caller, owner: &Owner) -> bool {
+        self.authorize(caller, owner, Permission::List).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{group::GroupConfig, user::UserId};
+
+    use super::*;
+
+    fn owner(uid: &str, group: Option<&str>) -> Owner {
+        Owner::new(UserId(uid.into()), group.map(Into::into))
+    }
+
+    fn caller(uid: &str, ous: &[&str]) -> Caller {
+        Caller {
+            uid: UserId(uid.into()),
+            organizational_units: ous.iter().map(|&ou| ou.into()).collect(),
+        }
+    }
+
+    fn policy() -> Policy {
+        let mut roles = HashMap::new();
+        roles.insert(
+            "admin".into(),
+            Role {
+                permissions: vec![
+                    Permission::List,
+                    Permission::GetLogs,
+                    Permission::GetStatus,
+                    Permission::Stop,
+                ],
+                users: vec!["root".into()],
+                organizational_units: vec![],
+            },
+        );
+        roles.insert(
+            "auditor".into(),
+            Role {
+                permissions: vec![Permission::List, Permission::GetLogs, Permission::GetStatus],
+                users: vec![],
+                organizational_units: vec!["audit".into()],
+            },
+        );
+        Policy {
+            roles,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policy_owner() {
+        let alice = caller("alice", &[]);
+        assert!(Policy::default()
+            .authorize(&alice, &owner("alice", None), Permission::Stop)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_policy_not_owner() {
+        let eve = caller("eve", &[]);
+        let alice = owner("alice", None);
+        assert!(matches!(
+            policy().authorize(&eve, &alice, Permission::GetStatus),
+            Err(GetError::NotFound)
+        ));
+        assert!(!policy().can_list(&eve, &alice));
+    }
+
+    #[test]
+    fn test_policy_admin() {
+        let root = caller("root", &[]);
+        let alice = owner("alice", None);
+        assert!(policy().authorize(&root, &alice, Permission::Stop).is_ok());
+        assert!(policy().can_list(&root, &alice));
+    }
+
+    #[test]
+    fn test_policy_auditor() {
+        let auditor = caller("bob", &["audit"]);
+        let alice = owner("alice", None);
+        let policy = policy();
+        assert!(policy
+            .authorize(&auditor, &alice, Permission::GetLogs)
+            .is_ok());
+        assert!(policy.can_list(&auditor, &alice));
+        assert!(matches!(
+            policy.authorize(&auditor, &alice, Permission::Stop),
+            Err(GetError::PermissionDenied(Permission::Stop))
+        ));
+    }
+
+    #[test]
+    fn test_policy_group() {
+        let config = GroupConfig {
+            organizational_units: true,
+            permissions: vec![Permission::GetStatus],
+            ..Default::default()
+        };
+        let policy = Policy {
+            groups: Groups::from_config(&config).unwrap(),
+            ..Default::default()
+        };
+        let bob = caller("bob", &["ops"]);
+        let shared = owner("alice", Some("ops"));
+        assert!(policy.is_member(&bob, "ops"));
+        assert!(policy
+            .authorize(&bob, &shared, Permission::GetStatus)
+            .is_ok());
+        assert!(matches!(
+            policy.authorize(&bob, &shared, Permission::Stop),
+            Err(GetError::PermissionDenied(Permission::Stop))
+        ));
+        assert!(matches!(
+            policy.authorize(&bob, &owner("alice", Some("dev")), Permission::GetStatus),
+            Err(GetError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_policy_exec() {
+        let mut policy = policy();
+        policy.commands = toml::from_str(
+            r#"
+            default = "deny"
+
+            [[rules]]
+            action = "allow"
+            executable = "/bin/*"
+            roles = ["admin"]
+            "#,
+        )
+        .unwrap();
+        assert!(policy
+            .authorize_exec(&caller("root", &[]), "/bin/sh", &[])
+            .is_ok());
+        assert!(policy
+            .authorize_exec(&caller("bob", &["audit"]), "/bin/sh", &[])
+            .is_err());
+    }
+}
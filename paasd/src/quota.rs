@@ -0,0 +1,236 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::Deserialize;
+use tonic::Status;
+use worker::LogBudget;
+
+use crate::user::UserId;
+
+/// Resources reserved by a process for the duration of its run.
+/// Reservations are only used for accounting, they are not enforced on the process.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Reservation {
+    pub memory_bytes: u64,
+    pub cpu_millis: u64,
+}
+
+/// Limits on the resources used by a single user.
+/// A limit that is not set means the resource is not limited.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum number of concurrently running processes.
+    pub max_running: Option<u64>,
+    /// Maximum total memory reserved by running processes.
+    pub max_memory_bytes: Option<u64>,
+    /// Maximum total CPU (in thousandths of a core) reserved by running processes.
+    pub max_cpu_millis: Option<u64>,
+    /// Maximum total size of the logs of the user's processes held in memory.
+    /// Lines beyond it are dropped, until logs are evicted.
+    pub max_log_bytes: Option<u64>,
+}
+
+impl Limits {
+    /// Takes the limits set in `self`, falling back to `other` for unset ones.
+    fn or(self, other: Limits) -> Limits {
+        Limits {
+            max_running: self.max_running.or(other.max_running),
+            max_memory_bytes: self.max_memory_bytes.or(other.max_memory_bytes),
+            max_cpu_millis: self.max_cpu_millis.or(other.max_cpu_millis),
+            max_log_bytes: self.max_log_bytes.or(other.max_log_bytes),
+        }
+    }
+
+    /// Checks whether a new process with the given reservation can be run
+    /// by a user with the given usage.
+    pub fn check(&self, usage: &Usage, reservation: &Reservation) -> Result<(), QuotaExceeded> {
+        let reserved = [
+            ("running processes", usage.running, 1, self.max_running),
+            (
+                "reserved memory bytes",
+                usage.memory_bytes,
+                reservation.memory_bytes,
+                self.max_memory_bytes,
+            ),
+            (
+                "reserved CPU millis",
+                usage.cpu_millis,
+                reservation.cpu_millis,
+                self.max_cpu_millis,
+            ),
+        ];
+        for &(resource, used, requested, limit) in reserved.iter() {
+            match limit {
+                Some(limit) if used.saturating_add(requested) > limit => {
+                    return Err(QuotaExceeded {
+                        resource,
+                        used,
+                        requested,
+                        limit,
+                    })
+                }
+                _ => {}
+            }
+        }
+        // The size of the logs is not known upfront,
+        // so new processes are rejected while the limit is reached.
+        match self.max_log_bytes {
+            Some(limit) if usage.log_bytes >= limit => Err(QuotaExceeded {
+                resource: "retained log bytes",
+                used: usage.log_bytes,
+                requested: 0,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The error produced when running a process would exceed the user's quota.
+#[derive(Debug, thiserror::Error)]
+#[error("Quota exceeded for {resource}: {used} used, {requested} requested, limit is {limit}")]
+pub struct QuotaExceeded {
+    pub resource: &'static str,
+    pub used: u64,
+    pub requested: u64,
+    pub limit: u64,
+}
+
+impl From<QuotaExceeded> for Status {
+    fn from(e: QuotaExceeded) -> Self {
+        Status::resource_exhausted(format!("{}", e))
+    }
+}
+
+/// Per-user quotas: the default limits, and overrides for specific users.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    pub default: Limits,
+    pub users: HashMap<String, Limits>,
+}
+
+impl QuotaConfig {
+    /// Returns the limits of the given user.
+    /// Limits not overridden for the user are taken from the defaults.
+    pub fn limits_for(&self, uid: &UserId) -> Limits {
+        match self.users.get(&uid.0) {
+            Some(limits) => limits.or(self.default),
+            None => self.default,
+        }
+    }
+}
+
+/// The resources currently used by a user.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Usage {
+    pub running: u64,
+    pub memory_bytes: u64,
+    pub cpu_millis: u64,
+    pub log_bytes: u64,
+}
+
+impl Usage {
+    /// Adds the resources reserved by a single process,
+    /// which only count while the process is running.
+    pub fn add(&mut self, running: bool, reservation: &Reservation) {
+        if running {
+            self.running += 1;
+            self.memory_bytes += reservation.memory_bytes;
+            self.cpu_millis += reservation.cpu_millis;
+        }
+    }
+}
+
+/// The logs held in memory for the processes of each user.
+#[derive(Default)]
+pub struct LogBudgets(Mutex<HashMap<UserId, LogBudget>>);
+
+impl LogBudgets {
+    /// Returns the budget shared by the processes of the user, created with the given limits.
+    pub fn of(&self, uid: &UserId, limits: &Limits) -> LogBudget {
+        let mut budgets = self.0.lock().unwrap();
+        budgets
+            .entry(uid.clone())
+            .or_insert_with(|| LogBudget::new(limits.max_log_bytes))
+            .clone()
+    }
+
+    /// Returns the bytes of logs held in memory for the processes of the user.
+    pub fn used(&self, uid: &UserId) -> u64 {
+        self.0.lock().unwrap().get(uid).map_or(0, LogBudget::used)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quota_limits_for() {
+        let config: QuotaConfig = toml::from_str(
+            r#"
+            [default]
+            max_running = 10
+            max_log_bytes = 1000
+
+            [users.alice]
+            max_running = 100
+            "#,
+        )
+        .unwrap();
+        let alice = config.limits_for(&UserId("alice".into()));
+        assert_eq!(alice.max_running, Some(100));
+        assert_eq!(alice.max_log_bytes, Some(1000));
+        assert_eq!(alice.max_memory_bytes, None);
+        let bob = config.limits_for(&UserId("bob".into()));
+        assert_eq!(bob.max_running, Some(10));
+    }
+
+    #[test]
+    fn test_quota_check() {
+        let limits = Limits {
+            max_running: Some(2),
+            max_memory_bytes: Some(1000),
+            max_log_bytes: Some(100),
+            ..Default::default()
+        };
+        let mut usage = Usage::default();
+        let reservation = Reservation {
+            memory_bytes: 600,
+            cpu_millis: 10_000,
+        };
+        assert!(limits.check(&usage, &reservation).is_ok());
+
+        usage.add(true, &reservation);
+        let err = limits.check(&usage, &reservation).unwrap_err();
+        assert_eq!(err.resource, "reserved memory bytes");
+
+        usage.add(false, &reservation);
+        usage.log_bytes = 100;
+        let err = limits.check(&usage, &Reservation::default()).unwrap_err();
+        assert_eq!(err.resource, "retained log bytes");
+
+        usage.log_bytes = 0;
+        usage.add(true, &Reservation::default());
+        let err = limits.check(&usage, &Reservation::default()).unwrap_err();
+        assert_eq!(err.resource, "running processes");
+    }
+
+    #[test]
+    fn test_log_budgets() {
+        let budgets = LogBudgets::default();
+        let alice = UserId("alice".into());
+        let limits = Limits {
+            max_log_bytes: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(budgets.of(&alice, &limits), budgets.of(&alice, &limits));
+        assert_ne!(
+            budgets.of(&alice, &limits),
+            budgets.of(&UserId("bob".into()), &limits)
+        );
+        assert_eq!(budgets.used(&alice), 0);
+        assert_eq!(budgets.used(&UserId("carol".into())), 0);
+    }
+}
@@ -0,0 +1,295 @@
+//! Rate limiting of RPCs, per user and per RPC.
+//!
+//! Every user has a token bucket for each RPC. A request takes a token from its bucket,
+//! and is rejected with `RESOURCE_EXHAUSTED` if the bucket is empty.
+//! Requests are rejected before reaching `ProcessService`, so they are not audited.
+//!
+//! Interceptors of tonic know the caller, but not the RPC being called,
+//! so `RateLimited` makes the RPC known to the interceptor via the extensions of the request.
+//! The interceptor authenticates the caller, and passes it on to `ProcessService`
+//! in the extensions of the request too.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use tonic::{
+    codegen::{http, Service},
+    metadata::MetadataMap,
+    transport::NamedService,
+    Code, Request, Status,
+};
+
+use crate::{
+    audit::Rpc,
+    config::Config,
+    user::{Authenticated, Caller, IdentitySource, UserId},
+};
+
+/// The metadata key of the number of seconds after which a rate limited request may be retried.
+pub const RETRY_AFTER: &str = "retry-after";
+
+/// How often buckets that have refilled are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The parameters of a token bucket.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// Tokens added to the bucket per second.
+    pub per_second: f64,
+    /// Capacity of the bucket, i.e. the number of requests allowed in a burst.
+    pub burst: u32,
+}
+
+/// Settings of rate limiting. If no rate applies to an RPC, it is not rate limited.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// The rate of every RPC, unless overridden.
+    pub default: Option<Rate>,
+    /// Rates of specific RPCs, by gRPC method name, e.g. `GetStatus`.
+    pub rpcs: HashMap<String, Rate>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.burst.into(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst.into());
+        self.updated = now;
+    }
+
+    /// Takes a token from the bucket.
+    /// If the bucket is empty, returns the time until a token is available.
+    fn take(&mut self, rate: Rate, now: Instant) -> Result<(), Duration> {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / rate.per_second,
+            ))
+        }
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<(UserId, Rpc), Bucket>,
+    pruned: Instant,
+}
+
+pub struct RateLimiter {
+    default: Option<Rate>,
+    rpcs: HashMap<Rpc, Rate>,
+    identity: IdentitySource,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> anyhow::Result<Arc<Self>> {
+        let limits = &config.rate_limits;
+        let mut rpcs = HashMap::new();
+        for (method, &rate) in &limits.rpcs {
+            let rpc = Rpc::from_method(method)
+                .ok_or_else(|| anyhow!("unknown RPC '{}' in rate limits", method))?;
+            rpcs.insert(rpc, rate);
+        }
+        for rate in limits.default.iter().chain(rpcs.values()) {
+            if rate.per_second.is_nan() || rate.per_second <= 0.0 || rate.burst == 0 {
+                bail!("rate limits must have a positive rate and burst");
+            }
+        }
+        Ok(Arc::new(Self {
+            default: limits.default,
+            rpcs,
+            identity: config.identity,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }))
+    }
+
+    fn rate(&self, rpc: Rpc) -> Option<Rate> {
+        self.rpcs.get(&rpc).copied().or(self.default)
+    }
+
+    /// Takes a token from the bucket of the user for the RPC.
+    /// If the bucket is empty, returns the time until a token is available.
+    fn check(&self, uid: &UserId, rpc: Rpc, rate: Rate, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.pruned) >= PRUNE_INTERVAL {
+            // Full buckets are equivalent to new ones.
+            buckets.buckets.retain(|&(_, rpc), bucket| {
+                let rate = self.rate(rpc).expect("buckets only exist for limited RPCs");
+                bucket.refill(rate, now);
+                bucket.tokens < rate.burst.into()
+            });
+            buckets.pruned = now;
+        }
+        buckets
+            .buckets
+            .entry((uid.clone(), rpc))
+            .or_insert_with(|| Bucket::new(rate, now))
+            .take(rate, now)
+    }
+
+    #[allow(clippy::result_large_err)] // The signature of tonic interceptors.
+    fn intercept(&self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        let authenticated = Caller::from_request(&req, self.identity);
+        // Unauthenticated requests are rejected by the service.
+        let uid = authenticated.as_ref().ok().map(|caller| caller.uid.clone());
+        req.extensions_mut().insert(Authenticated(authenticated));
+        let rpc = req.extensions().get::<Rpc>().copied();
+        let limited = rpc.and_then(|rpc| Some((rpc, self.rate(rpc)?)));
+        let (uid, rpc, rate) = match (uid, limited) {
+            (Some(uid), Some((rpc, rate))) => (uid, rpc, rate),
+            _ => return Ok(req),
+        };
+
+        match self.check(&uid, rpc, rate, Instant::now()) {
+            Ok(()) => Ok(req),
+            Err(wait) => {
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                let mut metadata = MetadataMap::new();
+                metadata.insert(
+                    RETRY_AFTER,
+                    retry_after
+                        .to_string()
+                        .parse()
+                        .expect("number should be valid metadata"),
+                );
+                Err(Status::with_metadata(
+                    Code::ResourceExhausted,
+                    format!("Rate limit exceeded, retry after {}s", retry_after),
+                    metadata,
+                ))
+            }
+        }
+    }
+}
+
+/// The interceptor of `ProcessService`, authenticating callers and rate limiting its RPCs.
+/// Must be called within `RateLimited`.
+#[derive(Clone)]
+pub struct Interceptor(pub Arc<RateLimiter>);
+
+impl tonic::service::Interceptor for Interceptor {
+    fn call(&mut self, req: Request<()>) -> Result<Request<()>, Status> {
+        self.0.intercept(req)
+    }
+}
+
+/// Wraps a gRPC service, making the RPC being called known to its `Interceptor`.
+#[derive(Clone)]
+pub struct RateLimited<S>(pub S);
+
+impl<S, B> Service<http::Request<B>> for RateLimited<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        // The path is of the form `/package.Service/Method`
+        let rpc = req
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .and_then(Rpc::from_method);
+        if let Some(rpc) = rpc {
+            req.extensions_mut().insert(rpc);
+        }
+        self.0.call(req)
+    }
+}
+
+impl<S: NamedService> NamedService for RateLimited<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_buckets() {
+        let config: Config = toml::from_str(
+            r#"
+            [rate_limits.default]
+            per_second = 100.0
+            burst = 100
+
+            [rate_limits.rpcs.GetStatus]
+            per_second = 2.0
+            burst = 2
+            "#,
+        )
+        .unwrap();
+        let limiter = RateLimiter::new(&config).unwrap();
+        let alice = UserId("alice".into());
+        let bob = UserId("bob".into());
+        let rate = limiter.rate(Rpc::GetStatus).unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.check(&alice, Rpc::GetStatus, rate, now).is_ok());
+        assert!(limiter.check(&alice, Rpc::GetStatus, rate, now).is_ok());
+        let wait = limiter
+            .check(&alice, Rpc::GetStatus, rate, now)
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // Buckets are per user and per RPC
+        assert!(limiter.check(&bob, Rpc::GetStatus, rate, now).is_ok());
+        let rate = limiter.rate(Rpc::Stop).unwrap();
+        assert!(limiter.check(&alice, Rpc::Stop, rate, now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        let rate = limiter.rate(Rpc::GetStatus).unwrap();
+        assert!(limiter.check(&alice, Rpc::GetStatus, rate, later).is_ok());
+        assert!(limiter.check(&alice, Rpc::GetStatus, rate, later).is_err());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 3);
+
+        // Buckets that have refilled are pruned periodically
+        let later = later + PRUNE_INTERVAL;
+        assert!(limiter.check(&bob, Rpc::GetStatus, rate, later).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 1);
+    }
+
+    #[test]
+    fn test_rate_limiter_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [rate_limits.rpcs.Frobnicate]
+            per_second = 1.0
+            burst = 1
+            "#,
+        )
+        .unwrap();
+        assert!(RateLimiter::new(&config).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Schedules: commands started periodically on behalf of their owner,
+//! e.g. nightly cleanups.
+//!
+//! Every schedule has a task that sleeps until the next time it fires,
+//! and then starts a process like `Exec` does.
+//! The processes are ordinary jobs, linked to the schedule that created them.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use log::warn;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{cron::Cron, job::JobSpec, store::Store};
+
+/// A concretization of Store that holds schedules.
+pub type ScheduleStore = Store<Schedule>;
+
+/// When a schedule fires.
+#[derive(Clone, Debug)]
+pub enum Trigger {
+    Cron(Cron),
+    /// Every `every`, counting from `since`.
+    Interval {
+        every: Duration,
+        since: SystemTime,
+    },
+}
+
+impl Trigger {
+    /// Returns the first time the trigger fires strictly after `after`,
+    /// or `None` if it never does.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Trigger::Cron(cron) => cron.next_after(after),
+            Trigger::Interval { every, since } => {
+                let every = every.as_secs().max(1);
+                let elapsed = after.duration_since(*since).unwrap_or_default().as_secs();
+                let periods = elapsed / every + 1;
+                since.checked_add(Duration::from_secs(periods.checked_mul(every)?))
+            }
+        }
+    }
+}
+
+/// The state of a schedule, as reported to its owner.
+#[derive(Clone, Debug, Default)]
+pub struct ScheduleState {
+    pub paused: bool,
+    pub deleted: bool,
+    /// Not set while the schedule is paused.
+    pub next_run: Option<SystemTime>,
+    /// Number of processes started by the schedule.
+    pub runs: u64,
+    pub last_run: Option<Uuid>,
+    /// Why the process of the last run could not be started, e.g. exceeded quota.
+    pub last_error: Option<String>,
+}
+
+pub struct Schedule {
+    pub spec: JobSpec,
+    pub trigger: Trigger,
+    state: Mutex<ScheduleState>,
+    // Wakes up the task of the schedule when it is paused, resumed or deleted.
+    changed: Notify,
+}
+
+impl Schedule {
+    pub fn new(spec: JobSpec, trigger: Trigger) -> Self {
+        Self {
+            spec,
+            trigger,
+            state: Default::default(),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn state(&self) -> ScheduleState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Pauses or resumes the schedule.
+    /// Runs that are missed while the schedule is paused are skipped.
+    pub fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+        self.changed.notify_one();
+    }
+
+    /// Stops the schedule for good. Processes that it has started are not affected.
+    pub fn delete(&self) {
+        self.state.lock().unwrap().deleted = true;
+        self.changed.notify_one();
+    }
+
+    /// Fires the schedule until it is deleted, calling `start` on every run.
+    /// `start` returns the ID of the started process, or why it could not be started.
+    pub async fn run<F, Fut>(self: Arc<Self>, mut start: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Uuid, String>>,
+    {
+        let mut last_fired = None;
+        loop {
+            // Never fire twice for the same time, even if the clock is behind the timer.
+            let now = SystemTime::now().max(last_fired.unwrap_or(SystemTime::UNIX_EPOCH));
+            let next_run = {
+                let mut state = self.state.lock().unwrap();
+                if state.deleted {
+                    return;
+                }
+                state.next_run = match state.paused {
+                    true => None,
+                    false => self.trigger.next_after(now),
+                };
+                state.next_run
+            };
+            let next_run = match next_run {
+                Some(t) => t,
+                None => {
+                    self.changed.notified().await;
+                    continue;
+                }
+            };
+            let wait = next_run.duration_since(now).unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.changed.notified() => continue,
+            }
+
+            last_fired = Some(next_run);
+            let result = start().await;
+            let mut state = self.state.lock().unwrap();
+            match result {
+                Ok(id) => {
+                    state.runs += 1;
+                    state.last_run = Some(id);
+                    state.last_error = None;
+                }
+                Err(e) => {
+                    warn!("could not start a scheduled process: {}", e);
+                    state.last_error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spec() -> JobSpec {
+        JobSpec {
+            argv: vec!["true".into()],
+            group: None,
+            reservation: Default::default(),
+            class: Default::default(),
+            restart: Default::default(),
+            probes: Default::default(),
+            log_rate_limit: Default::default(),
+            hooks: vec![],
+        }
+    }
+
+    #[test]
+    fn test_trigger_interval() {
+        let since = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let trigger = Trigger::Interval {
+            every: Duration::from_secs(60),
+            since,
+        };
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(trigger.next_after(at(1000)), Some(at(1060)));
+        assert_eq!(trigger.next_after(at(1059)), Some(at(1060)));
+        assert_eq!(trigger.next_after(at(1060)), Some(at(1120)));
+        assert_eq!(trigger.next_after(at(10)), Some(at(1060)));
+    }
+
+    #[tokio::test]
+    async fn test_schedule_run() {
+        let schedule = Arc::new(Schedule::new(
+            spec(),
+            Trigger::Interval {
+                every: Duration::from_secs(1),
+                since: SystemTime::now(),
+            },
+        ));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(schedule.clone().run(move || {
+            let _ = tx.send(());
+            futures::future::ready(Ok(Uuid::new_v4()))
+        }));
+
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        schedule.set_paused(true);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let state = schedule.state();
+        assert!(state.runs >= 2);
+        assert!(state.next_run.is_none());
+
+        schedule.delete();
+        task.await.unwrap();
+        assert_eq!(schedule.state().runs, state.runs);
+    }
+}
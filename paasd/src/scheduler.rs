@@ -0,0 +1,630 @@
+//! Admission of jobs.
+//!
+//! Jobs with dependencies are held pending until the jobs they depend on finish.
+//! Jobs are started immediately while there is capacity,
+//! and queued otherwise. When a process finishes, queued jobs are admitted
+//! in the order of their priority class, and within a class, by weighted fair share:
+//! the user with the fewest running processes relative to their weight goes first,
+//! and among those, the user who has waited the longest since their last admission.
+//! Queued jobs are promoted to the next class as other jobs are admitted before them,
+//! so that a steady stream of higher priority jobs can not starve the lower classes.
+//!
+//! Processes are spawned once the state of the scheduler is unlocked,
+//! as spawning may block.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use log::error;
+use serde::Deserialize;
+use worker::{Priority, Process};
+
+use crate::{
+    job::{Job, JobState},
+    user::UserId,
+};
+
+/// Priority classes of jobs, in ascending order of priority.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PriorityClass {
+    Batch,
+    #[default]
+    Normal,
+    Interactive,
+}
+
+impl PriorityClass {
+    const ALL: [PriorityClass; 3] = [
+        PriorityClass::Batch,
+        PriorityClass::Normal,
+        PriorityClass::Interactive,
+    ];
+
+    /// Returns the class the given number of levels above this one, up to the highest one.
+    fn promoted(self, levels: u64) -> PriorityClass {
+        let i = (self as u64)
+            .saturating_add(levels)
+            .min(Self::ALL.len() as u64 - 1);
+        Self::ALL[i as usize]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PriorityClass::Batch => "batch",
+            PriorityClass::Normal => "normal",
+            PriorityClass::Interactive => "interactive",
+        }
+    }
+
+    fn default_nice(self) -> Option<i32> {
+        match self {
+            PriorityClass::Batch => Some(10),
+            PriorityClass::Normal | PriorityClass::Interactive => None,
+        }
+    }
+
+    fn default_cpu_weight(self) -> u64 {
+        match self {
+            PriorityClass::Batch => 25,
+            PriorityClass::Normal => 100,
+            PriorityClass::Interactive => 400,
+        }
+    }
+}
+
+impl From<paas_types::PriorityClass> for PriorityClass {
+    fn from(class: paas_types::PriorityClass) -> Self {
+        match class {
+            paas_types::PriorityClass::Batch => PriorityClass::Batch,
+            paas_types::PriorityClass::Normal => PriorityClass::Normal,
+            paas_types::PriorityClass::Interactive => PriorityClass::Interactive,
+        }
+    }
+}
+
+/// CPU priority of the processes of a priority class.
+/// Settings not given are taken from the defaults of the class.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassConfig {
+    /// Niceness of the processes.
+    pub nice: Option<i32>,
+    /// CPU weight of the cgroup of the class. Only used with `cgroup_root`.
+    pub cpu_weight: Option<u64>,
+}
+
+/// Settings of the scheduler.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// Maximum number of processes running at once, across all users.
+    /// If not given, jobs are never queued.
+    pub max_running: Option<usize>,
+    /// Fair-share weights of users, by user ID. Users not listed have a weight of 1.
+    pub weights: HashMap<String, u64>,
+    /// A cgroup (v2) directory under which a cgroup is created for each priority class.
+    /// If not given, processes are not placed in cgroups.
+    pub cgroup_root: Option<PathBuf>,
+    pub interactive: ClassConfig,
+    pub normal: ClassConfig,
+    pub batch: ClassConfig,
+}
+
+impl SchedulerConfig {
+    fn class(&self, class: PriorityClass) -> &ClassConfig {
+        match class {
+            PriorityClass::Batch => &self.batch,
+            PriorityClass::Normal => &self.normal,
+            PriorityClass::Interactive => &self.interactive,
+        }
+    }
+}
+
+/// The share of the capacity used by a single user.
+struct Share {
+    running: usize,
+    weight: u64,
+    // Admission counter value at the user's last admission.
+    last_admitted: u64,
+}
+
+impl Share {
+    /// Compares the running processes relative to the weights of the shares.
+    fn cmp_usage(&self, other: &Share) -> Ordering {
+        (self.running as u64 * other.weight).cmp(&(other.running as u64 * self.weight))
+    }
+}
+
+/// Number of admissions after which a queued job that was passed over is promoted
+/// to the next priority class.
+const PROMOTE_AFTER_ADMISSIONS: u64 = 8;
+
+/// A job waiting for capacity.
+struct Queued {
+    uid: UserId,
+    job: Arc<Job>,
+    // Admission counter value when the job was queued.
+    since: u64,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    admissions: u64,
+    shares: HashMap<UserId, Share>,
+    queue: VecDeque<Queued>,
+    pending: Vec<Arc<Job>>,
+}
+
+impl State {
+    /// Returns the class of a queued job, after its promotions.
+    fn class(&self, queued: &Queued) -> PriorityClass {
+        let passed_over = self.admissions - queued.since;
+        queued
+            .job
+            .class
+            .promoted(passed_over / PROMOTE_AFTER_ADMISSIONS)
+    }
+
+    /// Returns the index of the queued job to admit next.
+    fn next(&self) -> Option<usize> {
+        // Users without a share have no running processes.
+        let idle = Share {
+            running: 0,
+            weight: 1,
+            last_admitted: 0,
+        };
+        let share = |uid| self.shares.get(uid).unwrap_or(&idle);
+        self.queue
+            .iter()
+            .enumerate()
+            .min_by(|(i, a), (j, b)| {
+                let (share_a, share_b) = (share(&a.uid), share(&b.uid));
+                self.class(b)
+                    .cmp(&self.class(a))
+                    .then_with(|| share_a.cmp_usage(share_b))
+                    .then(share_a.last_admitted.cmp(&share_b.last_admitted))
+                    .then(i.cmp(j))
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+/// What happened to a submitted job.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Admission {
+    Started,
+    Queued,
+    /// Waiting for the jobs it depends on.
+    Pending,
+}
+
+pub struct Scheduler {
+    max_running: Option<usize>,
+    weights: HashMap<String, u64>,
+    priorities: HashMap<PriorityClass, Priority>,
+    state: Mutex<State>,
+}
+
+impl Scheduler {
+    /// Creates the scheduler, along with the cgroups of the priority classes if configured.
+    pub fn new(config: &SchedulerConfig) -> anyhow::Result<Arc<Self>> {
+        if let Some((uid, _)) = config.weights.iter().find(|(_, w)| **w == 0) {
+            bail!("fair-share weight of user '{}' must be positive", uid);
+        }
+        let mut priorities = HashMap::new();
+        for &class in PriorityClass::ALL.iter() {
+            let class_config = config.class(class);
+            let cgroup = match &config.cgroup_root {
+                Some(root) => {
+                    let path = root.join(class.name());
+                    let weight = class_config
+                        .cpu_weight
+                        .unwrap_or_else(|| class.default_cpu_weight());
+                    worker::create_cgroup(&path, weight)
+                        .with_context(|| format!("could not create cgroup {}", path.display()))?;
+                    Some(path)
+                }
+                None => None,
+            };
+            let priority = Priority {
+                nice: class_config.nice.or_else(|| class.default_nice()),
+                cgroup,
+            };
+            priorities.insert(class, priority);
+        }
+        Ok(Arc::new(Self {
+            max_running: config.max_running,
+            weights: config.weights.clone(),
+            priorities,
+            state: Default::default(),
+        }))
+    }
+
+    fn has_capacity(&self, state: &State) -> bool {
+        self.max_running.is_none_or(|max| state.running < max)
+    }
+
+    /// Starts the job if there is capacity, or queues it otherwise.
+    /// Jobs with dependencies are held pending until the dependencies are satisfied,
+    /// and cancelled if they are not.
+    /// If the job is started immediately but its process can not be spawned,
+    /// returns the error.
+    pub fn submit(self: &Arc<Self>, uid: UserId, job: Arc<Job>) -> io::Result<Admission> {
+        let admission = {
+            let mut state = self.state.lock().unwrap();
+            if matches!(job.state(), JobState::Pending) {
+                state.pending.push(job.clone());
+                self.resolve_on_dependencies(uid, job);
+                return Ok(Admission::Pending);
+            }
+            self.admit(&mut state, &uid, &job)
+        };
+        if admission == Admission::Started {
+            if let Err(e) = self.launch(&uid, &job) {
+                self.release(&uid);
+                return Err(e);
+            }
+        }
+        Ok(admission)
+    }
+
+    /// Takes capacity for the job if there is some, in which case it must then be launched,
+    /// or queues it otherwise.
+    fn admit(&self, state: &mut State, uid: &UserId, job: &Arc<Job>) -> Admission {
+        if self.has_capacity(state) {
+            self.reserve(state, uid);
+            Admission::Started
+        } else {
+            state.queue.push_back(Queued {
+                uid: uid.clone(),
+                job: job.clone(),
+                since: state.admissions,
+            });
+            Admission::Queued
+        }
+    }
+
+    fn resolve_on_dependencies(self: &Arc<Self>, uid: UserId, job: Arc<Job>) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let result = job.dependencies.wait().await;
+            let admission = {
+                let mut state = scheduler.state.lock().unwrap();
+                // The job may have been cancelled in the meantime.
+                match state.pending.iter().position(|j| Arc::ptr_eq(j, &job)) {
+                    Some(i) => state.pending.swap_remove(i),
+                    None => return,
+                };
+                match result {
+                    Ok(()) => {
+                        job.unblock();
+                        scheduler.admit(&mut state, &uid, &job)
+                    }
+                    Err(reason) => {
+                        job.cancel(reason);
+                        return;
+                    }
+                }
+            };
+            if admission == Admission::Started {
+                // The job is marked as failed, nothing else to be done.
+                if let Err(e) = scheduler.launch(&uid, &job) {
+                    error!("could not start job after its dependencies: {:?}", e);
+                    scheduler.release(&uid);
+                }
+            }
+        });
+    }
+
+    /// Removes a pending or queued job, cancelling it.
+    /// Returns `false` if the job was neither pending nor queued.
+    pub fn cancel(&self, job: &Arc<Job>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = state.pending.iter().position(|j| Arc::ptr_eq(j, job)) {
+            state.pending.swap_remove(i);
+        } else if let Some(i) = state.queue.iter().position(|q| Arc::ptr_eq(&q.job, job)) {
+            state.queue.remove(i);
+        } else {
+            return false;
+        }
+        job.cancel("Stopped before it was started".into());
+        true
+    }
+
+    /// Takes the capacity for a job of the user.
+    fn reserve(&self, state: &mut State, uid: &UserId) {
+        state.running += 1;
+        state.admissions += 1;
+        let weight = self.weights.get(&uid.0).copied().unwrap_or(1);
+        let share = state.shares.entry(uid.clone()).or_insert(Share {
+            running: 0,
+            weight,
+            last_admitted: 0,
+        });
+        share.running += 1;
+        share.last_admitted = state.admissions;
+    }
+
+    /// Spawns the process of a job that capacity was reserved for.
+    /// The capacity must be released if the process can not be spawned.
+    fn launch(self: &Arc<Self>, uid: &UserId, job: &Job) -> io::Result<()> {
+        debug_assert!(matches!(job.state(), JobState::Queued));
+        let process = job.start(&self.priorities[&job.class])?;
+        self.release_on_exit(uid.clone(), process);
+        Ok(())
+    }
+
+    fn release_on_exit(self: &Arc<Self>, uid: UserId, process: Process) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            process.wait().await;
+            scheduler.release(&uid);
+        });
+    }
+
+    /// Frees the capacity used by a process of the user that finished or could not be spawned,
+    /// and starts queued jobs.
+    fn release(self: &Arc<Self>, uid: &UserId) {
+        let mut released = vec![uid.clone()];
+        while !released.is_empty() {
+            let admitted = {
+                let mut state = self.state.lock().unwrap();
+                for uid in released.drain(..) {
+                    state.running -= 1;
+                    if let Some(share) = state.shares.get_mut(&uid) {
+                        share.running -= 1;
+                        if share.running == 0 && !state.queue.iter().any(|q| q.uid == uid) {
+                            state.shares.remove(&uid);
+                        }
+                    }
+                }
+                let mut admitted = vec![];
+                while self.has_capacity(&state) {
+                    let queued = match state.next() {
+                        Some(i) => state.queue.remove(i).expect("index should be valid"),
+                        None => break,
+                    };
+                    self.reserve(&mut state, &queued.uid);
+                    admitted.push(queued);
+                }
+                admitted
+            };
+            for Queued { uid, job, .. } in admitted {
+                // The job is marked as failed, nothing else to be done.
+                if let Err(e) = self.launch(&uid, &job) {
+                    error!("could not start queued job: {:?}", e);
+                    released.push(uid);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::job::{Condition, Dependencies, JobSpec};
+
+    fn spec(args: &[&str], class: PriorityClass) -> JobSpec {
+        JobSpec {
+            argv: args.iter().map(|a| a.to_string()).collect(),
+            group: None,
+            reservation: Default::default(),
+            class,
+            restart: Default::default(),
+            probes: Default::default(),
+            log_rate_limit: Default::default(),
+            hooks: vec![],
+        }
+    }
+
+    fn job(args: &[&str]) -> Arc<Job> {
+        class_job(args, PriorityClass::Normal)
+    }
+
+    fn class_job(args: &[&str], class: PriorityClass) -> Arc<Job> {
+        Arc::new(Job::new(spec(args, class), None, Default::default()))
+    }
+
+    fn dependent_job(args: &[&str], jobs: &[&Arc<Job>], condition: Condition) -> Arc<Job> {
+        let dependencies = Dependencies {
+            jobs: jobs.iter().map(|&j| (Uuid::new_v4(), j.clone())).collect(),
+            condition,
+        };
+        Arc::new(Job::new(
+            spec(args, PriorityClass::Normal),
+            None,
+            dependencies,
+        ))
+    }
+
+    async fn started(job: &Job) -> Process {
+        tokio::time::timeout(Duration::from_secs(5), job.started())
+            .await
+            .expect("job should have been started")
+            .expect("job should have been started successfully")
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_unlimited() {
+        let scheduler = Scheduler::new(&SchedulerConfig::default()).unwrap();
+        let uid = UserId("alice".into());
+        for _ in 0..3 {
+            let job = job(&["true"]);
+            assert_eq!(
+                scheduler.submit(uid.clone(), job.clone()).unwrap(),
+                Admission::Started
+            );
+            assert!(job.process().is_some());
+        }
+        assert!(scheduler
+            .submit(uid, job(&["this_command_does_not_exist"]))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_fair_share() {
+        let scheduler = Scheduler::new(&SchedulerConfig {
+            max_running: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let alice = UserId("alice".into());
+        let bob = UserId("bob".into());
+        let a1 = job(&["sleep", "10"]);
+        let a2 = job(&["sleep", "10"]);
+        let a3 = job(&["sleep", "10"]);
+        let b1 = job(&["sleep", "10"]);
+        let submit = |uid: &UserId, job: &Arc<Job>| scheduler.submit(uid.clone(), job.clone());
+        assert_eq!(submit(&alice, &a1).unwrap(), Admission::Started);
+        assert_eq!(submit(&alice, &a2).unwrap(), Admission::Queued);
+        assert_eq!(submit(&alice, &a3).unwrap(), Admission::Queued);
+        assert_eq!(submit(&bob, &b1).unwrap(), Admission::Queued);
+        assert!(matches!(b1.state(), JobState::Queued));
+
+        assert!(scheduler.cancel(&a3));
+        assert!(!scheduler.cancel(&a3));
+        assert!(a3.started().await.is_none());
+
+        // Bob has not been admitted yet, so he goes before Alice's earlier job
+        a1.process().unwrap().stop().await.unwrap();
+        started(&b1).await.stop().await.unwrap();
+        started(&a2).await.stop().await.unwrap();
+        assert!(a3.process().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_dependencies() {
+        let scheduler = Scheduler::new(&SchedulerConfig::default()).unwrap();
+        let uid = UserId("alice".into());
+        let submit = |job: &Arc<Job>| scheduler.submit(uid.clone(), job.clone()).unwrap();
+        let build = job(&["sleep", "0.1"]);
+        let fails = job(&["false"]);
+        let test = dependent_job(&["true"], &[&build], Condition::Success);
+        let package = dependent_job(&["true"], &[&build, &fails], Condition::Success);
+        let cleanup = dependent_job(&["true"], &[&build, &fails], Condition::AnyExit);
+        let stopped = dependent_job(&["true"], &[&build], Condition::Success);
+
+        for j in &[&test, &package, &cleanup, &stopped] {
+            assert_eq!(submit(j), Admission::Pending);
+        }
+        assert!(scheduler.cancel(&stopped));
+        assert_eq!(submit(&build), Admission::Started);
+        assert_eq!(submit(&fails), Admission::Started);
+
+        assert!(started(&test).await.wait().await.success());
+        assert!(started(&cleanup).await.wait().await.success());
+        assert!(package.started().await.is_none());
+        assert!(matches!(package.state(), JobState::Cancelled(r) if r.contains("did not succeed")));
+        assert!(matches!(stopped.state(), JobState::Cancelled(_)));
+        assert_eq!(Condition::Success.check(&fails).await, Some(false));
+        assert_eq!(Condition::AnyExit.check(&fails).await, Some(true));
+    }
+
+    #[test]
+    fn test_scheduler_next() {
+        let alice = UserId("alice".into());
+        let bob = UserId("bob".into());
+        let carol = UserId("carol".into());
+        let mut state = State::default();
+        state.shares.insert(
+            alice.clone(),
+            Share {
+                running: 2,
+                weight: 2,
+                last_admitted: 2,
+            },
+        );
+        state.shares.insert(
+            bob.clone(),
+            Share {
+                running: 2,
+                weight: 1,
+                last_admitted: 1,
+            },
+        );
+        assert_eq!(state.next(), None);
+
+        let queued = |uid: &UserId, class| Queued {
+            uid: uid.clone(),
+            job: class_job(&["true"], class),
+            since: 0,
+        };
+        // Carol has nothing running, but batch jobs go last
+        state.queue.push_back(queued(&carol, PriorityClass::Batch));
+        state.queue.push_back(queued(&bob, PriorityClass::Normal));
+        state.queue.push_back(queued(&alice, PriorityClass::Normal));
+        // Alice uses less of her share, despite a later admission
+        assert_eq!(state.next(), Some(2));
+
+        state
+            .queue
+            .push_back(queued(&bob, PriorityClass::Interactive));
+        assert_eq!(state.next(), Some(3));
+    }
+
+    #[test]
+    fn test_scheduler_promotion() {
+        let alice = UserId("alice".into());
+        let mut state = State::default();
+        state.queue.push_back(Queued {
+            uid: alice.clone(),
+            job: class_job(&["true"], PriorityClass::Batch),
+            since: 0,
+        });
+        // Interactive jobs keep being admitted before the batch job,
+        // which is promoted to the normal class, then to the interactive one
+        let mut admitted = 0;
+        loop {
+            state.queue.push_back(Queued {
+                uid: alice.clone(),
+                job: class_job(&["true"], PriorityClass::Interactive),
+                since: state.admissions,
+            });
+            match state.next().unwrap() {
+                0 => break,
+                next => state.queue.remove(next),
+            };
+            state.admissions += 1;
+            admitted += 1;
+            assert_eq!(
+                state.class(&state.queue[0]),
+                match admitted / PROMOTE_AFTER_ADMISSIONS {
+                    0 => PriorityClass::Batch,
+                    1 => PriorityClass::Normal,
+                    _ => PriorityClass::Interactive,
+                }
+            );
+        }
+        assert_eq!(admitted, 2 * PROMOTE_AFTER_ADMISSIONS);
+    }
+
+    #[test]
+    fn test_scheduler_config() {
+        let config: SchedulerConfig = toml::from_str(
+            r#"
+            max_running = 4
+
+            [weights]
+            alice = 0
+
+            [batch]
+            nice = 19
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.class(PriorityClass::Batch).nice, Some(19));
+        assert!(Scheduler::new(&config).is_err());
+    }
+}
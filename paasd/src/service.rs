@@ -1,24 +1,53 @@
 use std::{
-    convert::{TryFrom, TryInto},
+    convert::TryInto,
     os::unix::process::ExitStatusExt,
     pin::Pin,
     process::ExitStatus as StdExitStatus,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
-use futures::{stream::Stream, StreamExt};
+use anyhow::Context;
+use futures::{stream::Stream, Future, StreamExt};
+use tokio::sync::{broadcast::error::RecvError, Mutex};
 use tonic::{Request, Response, Status};
 
 use paas_types::process_service_server as server_types;
 use paas_types::{
-    status_response::ExitStatus, ExecRequest, ExecResponse, LogsRequest, LogsResponse,
-    StatusRequest, StatusResponse, StopRequest, StopResponse,
+    completion_hook, dependencies, list_response, list_schedules_response, logs_request, probe,
+    quota_response, restart_policy, schedule_request,
+    status_response::{self, dependency, hook_delivery, ExitStatus, State},
+    watch_events_response, DeleteScheduleRequest, DeleteScheduleResponse, ExecRequest,
+    ExecResponse, ExportLogsResponse, ListRequest, ListResponse, ListSchedulesRequest,
+    ListSchedulesResponse, LogsRequest, LogsResponse, PauseScheduleRequest, PauseScheduleResponse,
+    QuotaRequest, QuotaResponse, ScheduleRequest, ScheduleResponse, StatusRequest, StatusResponse,
+    StopRequest, StopResponse, WatchEventsRequest, WatchEventsResponse,
+};
+use uuid::Uuid;
+use worker::{
+    BatchLimits, Health, LogFilter, LogQuery, LogRateLimit, LogStart, LogsEvicted, Probe,
+    ProbeCheck, Probes, ProcessEvent, RestartMode, RestartPolicy,
 };
-use worker::Process;
 
-use crate::{store::ProcessStore, user::UserId};
+use crate::{
+    audit::{AuditLog, Event, Rpc},
+    backpressure::{self, Queued, StreamConfig},
+    config::{Config, ProbeConfig},
+    events::{self, EventBus},
+    export::Exporter,
+    hook::{self, DeliveryState, HookConfig},
+    job::{Condition, Dependencies, Job, JobSpec, JobState},
+    logfile::{self, LogConfig},
+    policy::{Permission, Policy},
+    quota::{LogBudgets, QuotaConfig, Reservation, Usage},
+    schedule::{Schedule, ScheduleStore, Trigger},
+    scheduler::{Admission, Scheduler},
+    store::{GetError, Owner, ProcessStore},
+    user::{Authenticated, Caller, IdentitySource, UserId},
+};
 
 const NO_PID: &str = "Process ID not given";
+const NO_SCHEDULE_ID: &str = "Schedule ID not given";
 
 fn std_status_to_paas_status(status: StdExitStatus) -> ExitStatus {
     let code = status.code();
@@ -30,94 +59,1084 @@ fn std_status_to_paas_status(status: StdExitStatus) -> ExitStatus {
     }
 }
 
+fn std_status_to_paas_run(status: StdExitStatus) -> status_response::Run {
+    let exit_status = match std_status_to_paas_status(status) {
+        ExitStatus::Code(c) => status_response::run::ExitStatus::Code(c),
+        ExitStatus::Signal(s) => status_response::run::ExitStatus::Signal(s),
+    };
+    status_response::Run {
+        exit_status: Some(exit_status),
+    }
+}
+
+fn paas_restart_policy_to_worker(policy: paas_types::RestartPolicy) -> RestartPolicy {
+    let defaults = RestartPolicy::default();
+    let millis = |ms, default| match ms {
+        0 => default,
+        ms => Duration::from_millis(ms),
+    };
+    RestartPolicy {
+        mode: match policy.mode() {
+            restart_policy::Mode::Never => RestartMode::Never,
+            restart_policy::Mode::OnFailure => RestartMode::OnFailure,
+            restart_policy::Mode::Always => RestartMode::Always,
+        },
+        max_restarts: policy
+            .limit
+            .map(|restart_policy::Limit::MaxRestarts(max)| max),
+        initial_backoff: millis(policy.initial_backoff_millis, defaults.initial_backoff),
+        max_backoff: millis(policy.max_backoff_millis, defaults.max_backoff),
+    }
+}
+
+/// The log rate limit of a process: the one requested, within the maximum of the config.
+fn log_rate_limit(requested: paas_types::LogRateLimit, config: &LogConfig) -> LogRateLimit {
+    // 0 is unlimited.
+    let limit = |requested: u64, max: Option<u64>| match (requested, max) {
+        (0, max) => max,
+        (requested, max) => Some(max.map_or(requested, |max| requested.min(max))),
+    };
+    LogRateLimit {
+        lines_per_sec: limit(requested.lines_per_second, config.max_lines_per_sec),
+        bytes_per_sec: limit(requested.bytes_per_second, config.max_bytes_per_sec),
+    }
+}
+
+type GetLogsStream =
+    Pin<Box<dyn Stream<Item = Result<LogsResponse, Status>> + Send + Sync + 'static>>;
+type ExportLogsStream =
+    Pin<Box<dyn Stream<Item = Result<ExportLogsResponse, Status>> + Send + Sync + 'static>>;
+type WatchEventsStream =
+    Pin<Box<dyn Stream<Item = Result<WatchEventsResponse, Status>> + Send + Sync + 'static>>;
+
+/// Records the details of a request or a response in an audit log event.
+trait Auditable {
+    fn audit(&self, _event: &mut Event) {}
+}
+
+fn audit_pid(pid: &Option<paas_types::Uuid>, event: &mut Event) {
+    event.process = pid.clone().and_then(|p| p.try_into().ok());
+}
+
+fn audit_schedule(id: &Option<paas_types::Uuid>, event: &mut Event) {
+    event.schedule = id.clone().and_then(|s| s.try_into().ok());
+}
+
+impl Auditable for ExecRequest {
+    fn audit(&self, event: &mut Event) {
+        event.argv = Some(self.args.clone());
+    }
+}
+
+impl Auditable for ExecResponse {
+    fn audit(&self, event: &mut Event) {
+        audit_pid(&self.id, event);
+    }
+}
+
+impl Auditable for LogsRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_pid(&self.id, event);
+    }
+}
+
+impl Auditable for StatusRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_pid(&self.id, event);
+    }
+}
+
+impl Auditable for StopRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_pid(&self.id, event);
+    }
+}
+
+impl Auditable for ScheduleRequest {
+    fn audit(&self, event: &mut Event) {
+        event.argv = self.exec.as_ref().map(|e| e.args.clone());
+    }
+}
+
+impl Auditable for ScheduleResponse {
+    fn audit(&self, event: &mut Event) {
+        audit_schedule(&self.id, event);
+    }
+}
+
+impl Auditable for PauseScheduleRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_schedule(&self.id, event);
+    }
+}
+
+impl Auditable for DeleteScheduleRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_schedule(&self.id, event);
+    }
+}
+
+impl Auditable for WatchEventsRequest {
+    fn audit(&self, event: &mut Event) {
+        audit_pid(&self.id, event);
+    }
+}
+
+impl Auditable for GetLogsStream {}
+impl Auditable for ExportLogsStream {}
+impl Auditable for WatchEventsStream {}
+impl Auditable for StatusResponse {}
+impl Auditable for StopResponse {}
+impl Auditable for ListRequest {}
+impl Auditable for ListResponse {}
+impl Auditable for QuotaRequest {}
+impl Auditable for QuotaResponse {}
+impl Auditable for ListSchedulesRequest {}
+impl Auditable for ListSchedulesResponse {}
+impl Auditable for PauseScheduleResponse {}
+impl Auditable for DeleteScheduleResponse {}
+
+// `tonic::Status` is large, but it is what the handlers return, so helpers return it too.
+#[allow(clippy::result_large_err)]
+fn log_start(start: Option<logs_request::Start>) -> Result<LogStart, Status> {
+    Ok(match start {
+        None => LogStart::default(),
+        Some(logs_request::Start::Offset(offset)) => LogStart::Offset(offset as usize),
+        Some(logs_request::Start::Tail(lines)) => LogStart::Tail(lines as usize),
+        Some(logs_request::Start::Since(time)) => LogStart::Since(
+            humantime::parse_rfc3339_weak(&time)
+                .map_err(|e| Status::invalid_argument(format!("Invalid time: {}", e)))?,
+        ),
+    })
+}
+
+/// Maximum number of context lines of a log filter, which are held by the stream.
+const MAX_LOG_CONTEXT: u32 = 1000;
+
+#[allow(clippy::result_large_err)]
+fn log_filter(filter: paas_types::LogFilter) -> Result<LogFilter, Status> {
+    if filter.before_context.max(filter.after_context) > MAX_LOG_CONTEXT {
+        return Err(Status::invalid_argument(format!(
+            "At most {} lines of context are allowed",
+            MAX_LOG_CONTEXT
+        )));
+    }
+    Ok(LogFilter {
+        regex: filter
+            .regex
+            .parse()
+            .map_err(|e| Status::invalid_argument(format!("Invalid regex: {}", e)))?,
+        invert: filter.invert,
+        before: filter.before_context as usize,
+        after: filter.after_context as usize,
+    })
+}
+
+fn event_response(event: &events::Event, missed: u64) -> WatchEventsResponse {
+    use watch_events_response::Kind;
+    let mut resp = WatchEventsResponse {
+        id: Some(event.id.into()),
+        owner: event.owner.user.0.clone(),
+        time: humantime::format_rfc3339_millis(event.time).to_string(),
+        missed,
+        ..Default::default()
+    };
+    let (kind, status) = match event.event {
+        ProcessEvent::Spawned { pid } => {
+            resp.pid = pid;
+            (Kind::Spawned, None)
+        }
+        ProcessEvent::StdoutOpened { run } => {
+            resp.run = run;
+            (Kind::StdoutOpened, None)
+        }
+        ProcessEvent::Stopping { run } => {
+            resp.run = run;
+            (Kind::Stopping, None)
+        }
+        ProcessEvent::Exited { run, status } => {
+            resp.run = run;
+            (Kind::Exited, Some(status))
+        }
+        ProcessEvent::Restarted { run, pid } => {
+            resp.run = run;
+            resp.pid = pid;
+            (Kind::Restarted, None)
+        }
+        ProcessEvent::Reaped(status) => (Kind::Reaped, Some(status)),
+    };
+    resp.set_kind(kind);
+    resp.exit_status = status.map(|s| match std_status_to_paas_status(s) {
+        ExitStatus::Code(c) => watch_events_response::ExitStatus::Code(c),
+        ExitStatus::Signal(s) => watch_events_response::ExitStatus::Signal(s),
+    });
+    resp
+}
+
+fn quota_resource(used: u64, limit: Option<u64>) -> Option<quota_response::Resource> {
+    Some(quota_response::Resource {
+        used,
+        limit: limit.map(quota_response::resource::Limit::Max),
+    })
+}
+
 #[derive(Clone)]
 pub struct ProcessService {
     store: Arc<ProcessStore>,
+    identity: IdentitySource,
+    policy: Arc<Policy>,
+    audit: Option<Arc<AuditLog>>,
+    quotas: Arc<QuotaConfig>,
+    log_budgets: Arc<LogBudgets>,
+    scheduler: Arc<Scheduler>,
+    schedules: Arc<ScheduleStore>,
+    hooks: Arc<HookConfig>,
+    probes: Arc<ProbeConfig>,
+    logs: Arc<LogConfig>,
+    streams: StreamConfig,
+    events: Arc<EventBus>,
+    // Held while checking quotas and starting a process,
+    // so that concurrent requests can not exceed the quota together.
+    admission: Arc<Mutex<()>>,
 }
 
+#[allow(clippy::result_large_err)]
 impl ProcessService {
-    pub fn new(store: Arc<ProcessStore>) -> Self {
-        Self { store }
+    pub fn new(store: Arc<ProcessStore>, config: &Config) -> anyhow::Result<Self> {
+        let audit = match &config.audit_log {
+            Some(path) => {
+                Some(Arc::new(AuditLog::open(path).with_context(|| {
+                    format!("could not open audit log {}", path.display())
+                })?))
+            }
+            None => None,
+        };
+        Ok(Self {
+            store,
+            identity: config.identity,
+            policy: Arc::new(Policy::from_config(config)?),
+            audit,
+            quotas: Arc::new(config.quotas.clone()),
+            log_budgets: Default::default(),
+            scheduler: Scheduler::new(&config.scheduler)?,
+            schedules: Arc::new(ScheduleStore::new()),
+            hooks: Arc::new(config.hooks.clone()),
+            probes: Arc::new(config.probes.clone()),
+            logs: Arc::new(config.logs.clone()),
+            streams: config.streams.clone(),
+            events: Arc::new(EventBus::new()),
+            admission: Default::default(),
+        })
     }
 
-    fn authenticate<T>(req: &Request<T>) -> Result<UserId, Status> {
-        let peer_certs = req.peer_certs().unwrap_or_else(|| {
-            unreachable!("peer certificates should always be present (guaranteed by TLS config)")
-        });
-        let cert = peer_certs
-            .iter()
-            .next()
-            .unwrap_or_else(|| unreachable!("at least one peer cert should always be present"));
+    /// Returns the caller authenticated by the interceptor,
+    /// or authenticates it if the request did not go through one.
+    fn authenticate<T>(&self, req: &mut Request<T>) -> Result<Caller, Status> {
+        let authenticated = match req.extensions_mut().remove::<Authenticated>() {
+            Some(Authenticated(authenticated)) => authenticated,
+            None => Caller::from_request(req, self.identity),
+        };
+        authenticated.map_err(Into::<Status>::into)
+    }
 
-        Ok(UserId::try_from(cert).map_err(Into::<Status>::into)?)
+    fn get_job(
+        &self,
+        pid: paas_types::Uuid,
+        caller: &Caller,
+        permission: Permission,
+    ) -> Result<Arc<Job>, Status> {
+        self.get_owned_job(pid, caller, permission)
+            .map(|(_, job, _)| job)
     }
 
-    fn get_process(&self, pid: paas_types::Uuid, uid: &UserId) -> Result<Arc<Process>, Status> {
+    /// Gets a job like `get_job`, along with its ID and owner.
+    fn get_owned_job(
+        &self,
+        pid: paas_types::Uuid,
+        caller: &Caller,
+        permission: Permission,
+    ) -> Result<(Uuid, Arc<Job>, Owner), Status> {
         let pid = pid
             .try_into()
             .map_err(|_| Status::invalid_argument("Invalid UUID"))?;
-        Ok(self.store.get(pid, &uid).map_err(Into::<Status>::into)?)
+        let mut owner = None;
+        let job = self
+            .store
+            .get(pid, |o| {
+                owner = Some(o.clone());
+                self.policy.authorize(caller, o, permission)
+            })
+            .map_err(Into::<Status>::into)?;
+        Ok((pid, job, owner.expect("the owner is known once authorized")))
     }
-}
 
-#[tonic::async_trait]
-impl server_types::ProcessService for ProcessService {
-    type GetLogsStream =
-        Pin<Box<dyn Stream<Item = Result<LogsResponse, Status>> + Send + Sync + 'static>>;
+    /// Gets a schedule of the caller.
+    /// Schedules are private to their owner, others are told that they do not exist.
+    fn get_schedule(
+        &self,
+        id: Option<paas_types::Uuid>,
+        caller: &Caller,
+        remove: bool,
+    ) -> Result<Arc<Schedule>, Status> {
+        let id = id
+            .ok_or_else(|| Status::invalid_argument(NO_SCHEDULE_ID))?
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid UUID"))?;
+        let authorize = |owner: &Owner| match owner.user == caller.uid {
+            true => Ok(()),
+            false => Err(GetError::NotFound),
+        };
+        let schedule = match remove {
+            true => self.schedules.remove(id, authorize),
+            false => self.schedules.get(id, authorize),
+        };
+        schedule.map_err(|_| Status::not_found("Schedule with the given id not found"))
+    }
 
-    async fn exec(&self, req: Request<ExecRequest>) -> Result<Response<ExecResponse>, Status> {
-        let uid = Self::authenticate(&req)?;
-        let req = req.into_inner();
-        let mut args = req.args.iter();
+    /// Sums up the resources used by the processes of the given user.
+    /// Queued processes count as running.
+    async fn usage(&self, uid: &UserId) -> Usage {
+        let mut usage = Usage {
+            log_bytes: self.log_budgets.used(uid),
+            ..Default::default()
+        };
+        for (_, _, job) in self.store.list(|owner| owner.user == *uid) {
+            usage.add(job.is_active().await, &job.reservation);
+        }
+        usage
+    }
+
+    /// Authenticates the caller and runs the handler,
+    /// recording the request and its outcome in the audit log.
+    async fn audited<Req, Resp, F, Fut>(
+        &self,
+        rpc: Rpc,
+        mut req: Request<Req>,
+        handler: F,
+    ) -> Result<Response<Resp>, Status>
+    where
+        Req: Auditable,
+        Resp: Auditable,
+        F: FnOnce(Caller, Req) -> Fut,
+        Fut: Future<Output = Result<Resp, Status>>,
+    {
+        let mut event = Event::new(rpc);
+        event.peer = req.remote_addr();
+        req.get_ref().audit(&mut event);
+
+        let result = match self.authenticate(&mut req) {
+            Ok(caller) => {
+                event.caller = Some(caller.uid.0.clone());
+                handler(caller, req.into_inner()).await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Some(audit) = &self.audit {
+            if let Ok(resp) = &result {
+                resp.audit(&mut event);
+            }
+            audit.record(event, result.as_ref().err());
+        }
+        result.map(Response::new)
+    }
+
+    /// Checks that the caller may execute the command.
+    fn authorize_exec(&self, caller: &Caller, argv: &[String]) -> Result<(), Status> {
+        let mut args = argv.iter();
         let argv0 = args
             .next()
             .ok_or_else(|| Status::invalid_argument("'args' must be a non-empty array"))?;
-        match Process::spawn(argv0, args.map(AsRef::as_ref)) {
-            Ok(p) => {
-                let pid = self.store.insert(&uid, p);
-                Ok(Response::new(ExecResponse {
-                    id: Some(pid.into()),
-                }))
+        let args: Vec<&str> = args.map(AsRef::as_ref).collect();
+        self.policy
+            .authorize_exec(caller, argv0, &args)
+            .map_err(|e| Status::permission_denied(format!("{}", e)))
+    }
+
+    /// Validates a probe. Commands executed by the probe must be allowed for the caller,
+    /// and addresses it connects to must be allowed by the configuration.
+    fn probe(&self, caller: &Caller, probe: paas_types::Probe) -> Result<Probe, Status> {
+        let check = match probe.check {
+            Some(probe::Check::Exec(exec)) => {
+                self.authorize_exec(caller, &exec.args)?;
+                ProbeCheck::Exec(exec.args)
+            }
+            Some(probe::Check::Tcp(addr)) => {
+                self.probes.tcp(&addr)?;
+                ProbeCheck::Tcp(addr)
             }
-            Err(e) => Err(Status::unknown(format!("{}", e))),
+            Some(probe::Check::LogLine(regex)) => ProbeCheck::LogLine(
+                regex
+                    .parse()
+                    .map_err(|e| Status::invalid_argument(format!("Invalid regex: {}", e)))?,
+            ),
+            None => return Err(Status::invalid_argument("Probe without a check")),
+        };
+        let defaults = Probe::new(check);
+        let millis = |ms, default| match ms {
+            0 => default,
+            ms => Duration::from_millis(ms),
+        };
+        Ok(Probe {
+            interval: millis(probe.interval_millis, defaults.interval),
+            timeout: millis(probe.timeout_millis, defaults.timeout),
+            failure_threshold: match probe.failure_threshold {
+                0 => defaults.failure_threshold,
+                n => n,
+            },
+            ..defaults
+        })
+    }
+
+    /// Validates a completion hook. Commands executed by the hook must be allowed for the caller.
+    fn hook(
+        &self,
+        caller: &Caller,
+        hook: paas_types::CompletionHook,
+    ) -> Result<hook::Action, Status> {
+        match hook.action {
+            Some(completion_hook::Action::WebhookUrl(url)) => self.hooks.webhook(&url),
+            Some(completion_hook::Action::Command(command)) => {
+                self.authorize_exec(caller, &command.args)?;
+                Ok(hook::Action::Command(command.args))
+            }
+            None => Err(Status::invalid_argument(
+                "Completion hook without an action",
+            )),
         }
     }
 
-    async fn get_logs(
+    /// Validates an `ExecRequest` and checks that the caller may execute the command.
+    fn job_spec(&self, caller: &Caller, req: ExecRequest) -> Result<JobSpec, Status> {
+        self.authorize_exec(caller, &req.args)?;
+        let probes = Probes {
+            health: req
+                .health_check
+                .clone()
+                .map(|p| self.probe(caller, p))
+                .transpose()?,
+            readiness: req
+                .readiness_check
+                .clone()
+                .map(|p| self.probe(caller, p))
+                .transpose()?,
+            restart_unhealthy: req.restart_unhealthy,
+        };
+        let hooks = req
+            .completion_hooks
+            .iter()
+            .map(|h| self.hook(caller, h.clone()))
+            .collect::<Result<_, _>>()?;
+        let class = req.priority().into();
+        let restart = paas_restart_policy_to_worker(req.restart.clone().unwrap_or_default());
+        let log_rate_limit = log_rate_limit(req.log_rate_limit.unwrap_or_default(), &self.logs);
+        let group = match req.group {
+            g if g.is_empty() => None,
+            g if self.policy.is_member(caller, &g) => Some(g),
+            g => {
+                return Err(Status::permission_denied(format!(
+                    "Not a member of group '{}'",
+                    g
+                )))
+            }
+        };
+        Ok(JobSpec {
+            argv: req.args,
+            group,
+            reservation: Reservation {
+                memory_bytes: req.memory_bytes,
+                cpu_millis: req.cpu_millis,
+            },
+            class,
+            restart,
+            probes,
+            log_rate_limit,
+            hooks,
+        })
+    }
+
+    /// Looks up the jobs a new job depends on.
+    /// The caller must be allowed to get their status.
+    fn dependencies(
         &self,
-        req: Request<LogsRequest>,
-    ) -> Result<Response<Self::GetLogsStream>, Status> {
-        let uid = Self::authenticate(&req)?;
-        let req = req.into_inner();
+        caller: &Caller,
+        dependencies: paas_types::Dependencies,
+    ) -> Result<Dependencies, Status> {
+        let condition = match dependencies.condition() {
+            dependencies::Condition::Success => Condition::Success,
+            dependencies::Condition::AnyExit => Condition::AnyExit,
+        };
+        let jobs = dependencies
+            .processes
+            .into_iter()
+            .map(|pid| {
+                let id = pid
+                    .clone()
+                    .try_into()
+                    .map_err(|_| Status::invalid_argument("Invalid UUID"))?;
+                Ok((id, self.get_job(pid, caller, Permission::GetStatus)?))
+            })
+            .collect::<Result<_, Status>>()?;
+        Ok(Dependencies { jobs, condition })
+    }
+
+    /// Checks the quota of the user and submits a job to the scheduler.
+    /// Returns the ID of the job, and what happened to it.
+    async fn admit(
+        &self,
+        uid: UserId,
+        spec: JobSpec,
+        schedule: Option<Uuid>,
+        dependencies: Dependencies,
+    ) -> Result<(Uuid, Admission), Status> {
+        let _admission = self.admission.lock().await;
+        let usage = self.usage(&uid).await;
+        let limits = self.quotas.limits_for(&uid);
+        limits.check(&usage, &spec.reservation)?;
+        // The ID is generated up front, as the events of the job are tagged with it.
+        let pid = Uuid::new_v4();
+        let owner = Owner::new(uid.clone(), spec.group.clone());
+        let events = self.events.sink(pid, owner.clone());
+        let log_budget = self.log_budgets.of(&uid, &limits);
+        let job = Arc::new(
+            Job::new(spec, schedule, dependencies)
+                .with_events(events)
+                .with_log_budget(log_budget),
+        );
+        let admission = self
+            .scheduler
+            .submit(uid.clone(), job.clone())
+            .map_err(|e| Status::unknown(format!("{}", e)))?;
+        self.store.insert_with_id(pid, owner, job.clone());
+        if !job.hooks.is_empty() {
+            tokio::spawn(hook::notify(pid, uid.0, job.clone(), self.hooks.clone()));
+        }
+        if self.logs.dir.is_some() {
+            tokio::spawn(logfile::persist(pid, job.clone(), self.logs.clone()));
+        }
+        for (_, dependency) in &job.dependencies.jobs {
+            dependency.add_dependent(pid);
+        }
+        Ok((pid, admission))
+    }
+
+    async fn handle_exec(
+        &self,
+        caller: Caller,
+        mut req: ExecRequest,
+    ) -> Result<ExecResponse, Status> {
+        let dependencies =
+            self.dependencies(&caller, req.dependencies.take().unwrap_or_default())?;
+        let spec = self.job_spec(&caller, req)?;
+        let (pid, admission) = self.admit(caller.uid, spec, None, dependencies).await?;
+        Ok(ExecResponse {
+            id: Some(pid.into()),
+            queued: admission == Admission::Queued,
+            pending: admission == Admission::Pending,
+        })
+    }
+
+    /// Returns the stream of log batches requested, along with the ID and owner of the process.
+    /// The batches are read ahead into a bounded buffer, with backpressure on slow clients.
+    fn log_batches(
+        &self,
+        caller: &Caller,
+        req: LogsRequest,
+        metadata: bool,
+    ) -> Result<
+        (
+            Uuid,
+            Owner,
+            impl Stream<Item = Result<Queued, Status>> + Send + Sync + 'static,
+        ),
+        Status,
+    > {
         let pid = req.id.ok_or_else(|| Status::invalid_argument(NO_PID))?;
-        let process = self.get_process(pid, &uid)?;
-        // TODO: buffer multiple lines
-        let stream = process.logs().map(|b| Ok(LogsResponse { lines: vec![b] }));
-        Ok(Response::new(Box::pin(stream)))
+        let (id, job, owner) = self.get_owned_job(pid, caller, Permission::GetLogs)?;
+        let query = LogQuery {
+            start: log_start(req.start)?,
+            follow: req.follow,
+            filter: req.filter.map(log_filter).transpose()?,
+            metadata,
+        };
+        // Logs of a queued process are followed once it starts,
+        // while it has no logs captured so far.
+        let follow = query.follow;
+        let process = futures::stream::once(async move {
+            match follow {
+                true => job.started().await,
+                false => job.process(),
+            }
+        });
+        let config = self.logs.clone();
+        let batches = process
+            .filter_map(futures::future::ready)
+            .flat_map(move |p| {
+                let limits = BatchLimits::default();
+                match p.log_batches(query.clone(), limits) {
+                    Ok(batches) => batches.map(Ok).left_stream(),
+                    // Evicted logs are read back from their file.
+                    Err(LogsEvicted) => p
+                        .stored_log_batches(logfile::read(id, &config), query.clone(), limits)
+                        .map(|batch| {
+                            batch.map_err(|e| {
+                                Status::internal(format!("Could not read the logs: {}", e))
+                            })
+                        })
+                        .right_stream(),
+                }
+            });
+        Ok((id, owner, backpressure::bounded(batches, &self.streams)))
     }
 
-    async fn get_status(
+    async fn handle_get_logs(
         &self,
-        req: Request<StatusRequest>,
-    ) -> Result<Response<StatusResponse>, Status> {
-        let uid = Self::authenticate(&req)?;
-        let req = req.into_inner();
+        caller: Caller,
+        req: LogsRequest,
+    ) -> Result<GetLogsStream, Status> {
+        let (_, _, batches) = self.log_batches(&caller, req, false)?;
+        let stream = batches.map(|queued| {
+            queued.map(|Queued { batch, skipped }| {
+                let suppressed = batch.suppressed.unwrap_or_default();
+                LogsResponse {
+                    lines: batch.lines,
+                    run: batch.run,
+                    cursor: batch.cursor as u64,
+                    line_numbers: batch.line_numbers.into_iter().map(|n| n as u64).collect(),
+                    skipped_lines: skipped as u64,
+                    suppressed_lines: suppressed.lines as u64,
+                    suppressed_bytes: suppressed.bytes as u64,
+                }
+            })
+        });
+        Ok(Box::pin(stream))
+    }
+
+    async fn handle_export_logs(
+        &self,
+        caller: Caller,
+        req: LogsRequest,
+    ) -> Result<ExportLogsStream, Status> {
+        let (id, owner, batches) = self.log_batches(&caller, req, true)?;
+        let exporter = Exporter::new(id, &owner);
+        let stream = batches.map(move |queued| {
+            queued.map(|Queued { batch, skipped }| {
+                let suppressed = batch.suppressed.unwrap_or_default();
+                ExportLogsResponse {
+                    records: exporter.records(&batch),
+                    cursor: batch.cursor as u64,
+                    skipped_lines: skipped as u64,
+                    suppressed_lines: suppressed.lines as u64,
+                    suppressed_bytes: suppressed.bytes as u64,
+                }
+            })
+        });
+        Ok(Box::pin(stream))
+    }
+
+    async fn handle_get_status(
+        &self,
+        caller: Caller,
+        req: StatusRequest,
+    ) -> Result<StatusResponse, Status> {
         let pid = req.id.ok_or_else(|| Status::invalid_argument(NO_PID))?;
-        let process = self.get_process(pid, &uid)?;
-        Ok(Response::new(StatusResponse {
-            exit_status: process.status().await.map(std_status_to_paas_status),
-        }))
+        let job = self.get_job(pid, &caller, Permission::GetStatus)?;
+        let mut resp = StatusResponse {
+            schedule: job.schedule.map(Into::into),
+            ..Default::default()
+        };
+        for (id, dependency) in &job.dependencies.jobs {
+            let state = match job.dependencies.condition.check(dependency).await {
+                None => dependency::State::Waiting,
+                Some(true) => dependency::State::Satisfied,
+                Some(false) => dependency::State::Failed,
+            };
+            let mut dependency = status_response::Dependency {
+                id: Some((*id).into()),
+                ..Default::default()
+            };
+            dependency.set_state(state);
+            resp.dependencies.push(dependency);
+        }
+        resp.set_condition(match job.dependencies.condition {
+            Condition::Success => dependencies::Condition::Success,
+            Condition::AnyExit => dependencies::Condition::AnyExit,
+        });
+        resp.dependents = job
+            .dependents()
+            .into_iter()
+            .filter(|&id| {
+                self.get_job(id.into(), &caller, Permission::GetStatus)
+                    .is_ok()
+            })
+            .map(Into::into)
+            .collect();
+        resp.hooks = job
+            .hooks
+            .iter()
+            .map(|h| {
+                let delivery = h.delivery();
+                let mut resp = status_response::HookDelivery {
+                    target: h.action.target(),
+                    attempts: delivery.attempts,
+                    last_error: delivery.last_error.unwrap_or_default(),
+                    ..Default::default()
+                };
+                resp.set_state(match delivery.state {
+                    DeliveryState::Waiting => hook_delivery::State::Waiting,
+                    DeliveryState::Delivering => hook_delivery::State::Delivering,
+                    DeliveryState::Delivered => hook_delivery::State::Delivered,
+                    DeliveryState::Undelivered => hook_delivery::State::Undelivered,
+                });
+                resp
+            })
+            .collect();
+        match job.state() {
+            JobState::Pending => resp.set_state(State::Pending),
+            JobState::Queued => resp.set_state(State::Queued),
+            JobState::Started(p) => {
+                resp.set_state(State::Started);
+                resp.exit_status = p.status().await.map(std_status_to_paas_status);
+                resp.restarts = p.restarts();
+                resp.runs = p.runs().into_iter().map(std_status_to_paas_run).collect();
+                resp.suppressed_log_lines = p.suppressed_log_lines() as u64;
+                resp.suppressed_log_bytes = p.suppressed_log_bytes() as u64;
+                resp.set_health(match p.health() {
+                    None => status_response::Health::NotChecked,
+                    Some(Health::Unknown) => status_response::Health::Unknown,
+                    Some(Health::Healthy) => status_response::Health::Healthy,
+                    Some(Health::Unhealthy) => status_response::Health::Unhealthy,
+                });
+                resp.set_readiness(match p.ready() {
+                    None => status_response::Readiness::NotChecked,
+                    Some(false) => status_response::Readiness::NotReady,
+                    Some(true) => status_response::Readiness::Ready,
+                });
+            }
+            JobState::Cancelled(reason) => {
+                resp.set_state(State::Cancelled);
+                resp.error = reason;
+            }
+            JobState::Failed(e) => {
+                resp.set_state(State::Failed);
+                resp.error = e;
+            }
+        }
+        Ok(resp)
     }
 
-    async fn stop(&self, req: Request<StopRequest>) -> Result<Response<StopResponse>, Status> {
-        let uid = Self::authenticate(&req)?;
-        let req = req.into_inner();
+    async fn handle_watch_events(
+        &self,
+        caller: Caller,
+        req: WatchEventsRequest,
+    ) -> Result<WatchEventsStream, Status> {
+        let id: Option<Uuid> = match req.id {
+            Some(pid) => {
+                self.get_job(pid.clone(), &caller, Permission::GetStatus)?;
+                Some(
+                    pid.try_into()
+                        .map_err(|_| Status::invalid_argument("Invalid UUID"))?,
+                )
+            }
+            None => None,
+        };
+        let policy = self.policy.clone();
+        let visible = move |event: &events::Event| {
+            id.is_none_or(|id| id == event.id)
+                && policy
+                    .authorize(&caller, &event.owner, Permission::GetStatus)
+                    .is_ok()
+        };
+        let stream = futures::stream::unfold(
+            (self.events.subscribe(), visible),
+            |(mut rx, visible)| async move {
+                let mut missed = 0;
+                loop {
+                    match rx.recv().await {
+                        Ok(event) if visible(&event) => {
+                            let resp = event_response(&event, missed);
+                            return Some((Ok(resp), (rx, visible)));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(n)) => missed += n,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        );
+        Ok(Box::pin(stream))
+    }
+
+    async fn handle_stop(&self, caller: Caller, req: StopRequest) -> Result<StopResponse, Status> {
         let pid = req.id.ok_or_else(|| Status::invalid_argument(NO_PID))?;
-        let process = self.get_process(pid, &uid)?;
+        let job = self.get_job(pid, &caller, Permission::Stop)?;
+        if self.scheduler.cancel(&job) {
+            return Ok(StopResponse {});
+        }
+        // Not queued: either started, being started by the scheduler, or never going to be.
+        let process = match job.started().await {
+            Some(p) => p,
+            None => return Ok(StopResponse {}),
+        };
         match process.stop().await {
-            Ok(_) => Ok(Response::new(StopResponse {})),
+            Ok(_) => Ok(StopResponse {}),
             // TODO: aborted is a questionable status here
             Err(()) => Err(Status::aborted("Stop operation already in progress")),
         }
     }
+
+    async fn handle_list(&self, caller: Caller, _req: ListRequest) -> Result<ListResponse, Status> {
+        let processes = self
+            .store
+            .list(|owner| self.policy.can_list(&caller, owner))
+            .into_iter()
+            .map(|(id, owner, job)| list_response::Process {
+                id: Some(id.into()),
+                owner: owner.user.0,
+                group: owner.group.unwrap_or_default(),
+                schedule: job.schedule.map(Into::into),
+            })
+            .collect();
+        Ok(ListResponse { processes })
+    }
+
+    async fn handle_get_quota(
+        &self,
+        caller: Caller,
+        _req: QuotaRequest,
+    ) -> Result<QuotaResponse, Status> {
+        let usage = self.usage(&caller.uid).await;
+        let limits = self.quotas.limits_for(&caller.uid);
+        Ok(QuotaResponse {
+            running: quota_resource(usage.running, limits.max_running),
+            memory_bytes: quota_resource(usage.memory_bytes, limits.max_memory_bytes),
+            cpu_millis: quota_resource(usage.cpu_millis, limits.max_cpu_millis),
+            log_bytes: quota_resource(usage.log_bytes, limits.max_log_bytes),
+        })
+    }
+
+    async fn handle_schedule(
+        &self,
+        caller: Caller,
+        req: ScheduleRequest,
+    ) -> Result<ScheduleResponse, Status> {
+        let exec = req
+            .exec
+            .ok_or_else(|| Status::invalid_argument("'exec' must be given"))?;
+        if exec
+            .dependencies
+            .as_ref()
+            .is_some_and(|d| !d.processes.is_empty())
+        {
+            return Err(Status::invalid_argument(
+                "Scheduled processes can not have dependencies",
+            ));
+        }
+        let trigger = match req.trigger {
+            Some(schedule_request::Trigger::Cron(cron)) => Trigger::Cron(cron.parse()?),
+            Some(schedule_request::Trigger::IntervalSeconds(secs)) if secs > 0 => {
+                Trigger::Interval {
+                    every: Duration::from_secs(secs),
+                    since: SystemTime::now(),
+                }
+            }
+            _ => {
+                return Err(Status::invalid_argument(
+                    "Either a cron expression or a positive interval must be given",
+                ))
+            }
+        };
+        if trigger.next_after(SystemTime::now()).is_none() {
+            return Err(Status::invalid_argument("The schedule would never run"));
+        }
+        let spec = self.job_spec(&caller, exec)?;
+        let schedule = Arc::new(Schedule::new(spec.clone(), trigger));
+        let id = self
+            .schedules
+            .insert(Owner::new(caller.uid.clone(), None), schedule.clone());
+
+        let service = self.clone();
+        tokio::spawn(schedule.run(move || {
+            let service = service.clone();
+            let uid = caller.uid.clone();
+            let spec = spec.clone();
+            async move {
+                let (pid, _) = service
+                    .admit(uid, spec, Some(id), Default::default())
+                    .await
+                    .map_err(|e| e.message().to_string())?;
+                Ok(pid)
+            }
+        }));
+        Ok(ScheduleResponse {
+            id: Some(id.into()),
+        })
+    }
+
+    async fn handle_list_schedules(
+        &self,
+        caller: Caller,
+        _req: ListSchedulesRequest,
+    ) -> Result<ListSchedulesResponse, Status> {
+        let schedules = self
+            .schedules
+            .list(|owner| owner.user == caller.uid)
+            .into_iter()
+            .map(|(id, _, schedule)| {
+                let state = schedule.state();
+                let trigger = match &schedule.trigger {
+                    Trigger::Cron(cron) => {
+                        list_schedules_response::schedule::Trigger::Cron(cron.to_string())
+                    }
+                    Trigger::Interval { every, .. } => {
+                        list_schedules_response::schedule::Trigger::IntervalSeconds(every.as_secs())
+                    }
+                };
+                list_schedules_response::Schedule {
+                    id: Some(id.into()),
+                    args: schedule.spec.argv.clone(),
+                    trigger: Some(trigger),
+                    paused: state.paused,
+                    next_run: state.next_run.map_or_else(String::new, |t| {
+                        humantime::format_rfc3339_seconds(t).to_string()
+                    }),
+                    runs: state.runs,
+                    last_run: state.last_run.map(Into::into),
+                    last_error: state.last_error.unwrap_or_default(),
+                }
+            })
+            .collect();
+        Ok(ListSchedulesResponse { schedules })
+    }
+
+    async fn handle_pause_schedule(
+        &self,
+        caller: Caller,
+        req: PauseScheduleRequest,
+    ) -> Result<PauseScheduleResponse, Status> {
+        self.get_schedule(req.id, &caller, false)?
+            .set_paused(req.paused);
+        Ok(PauseScheduleResponse {})
+    }
+
+    async fn handle_delete_schedule(
+        &self,
+        caller: Caller,
+        req: DeleteScheduleRequest,
+    ) -> Result<DeleteScheduleResponse, Status> {
+        self.get_schedule(req.id, &caller, true)?.delete();
+        Ok(DeleteScheduleResponse {})
+    }
+}
+
+#[tonic::async_trait]
+impl server_types::ProcessService for ProcessService {
+    type GetLogsStream = GetLogsStream;
+    type ExportLogsStream = ExportLogsStream;
+    type WatchEventsStream = WatchEventsStream;
+
+    async fn exec(&self, req: Request<ExecRequest>) -> Result<Response<ExecResponse>, Status> {
+        self.audited(Rpc::Exec, req, |caller, req| self.handle_exec(caller, req))
+            .await
+    }
+
+    async fn get_logs(
+        &self,
+        req: Request<LogsRequest>,
+    ) -> Result<Response<Self::GetLogsStream>, Status> {
+        self.audited(Rpc::GetLogs, req, |caller, req| {
+            self.handle_get_logs(caller, req)
+        })
+        .await
+    }
+
+    async fn export_logs(
+        &self,
+        req: Request<LogsRequest>,
+    ) -> Result<Response<Self::ExportLogsStream>, Status> {
+        self.audited(Rpc::ExportLogs, req, |caller, req| {
+            self.handle_export_logs(caller, req)
+        })
+        .await
+    }
+
+    async fn get_status(
+        &self,
+        req: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        self.audited(Rpc::GetStatus, req, |caller, req| {
+            self.handle_get_status(caller, req)
+        })
+        .await
+    }
+
+    async fn stop(&self, req: Request<StopRequest>) -> Result<Response<StopResponse>, Status> {
+        self.audited(Rpc::Stop, req, |caller, req| self.handle_stop(caller, req))
+            .await
+    }
+
+    async fn list(&self, req: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        self.audited(Rpc::List, req, |caller, req| self.handle_list(caller, req))
+            .await
+    }
+
+    async fn get_quota(
+        &self,
+        req: Request<QuotaRequest>,
+    ) -> Result<Response<QuotaResponse>, Status> {
+        self.audited(Rpc::GetQuota, req, |caller, req| {
+            self.handle_get_quota(caller, req)
+        })
+        .await
+    }
+
+    async fn schedule(
+        &self,
+        req: Request<ScheduleRequest>,
+    ) -> Result<Response<ScheduleResponse>, Status> {
+        self.audited(Rpc::Schedule, req, |caller, req| {
+            self.handle_schedule(caller, req)
+        })
+        .await
+    }
+
+    async fn list_schedules(
+        &self,
+        req: Request<ListSchedulesRequest>,
+    ) -> Result<Response<ListSchedulesResponse>, Status> {
+        self.audited(Rpc::ListSchedules, req, |caller, req| {
+            self.handle_list_schedules(caller, req)
+        })
+        .await
+    }
+
+    async fn pause_schedule(
+        &self,
+        req: Request<PauseScheduleRequest>,
+    ) -> Result<Response<PauseScheduleResponse>, Status> {
+        self.audited(Rpc::PauseSchedule, req, |caller, req| {
+            self.handle_pause_schedule(caller, req)
+        })
+        .await
+    }
+
+    async fn delete_schedule(
+        &self,
+        req: Request<DeleteScheduleRequest>,
+    ) -> Result<Response<DeleteScheduleResponse>, Status> {
+        self.audited(Rpc::DeleteSchedule, req, |caller, req| {
+            self.handle_delete_schedule(caller, req)
+        })
+        .await
+    }
+
+    async fn watch_events(
+        &self,
+        req: Request<WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        self.audited(Rpc::WatchEvents, req, |caller, req| {
+            self.handle_watch_events(caller, req)
+        })
+        .await
+    }
 }
@@ -6,21 +6,33 @@ use std::{
 use tonic::Status;
 use uuid::Uuid;
 
-use worker::Process;
+use crate::{job::Job, policy::Permission, user::UserId};
+
+/// The owner of a resource: the user who created it,
+/// and optionally a group it is shared with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Owner {
+    pub user: UserId,
+    pub group: Option<String>,
+}
 
-use crate::user::UserId;
+impl Owner {
+    pub fn new(user: UserId, group: Option<String>) -> Self {
+        Self { user, group }
+    }
+}
 
 /// Represents a resource stored in state, and *owned* by some user.
-/// The `owner` data is used for authorization in `Store` impl.
+/// The `owner` data is passed to the authorization checks of `Store` users.
 struct Owned<T> {
     value: Arc<T>,
-    owner: UserId,
+    owner: Owner,
 }
 
 impl<T> Owned<T> {
-    fn new(value: T, owner: UserId) -> Self {
+    fn new(value: impl Into<Arc<T>>, owner: Owner) -> Self {
         Self {
-            value: Arc::new(value),
+            value: value.into(),
             owner,
         }
     }
@@ -30,19 +42,22 @@ impl<T> Owned<T> {
 pub enum GetError {
     #[error("Process with the given id not found")]
     NotFound,
+    #[error("Not permitted to {0} the process")]
+    PermissionDenied(Permission),
 }
 
-impl Into<Status> for GetError {
-    fn into(self) -> Status {
+impl From<GetError> for Status {
+    fn from(e: GetError) -> Self {
         use GetError::*;
-        match self {
-            NotFound => Status::not_found(format!("{}", self)),
+        match e {
+            NotFound => Status::not_found(format!("{}", e)),
+            PermissionDenied(_) => Status::permission_denied(format!("{}", e)),
         }
     }
 }
 
 /// A concretization of Store that holds processes.
-pub type ProcessStore = Store<Process>;
+pub type ProcessStore = Store<Job>;
 
 /// An in-memory storage for objects such as processes,
 /// with ownership data attached.
@@ -55,35 +70,62 @@ impl<V> Store<V> {
         Self(RwLock::new(HashMap::new()))
     }
 
-    /// Tries to get a reference to a resource with the given `id`,
-    /// owned by the user identified by the given `uid`.
+    /// Tries to get a reference to a resource with the given `id`.
+    /// `authorize` is called with the owner of the resource
+    /// and decides whether the resource may be accessed.
     /// Returns an error if a resource is not found by the given ID,
-    /// or the user is not the owner of the resource.
-    pub fn get(&self, id: Uuid, uid: &UserId) -> Result<Arc<V>, GetError> {
+    /// or the error returned by `authorize`.
+    pub fn get(
+        &self,
+        id: Uuid,
+        authorize: impl FnOnce(&Owner) -> Result<(), GetError>,
+    ) -> Result<Arc<V>, GetError> {
         let read = self.0.read().unwrap();
         let Owned { value, owner } = read.get(&id).ok_or(GetError::NotFound)?;
-        // TODO: does this need constant-time EQ?
-        if owner == uid {
-            Ok(value.clone())
-        } else {
-            Err(GetError::NotFound)
-        }
+        authorize(owner)?;
+        Ok(value.clone())
     }
 
-    /// Puts the given resource into the store,
-    /// marking the resource as being owned by the given user.
+    /// Removes the resource with the given `id` from the store and returns it.
+    /// The resource is authorized and errors are returned as in `get`.
+    pub fn remove(
+        &self,
+        id: Uuid,
+        authorize: impl FnOnce(&Owner) -> Result<(), GetError>,
+    ) -> Result<Arc<V>, GetError> {
+        let mut write = self.0.write().unwrap();
+        let Owned { owner, .. } = write.get(&id).ok_or(GetError::NotFound)?;
+        authorize(owner)?;
+        let Owned { value, .. } = write.remove(&id).expect("resource should be present");
+        Ok(value)
+    }
+
+    /// Returns the IDs, owners and references to all the resources
+    /// whose owners satisfy the given predicate.
+    pub fn list(&self, mut filter: impl FnMut(&Owner) -> bool) -> Vec<(Uuid, Owner, Arc<V>)> {
+        let read = self.0.read().unwrap();
+        read.iter()
+            .filter(|(_, Owned { owner, .. })| filter(owner))
+            .map(|(id, Owned { value, owner })| (*id, owner.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Puts the given resource (or a reference to a shared one) into the store,
+    /// marking the resource as being owned by the given owner.
     /// Generates and returns a `Uuid` that can be used to later retrieve the resource.
-    pub fn insert(&self, uid: &UserId, value: V) -> Uuid {
+    pub fn insert(&self, owner: Owner, value: impl Into<Arc<V>>) -> Uuid {
         let pid = Uuid::new_v4();
-        let prev = self
-            .0
-            .write()
-            .unwrap()
-            .insert(pid, Owned::new(value, uid.clone()));
+        self.insert_with_id(pid, owner, value);
+        pid
+    }
+
+    /// Puts the given resource into the store under an ID generated beforehand
+    /// with `Uuid::new_v4`, for resources that need to know their ID before they are stored.
+    pub fn insert_with_id(&self, id: Uuid, owner: Owner, value: impl Into<Arc<V>>) {
+        let prev = self.0.write().unwrap().insert(id, Owned::new(value, owner));
         if prev.is_some() {
             unreachable!("Duplicate UUID generated");
         }
-        pid
     }
 }
 
@@ -92,12 +134,29 @@ mod test {
     use super::*;
     type Store = super::Store<()>;
 
+    fn owner(uid: &UserId) -> Owner {
+        Owner::new(uid.clone(), None)
+    }
+
+    fn owned_by(uid: &UserId) -> impl FnOnce(&Owner) -> Result<(), GetError> + '_ {
+        move |owner| {
+            if owner.user == *uid {
+                Ok(())
+            } else {
+                Err(GetError::NotFound)
+            }
+        }
+    }
+
     #[test]
     fn test_store_get_not_found() {
         let uid = UserId("alice".into());
         let store = Store::new();
         let pid = Uuid::new_v4();
-        assert!(matches!(store.get(pid, &uid), Err(GetError::NotFound)));
+        assert!(matches!(
+            store.get(pid, owned_by(&uid)),
+            Err(GetError::NotFound)
+        ));
     }
 
     #[test]
@@ -105,15 +164,46 @@ mod test {
         let uid1 = UserId("alice".into());
         let uid2 = UserId("eve".into());
         let store = Store::new();
-        let pid = store.insert(&uid1, ());
-        assert!(matches!(store.get(pid, &uid2), Err(GetError::NotFound)))
+        let pid = store.insert(owner(&uid1), ());
+        assert!(matches!(
+            store.get(pid, owned_by(&uid2)),
+            Err(GetError::NotFound)
+        ))
     }
 
     #[test]
     fn test_store_get_authorized() {
         let uid = UserId("alice".into());
         let store = Store::new();
-        let pid = store.insert(&uid, ());
-        assert!(store.get(pid, &uid).is_ok())
+        let pid = store.insert(owner(&uid), ());
+        assert!(store.get(pid, owned_by(&uid)).is_ok())
+    }
+
+    #[test]
+    fn test_store_remove() {
+        let uid1 = UserId("alice".into());
+        let uid2 = UserId("eve".into());
+        let store = Store::new();
+        let pid = store.insert(owner(&uid1), ());
+        assert!(store.remove(pid, owned_by(&uid2)).is_err());
+        assert!(store.remove(pid, owned_by(&uid1)).is_ok());
+        assert!(matches!(
+            store.get(pid, owned_by(&uid1)),
+            Err(GetError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_store_list() {
+        let uid1 = UserId("alice".into());
+        let uid2 = UserId("eve".into());
+        let store = Store::new();
+        let pid = store.insert(owner(&uid1), ());
+        store.insert(owner(&uid2), ());
+        assert_eq!(
+            store.list(|o| o.user == uid1),
+            vec![(pid, owner(&uid1), Arc::new(()))]
+        );
+        assert_eq!(store.list(|_| true).len(), 2);
     }
 }
@@ -1,40 +1,207 @@
-use std::convert::TryFrom;
-use tonic::{transport::Certificate, Status};
-use x509_parser::{error::X509Error, nom::Finish, parse_x509_certificate};
+use std::fmt;
+
+use serde::Deserialize;
+use tonic::{Request, Status};
+use x509_parser::{
+    certificate::X509Certificate, error::X509Error, extensions::GeneralName, nom::Finish,
+    parse_x509_certificate,
+};
+
+const SPIFFE_SCHEME: &str = "spiffe://";
 
 /// The error produced by unsuccessful authentication of a user from a client certificate.
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
     #[error("malformed X509 certificate")]
     X509(#[from] X509Error),
-    #[error("no common name found in the certificate")]
-    NoCommonName,
+    #[error("no {0} found in the certificate")]
+    NoIdentity(IdentitySource),
+    #[error("more than one {0} found in the certificate")]
+    AmbiguousIdentity(IdentitySource),
+}
+
+impl From<AuthError> for Status {
+    fn from(e: AuthError) -> Self {
+        Status::unauthenticated(format!("{}", e))
+    }
 }
 
-impl Into<Status> for AuthError {
-    fn into(self) -> Status {
-        Status::unauthenticated(format!("{}", self))
+/// The part of a client certificate that identifies the user.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdentitySource {
+    /// The Common Name of the certificate subject.
+    #[default]
+    CommonName,
+    /// An e-mail address subject alternative name.
+    Email,
+    /// A DNS name subject alternative name.
+    Dns,
+    /// A URI subject alternative name with the `spiffe://` scheme,
+    /// e.g. `spiffe://corp/team/alice`.
+    Spiffe,
+}
+
+impl fmt::Display for IdentitySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use IdentitySource::*;
+        f.write_str(match self {
+            CommonName => "common name",
+            Email => "e-mail subject alternative name",
+            Dns => "DNS subject alternative name",
+            Spiffe => "SPIFFE ID",
+        })
+    }
+}
+
+impl IdentitySource {
+    /// Collects all the identities of this kind present in the certificate.
+    fn candidates<'a>(&self, cert: &'a X509Certificate) -> Result<Vec<&'a str>, X509Error> {
+        if let IdentitySource::CommonName = self {
+            return cert
+                .subject()
+                .iter_common_name()
+                .map(|cn| cn.as_str())
+                .collect();
+        }
+
+        let names = match cert.tbs_certificate.subject_alternative_name() {
+            Some((_, san)) => &san.general_names[..],
+            None => &[],
+        };
+        Ok(names
+            .iter()
+            .filter_map(|name| match (self, name) {
+                (IdentitySource::Email, GeneralName::RFC822Name(n)) => Some(*n),
+                (IdentitySource::Dns, GeneralName::DNSName(n)) => Some(*n),
+                (IdentitySource::Spiffe, GeneralName::URI(n)) if n.starts_with(SPIFFE_SCHEME) => {
+                    Some(*n)
+                }
+                _ => None,
+            })
+            .collect())
     }
 }
 
 /// Represents a user that has been successfully authenticated via a TLS client certificate.
-/// Wraps the user's common name, extracted from the certificate.
+/// Wraps the user's identity (e.g. common name), extracted from the certificate.
 // TODO: consider making field private,
 // so it is only possible to construct a UserId from a cert.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct UserId(pub String);
 
-impl TryFrom<&Certificate> for UserId {
-    type Error = AuthError;
+impl UserId {
+    /// Extracts the user's identity of the given kind from a certificate.
+    /// Certificates that contain more than one identity of that kind are rejected,
+    /// as it is not clear which one should be used.
+    fn from_certificate(cert: &X509Certificate, source: IdentitySource) -> Result<Self, AuthError> {
+        let mut candidates = source.candidates(cert)?.into_iter();
+        let identity = candidates.next().ok_or(AuthError::NoIdentity(source))?;
+        if candidates.next().is_some() {
+            return Err(AuthError::AmbiguousIdentity(source));
+        }
+        Ok(UserId(identity.into()))
+    }
+}
 
-    fn try_from(cert: &Certificate) -> Result<Self, Self::Error> {
-        let (_, cert) = parse_x509_certificate(cert.get_ref()).finish()?;
-        let common_name = cert
-            .subject()
-            .iter_common_name()
+/// An authenticated user making a request,
+/// together with the attributes of their certificate used for authorization.
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub uid: UserId,
+    /// The Organizational Units of the certificate subject.
+    pub organizational_units: Vec<String>,
+}
+
+impl Caller {
+    /// Authenticates the caller of a request from its client certificate.
+    pub fn from_request<T>(req: &Request<T>, source: IdentitySource) -> Result<Self, AuthError> {
+        let peer_certs = req.peer_certs().unwrap_or_else(|| {
+            unreachable!("peer certificates should always be present (guaranteed by TLS config)")
+        });
+        let cert = peer_certs
+            .iter()
             .next()
-            .ok_or(AuthError::NoCommonName)?
-            .as_str()?;
-        Ok(UserId(common_name.into()))
+            .unwrap_or_else(|| unreachable!("at least one peer cert should always be present"));
+        Self::from_der(cert.get_ref(), source)
+    }
+
+    /// Authenticates the caller from a DER-encoded client certificate.
+    /// See `UserId::from_certificate` for details.
+    pub fn from_der(der: &[u8], source: IdentitySource) -> Result<Self, AuthError> {
+        let (_, cert) = parse_x509_certificate(der).finish()?;
+        let uid = UserId::from_certificate(&cert, source)?;
+        let organizational_units = cert
+            .subject()
+            .iter_organizational_unit()
+            .map(|ou| ou.as_str().map(Into::into))
+            .collect::<Result<_, _>>()?;
+        Ok(Caller {
+            uid,
+            organizational_units,
+        })
+    }
+}
+
+/// The outcome of authenticating the caller of a request,
+/// stored in the extensions of the request by the interceptor of `ProcessService`
+/// so that the certificate is only parsed once.
+pub struct Authenticated(pub Result<Caller, AuthError>);
+
+#[cfg(test)]
+mod test {
+    use x509_parser::pem::parse_x509_pem;
+
+    use super::*;
+
+    fn der(name: &str) -> Vec<u8> {
+        let pem = std::fs::read(format!("../data/{}.pem", name)).unwrap();
+        parse_x509_pem(&pem).unwrap().1.contents
+    }
+
+    fn user_id(name: &str, source: IdentitySource) -> Result<UserId, AuthError> {
+        Caller::from_der(&der(name), source).map(|c| c.uid)
+    }
+
+    #[test]
+    fn test_user_id_common_name() {
+        let uid = user_id("client1", IdentitySource::CommonName).unwrap();
+        assert_eq!(uid, UserId("client1".into()));
+    }
+
+    #[test]
+    fn test_user_id_san() {
+        use IdentitySource::*;
+        let uid = user_id("client3", Email).unwrap();
+        assert_eq!(uid, UserId("client3@example.com".into()));
+        let uid = user_id("client3", Dns).unwrap();
+        assert_eq!(uid, UserId("client3.example.com".into()));
+        let uid = user_id("client3", Spiffe).unwrap();
+        assert_eq!(uid, UserId("spiffe://paas/team/client3".into()));
+    }
+
+    #[test]
+    fn test_caller_organizational_units() {
+        let caller = Caller::from_der(&der("client2"), IdentitySource::CommonName).unwrap();
+        assert_eq!(caller.uid, UserId("client2".into()));
+        assert_eq!(caller.organizational_units, vec!["ops".to_string()]);
+    }
+
+    #[test]
+    fn test_user_id_missing() {
+        let res = user_id("client1", IdentitySource::Spiffe);
+        assert!(matches!(
+            res,
+            Err(AuthError::NoIdentity(IdentitySource::Spiffe))
+        ));
+    }
+
+    #[test]
+    fn test_user_id_ambiguous() {
+        use IdentitySource::*;
+        for source in [CommonName, Spiffe].iter().copied() {
+            let res = user_id("ambiguous_client", source);
+            assert!(matches!(res, Err(AuthError::AmbiguousIdentity(s)) if s == source));
+        }
     }
 }
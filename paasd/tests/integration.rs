@@ -1,17 +1,37 @@
+use std::io::Cursor;
 use std::net::SocketAddr;
-use std::sync::Once;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Once,
+};
+use std::time::{Duration, SystemTime};
 
-use tokio;
-use tonic::Code;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tonic::{transport::Channel, Code};
 use uuid::Uuid;
 
-use paas_types::{ExecRequest, StatusRequest};
-use paasc::make_client;
-use paasd::make_server;
+use paas_types::process_service_client::ProcessServiceClient;
+use paas_types::{
+    completion_hook, dependencies, logs_request, probe, quota_response, restart_policy,
+    schedule_request, status_response, watch_events_response, CompletionHook,
+    DeleteScheduleRequest, Dependencies, ExecRequest, ListRequest, ListSchedulesRequest, LogFilter,
+    LogRateLimit, LogsRequest, PauseScheduleRequest, Probe, QuotaRequest, RestartPolicy,
+    ScheduleRequest, StatusRequest, StopRequest, WatchEventsRequest,
+};
+use paasc::{make_client, make_client_with_compression};
+use paasd::{
+    audit, make_server, Compression, Config, GroupConfig, HookConfig, IdentitySource, Limits,
+    LogConfig, Permission, QuotaConfig, Rate, RateLimitConfig, Role, SchedulerConfig, SlowConsumer,
+    StreamConfig, RETRY_AFTER,
+};
 
 fn exec_request(args: &[&str]) -> ExecRequest {
     ExecRequest {
         args: args.iter().copied().map(ToOwned::to_owned).collect(),
+        ..Default::default()
     }
 }
 
@@ -24,8 +44,12 @@ fn init() {
 }
 
 fn test_server(port: u16) {
+    test_server_with_config(port, Config::default());
+}
+
+fn test_server_with_config(port: u16, config: Config) {
     tokio::spawn(async move {
-        let server = make_server().unwrap();
+        let server = make_server(&config).unwrap();
         server
             .serve(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
             .await
@@ -85,3 +109,1409 @@ async fn test_untrusted_client() {
     let err = client.exec(exec_request(&["echo"])).await.unwrap_err();
     assert_eq!(err.code(), Code::Unknown);
 }
+
+#[tokio::test]
+async fn test_spiffe_identity() {
+    init();
+    let config = Config {
+        identity: IdentitySource::Spiffe,
+        ..Default::default()
+    };
+    test_server_with_config(18004, config);
+    let mut client3 = make_client(18004, "client3").await.unwrap();
+    let mut client1 = make_client(18004, "client1").await.unwrap();
+    let mut ambiguous = make_client(18004, "ambiguous_client").await.unwrap();
+
+    let pid = client3
+        .exec(exec_request(&["echo"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    assert!(client3.get_status(StatusRequest { id: pid }).await.is_ok());
+
+    // Certificates without a SPIFFE ID are rejected
+    let err = client1.exec(exec_request(&["echo"])).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    // Certificates with multiple SPIFFE IDs are rejected
+    let err = ambiguous.exec(exec_request(&["echo"])).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn test_roles() {
+    init();
+    let mut config = Config::default();
+    // client2 belongs to the "ops" organizational unit
+    config.roles.insert(
+        "auditor".into(),
+        Role {
+            permissions: vec![Permission::List, Permission::GetStatus],
+            organizational_units: vec!["ops".into()],
+            ..Default::default()
+        },
+    );
+    test_server_with_config(18005, config);
+    let mut client1 = make_client(18005, "client1").await.unwrap();
+    let mut client2 = make_client(18005, "client2").await.unwrap();
+
+    let pid1 = client1
+        .exec(exec_request(&["sleep", "10"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    // Auditor can list and inspect the process of another user
+    let processes = client2
+        .list(ListRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .processes;
+    assert_eq!(processes.len(), 1);
+    assert_eq!(processes[0].id, pid1);
+    assert_eq!(processes[0].owner, "client1");
+    assert!(client2
+        .get_status(StatusRequest { id: pid1.clone() })
+        .await
+        .is_ok());
+
+    // ...but can not stop it
+    let err = client2
+        .stop(StopRequest { id: pid1.clone() })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // Without a role, processes of other users are not listed
+    let pid2 = client2
+        .exec(exec_request(&["echo"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let processes = client1
+        .list(ListRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .processes;
+    assert!(processes.iter().all(|p| p.id != pid2));
+
+    client1.stop(StopRequest { id: pid1 }).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_group_ownership() {
+    init();
+    let config = Config {
+        groups: GroupConfig {
+            organizational_units: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18006, config);
+    let mut client1 = make_client(18006, "client1").await.unwrap();
+    let mut client2 = make_client(18006, "client2").await.unwrap();
+
+    // client2 belongs to the "ops" organizational unit, client1 does not
+    let err = client1
+        .exec(ExecRequest {
+            group: "ops".into(),
+            ..exec_request(&["echo"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let pid = client2
+        .exec(ExecRequest {
+            group: "ops".into(),
+            ..exec_request(&["echo"])
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let err = client1
+        .get_status(StatusRequest { id: pid })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_command_policy() {
+    init();
+    let path = std::env::temp_dir().join(format!("paasd-commands-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+        [[rules]]
+        name = "no-sleep"
+        action = "deny"
+        executable = "/**/sleep"
+        "#,
+    )
+    .unwrap();
+    let config = Config {
+        command_policy_file: Some(path.clone()),
+        ..Default::default()
+    };
+    test_server_with_config(18007, config);
+    let mut client = make_client(18007, "client1").await.unwrap();
+
+    assert!(client.exec(exec_request(&["echo"])).await.is_ok());
+    let err = client
+        .exec(exec_request(&["sleep", "10"]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert!(err.message().contains("rule 'no-sleep'"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    init();
+    let path = std::env::temp_dir().join(format!("paasd-audit-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = Config {
+        audit_log: Some(path.clone()),
+        ..Default::default()
+    };
+    test_server_with_config(18008, config);
+    let mut client1 = make_client(18008, "client1").await.unwrap();
+    let mut client2 = make_client(18008, "client2").await.unwrap();
+
+    let pid = client1
+        .exec(exec_request(&["echo", "hello"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    client1.stop(StopRequest { id: pid.clone() }).await.unwrap();
+    client2.stop(StopRequest { id: pid }).await.unwrap_err();
+
+    // Entries are written in the background
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    while contents.lines().count() < 3 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        contents = std::fs::read_to_string(&path).unwrap();
+    }
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(audit::verify(Cursor::new(&contents)).unwrap(), 3);
+
+    let entries: Vec<audit::Entry> = contents
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(entries[0].rpc, audit::Rpc::Exec);
+    assert_eq!(entries[0].caller.as_deref(), Some("client1"));
+    assert_eq!(
+        entries[0].argv,
+        Some(vec!["echo".to_string(), "hello".to_string()])
+    );
+    assert_eq!(entries[0].status, "Ok");
+    assert!(entries[0].peer.is_some());
+    assert_eq!(entries[1].rpc, audit::Rpc::Stop);
+    assert_eq!(entries[1].process, entries[0].process);
+    assert_eq!(entries[2].caller.as_deref(), Some("client2"));
+    assert_eq!(entries[2].status, "NotFound");
+}
+
+#[tokio::test]
+async fn test_quota() {
+    let config = Config {
+        quotas: QuotaConfig {
+            default: Limits {
+                max_running: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18009, config);
+    let mut client1 = make_client(18009, "client1").await.unwrap();
+    let mut client2 = make_client(18009, "client2").await.unwrap();
+
+    let pid = client1
+        .exec(exec_request(&["sleep", "10"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let err = client1
+        .exec(exec_request(&["sleep", "10"]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    // Quotas are per user
+    client2.exec(exec_request(&["true"])).await.unwrap();
+
+    let quota = client1
+        .get_quota(QuotaRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    let running = quota.running.unwrap();
+    assert_eq!(running.used, 1);
+    assert_eq!(running.limit, Some(quota_response::resource::Limit::Max(1)));
+    assert_eq!(quota.memory_bytes.unwrap().limit, None);
+
+    client1.stop(StopRequest { id: pid }).await.unwrap();
+    client1.exec(exec_request(&["true"])).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_queueing() {
+    let config = Config {
+        scheduler: SchedulerConfig {
+            max_running: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18010, config);
+    let mut client1 = make_client(18010, "client1").await.unwrap();
+    let mut client2 = make_client(18010, "client2").await.unwrap();
+
+    let running = client1
+        .exec(exec_request(&["sleep", "10"]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!running.queued);
+    let queued = client2
+        .exec(exec_request(&["echo", "hello"]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(queued.queued);
+    let cancelled = client1
+        .exec(exec_request(&["echo", "hello"]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(cancelled.queued);
+
+    let status = client2
+        .get_status(StatusRequest {
+            id: queued.id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Queued);
+
+    client1
+        .stop(StopRequest {
+            id: cancelled.id.clone(),
+        })
+        .await
+        .unwrap();
+    let status = client1
+        .get_status(StatusRequest { id: cancelled.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Cancelled);
+
+    // Logs of the queued process arrive once it is started
+    let mut logs = client2
+        .get_logs(LogsRequest {
+            id: queued.id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    client1.stop(StopRequest { id: running.id }).await.unwrap();
+    let resp = logs.message().await.unwrap().unwrap();
+    assert_eq!(resp.lines, vec![b"hello".to_vec()]);
+    let status = client2
+        .get_status(StatusRequest { id: queued.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Started);
+}
+
+#[tokio::test]
+async fn test_rate_limits() {
+    init();
+    let rate = Rate {
+        per_second: 0.01,
+        burst: 2,
+    };
+    let config = Config {
+        rate_limits: RateLimitConfig {
+            rpcs: vec![("GetStatus".to_string(), rate)].into_iter().collect(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18011, config);
+    let mut client1 = make_client(18011, "client1").await.unwrap();
+    let mut client2 = make_client(18011, "client2").await.unwrap();
+
+    let pid = client1
+        .exec(exec_request(&["true"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let status = || StatusRequest { id: pid.clone() };
+    client1.get_status(status()).await.unwrap();
+    client1.get_status(status()).await.unwrap();
+    let err = client1.get_status(status()).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    assert_eq!(
+        err.metadata().get(RETRY_AFTER).unwrap().to_str().unwrap(),
+        "100"
+    );
+
+    // Other users and RPCs have their own buckets
+    let err = client2.get_status(status()).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    client1.exec(exec_request(&["true"])).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_restart_policy() {
+    init();
+    test_server(18012);
+    let mut client = make_client(18012, "client1").await.unwrap();
+
+    let pid = client
+        .exec(ExecRequest {
+            restart: Some(RestartPolicy {
+                mode: restart_policy::Mode::OnFailure as i32,
+                limit: Some(restart_policy::Limit::MaxRestarts(1)),
+                initial_backoff_millis: 10,
+                ..Default::default()
+            }),
+            ..exec_request(&["bash", "-c", "echo hi; exit 2"])
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id: pid.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut responses = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        let lines: Vec<Vec<u8>> = resp.lines.iter().map(|l| l.to_vec()).collect();
+        responses.push((lines, resp.run));
+    }
+    assert_eq!(
+        responses,
+        vec![
+            (vec![b"hi".to_vec()], 0),
+            (vec![], 1),
+            (vec![b"hi".to_vec()], 1)
+        ]
+    );
+
+    let status = client
+        .get_status(StatusRequest { id: pid })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        status.exit_status,
+        Some(status_response::ExitStatus::Code(2))
+    );
+    assert_eq!(status.restarts, 1);
+    assert_eq!(status.runs.len(), 2);
+}
+
+#[tokio::test]
+async fn test_schedules() {
+    init();
+    test_server(18013);
+    let mut client1 = make_client(18013, "client1").await.unwrap();
+    let mut client2 = make_client(18013, "client2").await.unwrap();
+
+    let err = client1
+        .schedule(ScheduleRequest {
+            exec: Some(exec_request(&["true"])),
+            trigger: Some(schedule_request::Trigger::Cron("0 0 30 2 *".into())),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let id = client1
+        .schedule(ScheduleRequest {
+            exec: Some(exec_request(&["true"])),
+            trigger: Some(schedule_request::Trigger::IntervalSeconds(1)),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    // Wait for the first run
+    let schedule = loop {
+        let schedules = client1
+            .list_schedules(ListSchedulesRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .schedules;
+        assert_eq!(schedules.len(), 1);
+        if schedules[0].runs > 0 {
+            break schedules.into_iter().next().unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    };
+    assert_eq!(schedule.id, id);
+    assert_eq!(schedule.args, vec!["true".to_string()]);
+    assert!(!schedule.next_run.is_empty());
+
+    // Runs are ordinary processes, linked to the schedule
+    let status = client1
+        .get_status(StatusRequest {
+            id: schedule.last_run.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.schedule, id);
+    let processes = client1
+        .list(ListRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .processes;
+    assert!(processes
+        .iter()
+        .any(|p| p.id == schedule.last_run && p.schedule == id));
+
+    // Schedules are private to their owner
+    let pause = |paused| PauseScheduleRequest {
+        id: id.clone(),
+        paused,
+    };
+    let err = client2.pause_schedule(pause(true)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let schedules = client2
+        .list_schedules(ListSchedulesRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .schedules;
+    assert!(schedules.is_empty());
+
+    client1.pause_schedule(pause(true)).await.unwrap();
+    let schedules = client1
+        .list_schedules(ListSchedulesRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .schedules;
+    assert!(schedules[0].paused);
+    assert!(schedules[0].next_run.is_empty());
+
+    client1
+        .delete_schedule(DeleteScheduleRequest { id: id.clone() })
+        .await
+        .unwrap();
+    let schedules = client1
+        .list_schedules(ListSchedulesRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .schedules;
+    assert!(schedules.is_empty());
+    let err = client1
+        .delete_schedule(DeleteScheduleRequest { id })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_dependencies() {
+    init();
+    test_server(18014);
+    let mut client1 = make_client(18014, "client1").await.unwrap();
+    let mut client2 = make_client(18014, "client2").await.unwrap();
+    let after = |processes: Vec<Option<paas_types::Uuid>>, condition: dependencies::Condition| {
+        Some(Dependencies {
+            processes: processes.into_iter().flatten().collect(),
+            condition: condition as i32,
+        })
+    };
+
+    let build = client1
+        .exec(exec_request(&["sleep", "0.5"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let failing = client1
+        .exec(exec_request(&["false"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let test = client1
+        .exec(ExecRequest {
+            dependencies: after(vec![build.clone()], dependencies::Condition::Success),
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(test.pending);
+    let package = client1
+        .exec(ExecRequest {
+            dependencies: after(
+                vec![build.clone(), failing.clone()],
+                dependencies::Condition::Success,
+            ),
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    // Only processes visible to the caller can be depended on
+    let err = client2
+        .exec(ExecRequest {
+            dependencies: after(vec![build.clone()], dependencies::Condition::AnyExit),
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    let status = client1
+        .get_status(StatusRequest {
+            id: test.id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Pending);
+    assert_eq!(status.dependencies.len(), 1);
+    assert_eq!(status.dependencies[0].id, build);
+    assert_eq!(
+        status.dependencies[0].state(),
+        status_response::dependency::State::Waiting
+    );
+    let status = client1
+        .get_status(StatusRequest { id: build.clone() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.dependents.len(), 2);
+    assert!(status.dependents.contains(test.id.as_ref().unwrap()));
+
+    // Wait for the test to run once the build has succeeded
+    let mut logs = client1
+        .get_logs(LogsRequest {
+            id: test.id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    while logs.message().await.unwrap().is_some() {}
+    let status = client1
+        .get_status(StatusRequest { id: test.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Started);
+    assert_eq!(
+        status.dependencies[0].state(),
+        status_response::dependency::State::Satisfied
+    );
+
+    let status = client1
+        .get_status(StatusRequest { id: package.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state(), status_response::State::Cancelled);
+    assert!(status.error.contains("did not succeed"));
+}
+
+#[tokio::test]
+async fn test_probes() {
+    init();
+    test_server(18015);
+    let mut client = make_client(18015, "client1").await.unwrap();
+    let probe = |check| {
+        Some(Probe {
+            check: Some(check),
+            interval_millis: 50,
+            ..Default::default()
+        })
+    };
+
+    let err = client
+        .exec(ExecRequest {
+            readiness_check: probe(probe::Check::LogLine("(".into())),
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client
+        .exec(ExecRequest {
+            health_check: Some(Default::default()),
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let resp = client
+        .exec(ExecRequest {
+            health_check: probe(probe::Check::Exec(probe::Exec {
+                args: vec!["true".into()],
+            })),
+            readiness_check: probe(probe::Check::LogLine("^ready$".into())),
+            ..exec_request(&["bash", "-c", "sleep 0.2; echo ready; sleep 10"])
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let status = client
+        .get_status(StatusRequest {
+            id: resp.id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.readiness(), status_response::Readiness::NotReady);
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let status = client
+        .get_status(StatusRequest {
+            id: resp.id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.health(), status_response::Health::Healthy);
+    assert_eq!(status.readiness(), status_response::Readiness::Ready);
+    client.stop(StopRequest { id: resp.id }).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_completion_hooks() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    init();
+    let config = Config {
+        hooks: HookConfig {
+            webhook_hosts: vec!["127.0.0.1".into()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18016, config);
+    let mut client = make_client(18016, "client1").await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/done", listener.local_addr().unwrap());
+
+    let err = client
+        .exec(ExecRequest {
+            completion_hooks: vec![CompletionHook {
+                action: Some(completion_hook::Action::WebhookUrl(
+                    "http://localhost/done".into(),
+                )),
+            }],
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = client
+        .exec(ExecRequest {
+            completion_hooks: vec![CompletionHook {
+                action: Some(completion_hook::Action::WebhookUrl(
+                    "ftp://localhost".into(),
+                )),
+            }],
+            ..exec_request(&["true"])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let resp = client
+        .exec(ExecRequest {
+            completion_hooks: vec![CompletionHook {
+                action: Some(completion_hook::Action::WebhookUrl(url)),
+            }],
+            ..exec_request(&["bash", "-c", "sleep 0.2; exit 3"])
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let id = Uuid::from_slice(&resp.id.clone().unwrap().id).unwrap();
+
+    let (mut conn, _) = listener.accept().await.unwrap();
+    let mut request = vec![0; 4096];
+    let n = conn.read(&mut request).await.unwrap();
+    conn.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+        .await
+        .unwrap();
+    let request = String::from_utf8_lossy(&request[..n]).into_owned();
+    let (head, body) = request.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("POST /done"));
+    let notification: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(notification["id"], id.to_hyphenated().to_string());
+    assert_eq!(notification["owner"], "client1");
+    assert_eq!(notification["state"], "exited");
+    assert_eq!(notification["exit_code"], 3);
+    assert!(notification["duration_millis"].as_u64().unwrap() >= 200);
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let status = client
+        .get_status(StatusRequest { id: resp.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.hooks.len(), 1);
+    assert_eq!(
+        status.hooks[0].state(),
+        status_response::hook_delivery::State::Delivered
+    );
+    assert_eq!(status.hooks[0].attempts, 1);
+}
+
+#[tokio::test]
+async fn test_watch_events() {
+    use watch_events_response::Kind;
+
+    init();
+    test_server(18017);
+    let mut client1 = make_client(18017, "client1").await.unwrap();
+    let mut client2 = make_client(18017, "client2").await.unwrap();
+    let mut events = client1
+        .watch_events(WatchEventsRequest { id: None })
+        .await
+        .unwrap()
+        .into_inner();
+
+    // Processes of other users are not visible
+    client2.exec(exec_request(&["true"])).await.unwrap();
+    let id = client1
+        .exec(exec_request(&["bash", "-c", "exit 2"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut kinds = vec![];
+    while let Some(event) = events.message().await.unwrap() {
+        assert_eq!(event.id, id);
+        assert_eq!(event.owner, "client1");
+        kinds.push(event.kind());
+        if event.kind() == Kind::Reaped {
+            assert_eq!(
+                event.exit_status,
+                Some(watch_events_response::ExitStatus::Code(2))
+            );
+            break;
+        }
+    }
+    assert_eq!(
+        kinds,
+        vec![
+            Kind::Spawned,
+            Kind::StdoutOpened,
+            Kind::Exited,
+            Kind::Reaped
+        ]
+    );
+
+    let err = client2
+        .watch_events(WatchEventsRequest { id })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_log_start() {
+    init();
+    test_server(18018);
+    let mut client = make_client(18018, "client1").await.unwrap();
+    let id = client
+        .exec(exec_request(&[
+            "bash",
+            "-c",
+            "seq 1 10; sleep 0.2; seq 11 20",
+        ]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id: id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let first = logs.message().await.unwrap().unwrap();
+    assert_eq!(first.lines.len() as u64, first.cursor);
+    drop(logs);
+    let expected: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+
+    // Without following, the stream ends with the logs captured so far
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id: id.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut snapshot = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        snapshot.extend(
+            resp.lines
+                .into_iter()
+                .map(|l| String::from_utf8(l.to_vec()).unwrap()),
+        );
+    }
+    assert!(snapshot.len() >= first.lines.len());
+    assert!(expected.starts_with(&snapshot));
+
+    // A stream resumes after the last response received
+    let resumed = |start| {
+        let mut client = client.clone();
+        let id = id.clone();
+        async move {
+            let mut logs = client
+                .get_logs(LogsRequest {
+                    id,
+                    start: Some(start),
+                    follow: true,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let mut lines = vec![];
+            while let Some(resp) = logs.message().await? {
+                lines.extend(
+                    resp.lines
+                        .into_iter()
+                        .map(|l| String::from_utf8(l.to_vec()).unwrap()),
+                );
+            }
+            Ok::<_, tonic::Status>(lines)
+        }
+    };
+    let mut lines: Vec<String> = first
+        .lines
+        .into_iter()
+        .map(|l| String::from_utf8(l.to_vec()).unwrap())
+        .collect();
+    lines.extend(
+        resumed(logs_request::Start::Offset(first.cursor))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(lines, expected);
+
+    assert_eq!(
+        resumed(logs_request::Start::Tail(3)).await.unwrap(),
+        vec!["18", "19", "20"]
+    );
+    let future = humantime::format_rfc3339(SystemTime::now() + Duration::from_secs(60));
+    assert!(resumed(logs_request::Start::Since(future.to_string()))
+        .await
+        .unwrap()
+        .is_empty());
+    let error = resumed(logs_request::Start::Since("yesterday".into()))
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_log_filter() {
+    init();
+    test_server(18019);
+    let mut client = make_client(18019, "client1").await.unwrap();
+    let id = client
+        .exec(exec_request(&["seq", "1", "30"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let filter = |regex: &str, before_context| LogFilter {
+        regex: regex.into(),
+        before_context,
+        ..Default::default()
+    };
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id: id.clone(),
+            follow: true,
+            filter: Some(filter("^2.$", 1)),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut lines = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        assert_eq!(resp.lines.len(), resp.line_numbers.len());
+        lines.extend(resp.lines.into_iter().zip(resp.line_numbers));
+    }
+    let expected: Vec<_> = (19..=29).map(|n| (n.to_string().into(), n)).collect();
+    assert_eq!(lines, expected);
+
+    for filter in [filter("(", 0), filter("x", 100_000)] {
+        let error = client
+            .get_logs(LogsRequest {
+                id: id.clone(),
+                filter: Some(filter),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_log_persistence() {
+    init();
+    let dir = std::env::temp_dir().join(format!("paasd-logs-{}", std::process::id()));
+    let config = Config {
+        logs: LogConfig {
+            dir: Some(dir.clone()),
+            evict_after_secs: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18020, config);
+    let mut client = make_client(18020, "client1").await.unwrap();
+    let id = client
+        .exec(exec_request(&["seq", "1", "30"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    // The logs are compressed once the process has finished, then evicted from memory
+    let uuid = Uuid::from_slice(&id.clone().unwrap().id).unwrap();
+    let path = dir.join(format!("{}.log.zst", uuid.to_hyphenated()));
+    while !path.exists() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let get_logs = |start, filter| {
+        let mut client = client.clone();
+        let id = id.clone();
+        async move {
+            let mut logs = client
+                .get_logs(LogsRequest {
+                    id,
+                    start,
+                    filter,
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            let mut lines = vec![];
+            let mut cursor = 0;
+            while let Some(resp) = logs.message().await.unwrap() {
+                lines.extend(
+                    resp.lines
+                        .into_iter()
+                        .map(|l| String::from_utf8(l.to_vec()).unwrap()),
+                );
+                cursor = resp.cursor;
+            }
+            (lines, cursor)
+        }
+    };
+    let expected: Vec<String> = (1..=30).map(|i| i.to_string()).collect();
+    assert_eq!(get_logs(None, None).await, (expected.clone(), 30));
+    let tail = Some(logs_request::Start::Tail(5));
+    assert_eq!(get_logs(tail, None).await, (expected[25..].to_vec(), 30));
+    let filter = LogFilter {
+        regex: "^2.$".into(),
+        ..Default::default()
+    };
+    let (lines, _) = get_logs(None, Some(filter)).await;
+    assert_eq!(lines, expected[19..29].to_vec());
+
+    // Restarts and the metadata of lines are stored too
+    let id = client
+        .exec(ExecRequest {
+            restart: Some(RestartPolicy {
+                mode: restart_policy::Mode::OnFailure as i32,
+                limit: Some(restart_policy::Limit::MaxRestarts(1)),
+                initial_backoff_millis: 10,
+                ..Default::default()
+            }),
+            ..exec_request(&["bash", "-c", "echo out; echo err >&2; exit 1"])
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let uuid = Uuid::from_slice(&id.clone().unwrap().id).unwrap();
+    let path = dir.join(format!("{}.log.zst", uuid.to_hyphenated()));
+    while !path.exists() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut logs = client
+        .export_logs(LogsRequest {
+            id,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut records = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        records.extend_from_slice(&resp.records);
+    }
+    let records: Vec<serde_json::Value> = std::str::from_utf8(&records)
+        .unwrap()
+        .lines()
+        .map(|r| serde_json::from_str(r).unwrap())
+        .collect();
+    let streams: Vec<_> = records.iter().map(|r| &r["stream"]).collect();
+    assert_eq!(streams, ["stdout", "stderr", "stdout", "stderr"]);
+    let seqs: Vec<_> = records.iter().map(|r| &r["seq"]).collect();
+    assert_eq!(seqs, [1, 2, 3, 4]);
+    let timestamp = records[3]["timestamp"].as_str().unwrap();
+    assert!(humantime::parse_rfc3339(timestamp).is_ok());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_log_quota() {
+    init();
+    let dir = std::env::temp_dir().join(format!("paasd-log-quota-{}", std::process::id()));
+    let config = Config {
+        quotas: QuotaConfig {
+            default: Limits {
+                max_log_bytes: Some(19),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        logs: LogConfig {
+            dir: Some(dir.clone()),
+            evict_after_secs: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18027, config);
+    let mut client = make_client(18027, "client1").await.unwrap();
+
+    // Lines are dropped once the quota is used up: 9 lines of 1 byte and 5 of 2 bytes fit
+    let lines = seq_logs(&mut client, 100).await;
+    assert_eq!(lines, (1..=14).map(|i| i.to_string()).collect::<Vec<_>>());
+    let err = client.exec(exec_request(&["true"])).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+
+    // The quota is freed once the logs are evicted from memory
+    while let Err(e) = client.exec(exec_request(&["true"])).await {
+        assert_eq!(e.code(), Code::ResourceExhausted);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let quota = client
+        .get_quota(QuotaRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(quota.log_bytes.unwrap().used, 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_export_logs() {
+    init();
+    test_server(18021);
+    let mut client = make_client(18021, "client1").await.unwrap();
+    let script = r#"echo plain; sleep 0.1; echo '{"level": "warn", "n": 1}' >&2"#;
+    let id = client
+        .exec(exec_request(&["bash", "-c", script]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut logs = client
+        .export_logs(LogsRequest {
+            id: id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut records = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        records.extend_from_slice(&resp.records);
+    }
+    let records: Vec<serde_json::Value> = std::str::from_utf8(&records)
+        .unwrap()
+        .lines()
+        .map(|r| serde_json::from_str(r).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    let uuid = Uuid::from_slice(&id.unwrap().id).unwrap();
+    for (record, (seq, stream)) in records.iter().zip([(1, "stdout"), (2, "stderr")]) {
+        assert_eq!(record["uuid"], uuid.to_hyphenated().to_string());
+        assert_eq!(record["owner"], "client1");
+        assert_eq!(record["seq"], seq);
+        assert_eq!(record["stream"], stream);
+        let timestamp = record["timestamp"].as_str().unwrap();
+        assert!(humantime::parse_rfc3339(timestamp).is_ok());
+    }
+    assert_eq!(records[0]["line"], "plain");
+    // JSON logged by the process is embedded
+    assert_eq!(
+        records[1]["line"],
+        serde_json::json!({"level": "warn", "n": 1})
+    );
+}
+
+#[tokio::test]
+async fn test_slow_log_consumer() {
+    init();
+    let config = Config {
+        streams: StreamConfig {
+            send_buffer: 1,
+            slow_consumer: SlowConsumer::Drop,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18022, config);
+    let mut client = make_client(18022, "client1").await.unwrap();
+    let id = client
+        .exec(exec_request(&["seq", "1", "500000"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id,
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    // The logs are produced faster than they are received
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (mut received, mut skipped, mut cursor) = (0, 0, 0);
+    while let Some(resp) = logs.message().await.unwrap() {
+        received += resp.lines.len() as u64;
+        skipped += resp.skipped_lines;
+        cursor = resp.cursor;
+    }
+    assert!(skipped > 0);
+    assert_eq!(received + skipped, 500_000);
+    assert_eq!(cursor, 500_000);
+}
+
+#[tokio::test]
+async fn test_log_rate_limit() {
+    init();
+    let config = Config {
+        logs: LogConfig {
+            max_lines_per_sec: Some(50),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    test_server_with_config(18023, config);
+    let mut client = make_client(18023, "client1").await.unwrap();
+    // The requested limit is capped by the config
+    let id = client
+        .exec(ExecRequest {
+            log_rate_limit: Some(LogRateLimit {
+                lines_per_second: 1000,
+                bytes_per_second: 0,
+            }),
+            ..exec_request(&["seq", "1", "10000"])
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id: id.clone(),
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut lines = vec![];
+    let mut first_marker = None;
+    let (mut marked_lines, mut marked_bytes) = (0, 0);
+    while let Some(resp) = logs.message().await.unwrap() {
+        lines.extend(
+            resp.lines
+                .into_iter()
+                .map(|l| String::from_utf8(l.to_vec()).unwrap()),
+        );
+        if resp.suppressed_lines > 0 {
+            first_marker.get_or_insert(lines.len());
+            marked_lines += resp.suppressed_lines;
+            marked_bytes += resp.suppressed_bytes;
+        }
+    }
+    let status = client
+        .get_status(StatusRequest { id })
+        .await
+        .unwrap()
+        .into_inner();
+    // The markers are not lines of the logs
+    assert_eq!(first_marker, Some(50));
+    assert_eq!(lines.len() as u64 + status.suppressed_log_lines, 10_000);
+    assert_eq!(marked_lines, status.suppressed_log_lines);
+    assert_eq!(marked_bytes, status.suppressed_log_bytes);
+    assert!(status.suppressed_log_bytes > 0);
+}
+
+/// Forwards the connections to a port to another one,
+/// counting the bytes sent back to the clients.
+fn counting_proxy(port: u16, to: u16) -> Arc<AtomicUsize> {
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                let mut server = TcpStream::connect(("127.0.0.1", to)).await.unwrap();
+                let (mut client_read, mut client_write) = client.split();
+                let (mut server_read, mut server_write) = server.split();
+                let requests = tokio::io::copy(&mut client_read, &mut server_write);
+                let responses = async {
+                    let mut buf = vec![0; 64 * 1024];
+                    loop {
+                        let n = server_read.read(&mut buf).await?;
+                        if n == 0 {
+                            return Ok::<_, std::io::Error>(());
+                        }
+                        counter.fetch_add(n, Ordering::SeqCst);
+                        client_write.write_all(&buf[..n]).await?;
+                    }
+                };
+                // Ignore error: either side may close the connection.
+                let _ = tokio::try_join!(requests, responses);
+            });
+        }
+    });
+    received
+}
+
+/// Runs `seq 1 n` and returns its logs, read to the end.
+async fn seq_logs(client: &mut ProcessServiceClient<Channel>, n: usize) -> Vec<String> {
+    let id = client
+        .exec(exec_request(&["seq", "1", &n.to_string()]))
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let mut logs = client
+        .get_logs(LogsRequest {
+            id,
+            follow: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut lines = vec![];
+    while let Some(resp) = logs.message().await.unwrap() {
+        lines.extend(
+            resp.lines
+                .into_iter()
+                .map(|l| String::from_utf8(l.to_vec()).unwrap()),
+        );
+    }
+    lines
+}
+
+#[tokio::test]
+async fn test_compression() {
+    init();
+    let config = Config {
+        compression: Compression::Gzip,
+        ..Default::default()
+    };
+    test_server_with_config(18024, config);
+    test_server(18026);
+    let received = counting_proxy(18025, 18024);
+    let n = 200_000;
+    let expected: Vec<String> = (1..=n).map(|i| i.to_string()).collect();
+
+    // Without compression on the client, the server does not compress either
+    let mut client = make_client(18025, "client1").await.unwrap();
+    let before = received.load(Ordering::SeqCst);
+    assert_eq!(seq_logs(&mut client, n).await, expected);
+    let uncompressed = received.load(Ordering::SeqCst) - before;
+
+    let mut client = make_client_with_compression(18025, "client1", paasc::Compression::Gzip)
+        .await
+        .unwrap();
+    let before = received.load(Ordering::SeqCst);
+    assert_eq!(seq_logs(&mut client, n).await, expected);
+    let compressed = received.load(Ordering::SeqCst) - before;
+    // The logs are received in a third of the bandwidth, or less
+    assert!(
+        compressed * 3 < uncompressed,
+        "{} bytes compressed, {} uncompressed",
+        compressed,
+        uncompressed
+    );
+
+    // A server that does not compress accepts compressed requests, and responds uncompressed
+    let mut client = make_client_with_compression(18026, "client1", paasc::Compression::Gzip)
+        .await
+        .unwrap();
+    assert_eq!(seq_logs(&mut client, 1000).await, expected[..1000].to_vec());
+}
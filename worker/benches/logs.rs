@@ -0,0 +1,93 @@
+//! Throughput of streaming the logs of a process: line by line and in batches
+//! once the process has finished, so that only streaming is measured,
+//! and by many followers while the process is still producing them.
+//!
+//! Run with `cargo bench -p worker`. The size of the log defaults to 64 MiB,
+//! and can be set in bytes with the `LOG_BENCH_BYTES` environment variable.
+//! The number of followers defaults to 100, and can be set with `LOG_BENCH_FOLLOWERS`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::StreamExt;
+use tokio::runtime::Runtime;
+use worker::{BatchLimits, LogEntry, LogQuery, Process};
+
+const DEFAULT_BYTES: u64 = 64 << 20;
+const DEFAULT_FOLLOWERS: usize = 100;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
+fn spawn_producer(size: u64) -> Process {
+    // Lines of 99 characters, 100 bytes with the newline.
+    let script = format!("yes {} | head -c {}", "x".repeat(99), size);
+    Process::spawn("bash", ["-c", &script].iter().map(|s| s.as_ref())).unwrap()
+}
+
+async fn count_lines(p: Process) -> usize {
+    p.logs()
+        .fold(0, |n, e| async move {
+            n + matches!(e, LogEntry::Line(_)) as usize
+        })
+        .await
+}
+
+async fn count_batches(p: Process) -> usize {
+    p.log_batches(LogQuery::default(), BatchLimits::default())
+        .unwrap()
+        .fold(0, |n, _| async move { n + 1 })
+        .await
+}
+
+fn bench_streaming(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let size = env_or("LOG_BENCH_BYTES", DEFAULT_BYTES);
+    let p = rt.block_on(async {
+        let p = spawn_producer(size);
+        p.wait().await;
+        p
+    });
+
+    let mut group = c.benchmark_group("streaming");
+    group.throughput(Throughput::Bytes(p.log_bytes() as u64));
+    group.sample_size(10);
+    group.bench_function("per-line", |b| {
+        b.to_async(&rt).iter(|| count_lines(p.clone()))
+    });
+    group.bench_function("batched", |b| {
+        b.to_async(&rt).iter(|| count_batches(p.clone()))
+    });
+    group.finish();
+}
+
+fn bench_followers(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let size = env_or("LOG_BENCH_BYTES", DEFAULT_BYTES);
+    let followers = env_or("LOG_BENCH_FOLLOWERS", DEFAULT_FOLLOWERS);
+
+    let mut group = c.benchmark_group("followers");
+    // The throughput is of the logs delivered to all followers together,
+    // while the process produces them.
+    group.throughput(Throughput::Bytes(size * followers as u64));
+    group.sample_size(10);
+    group.bench_function(format!("{} followers", followers), |b| {
+        b.to_async(&rt).iter(|| async {
+            let p = spawn_producer(size);
+            let tasks: Vec<_> = (0..followers)
+                .map(|_| tokio::spawn(count_lines(p.clone())))
+                .collect();
+            futures::future::join_all(tasks)
+                .await
+                .into_iter()
+                .map(Result::unwrap)
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_streaming, bench_followers);
+criterion_main!(benches);
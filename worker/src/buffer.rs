@@ -0,0 +1,453 @@
+//! An append-only buffer of log entries that is read without locking.
+//!
+//! Entries are stored in a linked list of chunks, which are never moved or freed
+//! while the buffer or a cursor into them exists. Each slot of a chunk is written once.
+//! Writers append under a mutex, which is only contended by the (few) writers of a process,
+//! and then publish the new length atomically.
+//! Readers hold a cursor into the chunks, and read the slots that have been written
+//! with atomic loads, so any number of readers can follow the logs without contention.
+//!
+//! Once the logs are stored elsewhere, the entries can be evicted from memory.
+//! What is needed to find positions in the stored logs is kept:
+//! the number of entries, the positions of entries other than lines
+//! and the times of the first entries of chunks.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::SystemTime,
+};
+
+use crate::{EntryMetadata, LogEntry, LogStart, Output};
+
+/// Capacity of the first chunk. Chunks double in size up to `MAX_CHUNK`,
+/// so that processes with few lines of logs use little memory.
+const MIN_CHUNK: usize = 64;
+const MAX_CHUNK: usize = 4096;
+
+struct Slot {
+    time: SystemTime,
+    // The output of a line, none for other entries.
+    output: Option<Output>,
+    entry: LogEntry,
+}
+
+struct Chunk {
+    slots: Box<[OnceLock<Slot>]>,
+    next: OnceLock<Arc<Chunk>>,
+}
+
+impl Chunk {
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            slots: (0..capacity).map(|_| OnceLock::new()).collect(),
+            next: OnceLock::new(),
+        })
+    }
+}
+
+impl Drop for Chunk {
+    /// Drops the following chunks iteratively, since dropping them recursively
+    /// could overflow the stack for long logs.
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(chunk) = next {
+            next = match Arc::try_unwrap(chunk) {
+                Ok(mut chunk) => chunk.next.take(),
+                // Still referenced by a cursor, which will drop the rest.
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// The chunk being appended to, and the state shared by the chunks.
+struct Tail {
+    chunk: Arc<Chunk>,
+    // Position of the first slot of the chunk in the buffer.
+    start: usize,
+    // The first chunk, none once the entries have been evicted.
+    head: Option<Arc<Chunk>>,
+    // Positions of the restart markers, in order.
+    restarts: Vec<usize>,
+    // Positions of the entries that are not lines, including restart markers, in order.
+    markers: Vec<usize>,
+    // Positions and times of the first entries of the chunks, once the entries have been evicted.
+    index: Vec<(usize, SystemTime)>,
+    evicted: bool,
+}
+
+pub(crate) struct LogBuffer {
+    tail: Mutex<Tail>,
+    len: AtomicUsize,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        let head = Chunk::new(MIN_CHUNK);
+        Self {
+            tail: Mutex::new(Tail {
+                chunk: head.clone(),
+                start: 0,
+                head: Some(head),
+                restarts: vec![],
+                markers: vec![],
+                index: vec![],
+                evicted: false,
+            }),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of entries that have been appended.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Appends an entry, unless the entries have been evicted.
+    pub fn push(&self, entry: LogEntry, output: Option<Output>) {
+        let mut tail = self.tail.lock().unwrap();
+        if tail.evicted {
+            return;
+        }
+        let len = self.len.load(Ordering::Relaxed);
+        if len - tail.start == tail.chunk.slots.len() {
+            let next = Chunk::new((tail.chunk.slots.len() * 2).min(MAX_CHUNK));
+            let _ = tail.chunk.next.set(next.clone());
+            tail.chunk = next;
+            tail.start = len;
+        }
+        match entry {
+            LogEntry::Line(_) => {}
+            LogEntry::Restart(_) => {
+                tail.restarts.push(len);
+                tail.markers.push(len);
+            }
+            LogEntry::Suppressed(_) => tail.markers.push(len),
+        }
+        let slot = Slot {
+            time: SystemTime::now(),
+            output,
+            entry,
+        };
+        if tail.chunk.slots[len - tail.start].set(slot).is_err() {
+            unreachable!("slots are written once, under the lock");
+        }
+        self.len.store(len + 1, Ordering::Release);
+    }
+
+    /// Drops the entries. Cursors that have been created keep reading them.
+    /// Entries pushed afterwards are dropped too.
+    pub fn evict(&self) {
+        let mut tail = self.tail.lock().unwrap();
+        if tail.evicted {
+            return;
+        }
+        tail.evicted = true;
+        let len = self.len.load(Ordering::Relaxed);
+        let mut chunk = tail.head.take();
+        let mut start = 0;
+        while let Some(c) = chunk.filter(|_| start < len) {
+            let first = c.slots[0].get().expect("slots before the end are written");
+            tail.index.push((start, first.time));
+            start += c.slots.len();
+            chunk = c.next.get().cloned();
+        }
+        tail.chunk = Chunk::new(0);
+    }
+
+    /// Returns a cursor at the given position, which must not be past the end of the buffer,
+    /// or `None` if the entries have been evicted.
+    pub fn cursor_at(&self, pos: usize) -> Option<Cursor> {
+        assert!(pos <= self.len(), "cursor past the end of the buffer");
+        let mut chunk = self.tail.lock().unwrap().head.clone()?;
+        let mut offset = pos;
+        while offset > chunk.slots.len() {
+            offset -= chunk.slots.len();
+            chunk = chunk.next.get().expect("chunk before the end").clone();
+        }
+        Some(Cursor { chunk, offset })
+    }
+
+    /// Returns where a stream starting at `start` begins.
+    pub fn start(&self, start: LogStart) -> Start {
+        // Holding the lock, so that no entries are appended meanwhile.
+        let tail = self.tail.lock().unwrap();
+        let len = self.len.load(Ordering::Relaxed);
+        let pos = match start {
+            LogStart::Offset(offset) => offset.min(len),
+            LogStart::Tail(mut lines) => {
+                // Lines are counted back from the end, skipping the other entries.
+                let mut pos = len;
+                for &marker in tail.markers.iter().rev() {
+                    let between = pos - marker - 1;
+                    if lines <= between {
+                        break;
+                    }
+                    lines -= between;
+                    pos = marker;
+                }
+                pos.saturating_sub(lines)
+            }
+            LogStart::Since(time) => match &tail.head {
+                Some(head) => since(head.clone(), time, len),
+                // The first entry logged at or after the time is in the chunk
+                // before the first one that starts at or after it.
+                None => {
+                    let i = tail.index.partition_point(|&(_, t)| t < time);
+                    i.checked_sub(1).map_or(0, |i| tail.index[i].0)
+                }
+            },
+        };
+        let run = tail.restarts.partition_point(|&r| r < pos);
+        let number = pos - tail.markers.partition_point(|&m| m < pos);
+        Start {
+            pos,
+            run: run as u32,
+            number,
+        }
+    }
+}
+
+/// Where a stream of the logs begins.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Start {
+    pub pos: usize,
+    /// The run of the process the entries at the position belong to.
+    pub run: u32,
+    /// The number of lines before the position.
+    pub number: usize,
+}
+
+/// Returns the position of the first entry appended at or after `time`,
+/// looking at the first `len` entries from the `head` chunk, or `len` if there is none.
+/// Entries are appended in order of time, unless the system clock is set back.
+fn since(mut chunk: Arc<Chunk>, time: SystemTime, len: usize) -> usize {
+    let mut start = 0;
+    while start < len {
+        let written = &chunk.slots[..(len - start).min(chunk.slots.len())];
+        let i = written.partition_point(|slot| {
+            slot.get().expect("slots before the end are written").time < time
+        });
+        if i < written.len() {
+            return start + i;
+        }
+        start += written.len();
+        chunk = match chunk.next.get() {
+            Some(next) => next.clone(),
+            None => break,
+        };
+    }
+    len
+}
+
+/// A position in a `LogBuffer`, from which entries are read in order.
+pub(crate) struct Cursor {
+    chunk: Arc<Chunk>,
+    // Position in the chunk, equal to its capacity once it has been read entirely.
+    offset: usize,
+}
+
+impl Cursor {
+    fn next_slot(&mut self) -> Option<&Slot> {
+        if self.offset == self.chunk.slots.len() {
+            let next = self.chunk.next.get()?.clone();
+            self.chunk = next;
+            self.offset = 0;
+        }
+        let slot = self.chunk.slots[self.offset].get()?;
+        self.offset += 1;
+        Some(slot)
+    }
+
+    /// Returns the next entry, or `None` if it has not been appended yet.
+    pub fn next(&mut self) -> Option<&LogEntry> {
+        self.next_slot().map(|slot| &slot.entry)
+    }
+
+    /// Returns all entries that have been appended since the last read,
+    /// along with their metadata.
+    pub fn read_available(&mut self) -> Vec<(LogEntry, Option<EntryMetadata>)> {
+        let mut entries = vec![];
+        while let Some(slot) = self.next_slot() {
+            let metadata = EntryMetadata {
+                time: slot.time,
+                output: slot.output,
+            };
+            entries.push((slot.entry.clone(), Some(metadata)));
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Suppressed;
+
+    fn line(i: usize) -> LogEntry {
+        LogEntry::Line(format!("{}", i).into())
+    }
+
+    /// Returns the position and run of a stream starting at `start`.
+    fn pos_run(buffer: &LogBuffer, start: LogStart) -> (usize, u32) {
+        let start = buffer.start(start);
+        (start.pos, start.run)
+    }
+
+    #[test]
+    fn test_buffer_push_read() {
+        let buffer = LogBuffer::new();
+        let mut cursor = buffer.cursor_at(0).unwrap();
+        assert!(cursor.next().is_none());
+        // Spans chunks of different sizes
+        let output = |i| [Output::Stdout, Output::Stderr][i % 2];
+        for i in 0..10_000 {
+            buffer.push(line(i), Some(output(i)));
+        }
+        assert_eq!(buffer.len(), 10_000);
+        let entries = cursor.read_available();
+        assert_eq!(entries.len(), 10_000);
+        assert!(entries
+            .iter()
+            .enumerate()
+            .all(|(i, (e, m))| *e == line(i) && m.unwrap().output == Some(output(i))));
+
+        buffer.push(line(10_000), None);
+        assert_eq!(cursor.next(), Some(&line(10_000)));
+        for &pos in &[0, 63, 64, 65, 191, 192, 5000, 10_001] {
+            let mut cursor = buffer.cursor_at(pos).unwrap();
+            assert_eq!(
+                cursor.next().cloned(),
+                Some(line(pos)).filter(|_| pos <= 10_000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_buffer_start() {
+        let buffer = LogBuffer::new();
+        assert_eq!(pos_run(&buffer, LogStart::Tail(10)), (0, 0));
+        // Entries: 0..100, restart, 100..110, restart, 110..115
+        for i in 0..100 {
+            buffer.push(line(i), None);
+        }
+        // Lines are timed to the resolution of the clock.
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let middle = SystemTime::now();
+        buffer.push(LogEntry::Restart(1), None);
+        for i in 100..110 {
+            buffer.push(line(i), None);
+        }
+        buffer.push(LogEntry::Restart(2), None);
+        for i in 110..115 {
+            buffer.push(line(i), None);
+        }
+
+        assert_eq!(pos_run(&buffer, LogStart::Offset(0)), (0, 0));
+        assert_eq!(pos_run(&buffer, LogStart::Offset(101)), (101, 1));
+        assert_eq!(pos_run(&buffer, LogStart::Offset(1000)), (117, 2));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(0)), (117, 2));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(5)), (112, 2));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(6)), (110, 1));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(15)), (101, 1));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(16)), (99, 0));
+        assert_eq!(pos_run(&buffer, LogStart::Tail(1000)), (0, 0));
+        let pos = buffer.start(LogStart::Tail(20)).pos;
+        let lines = buffer.cursor_at(pos).unwrap().read_available();
+        let is_line = |(e, _): &&(LogEntry, _)| matches!(e, LogEntry::Line(_));
+        assert_eq!(lines.iter().filter(is_line).count(), 20);
+
+        assert_eq!(
+            pos_run(&buffer, LogStart::Since(SystemTime::UNIX_EPOCH)),
+            (0, 0)
+        );
+        assert_eq!(pos_run(&buffer, LogStart::Since(middle)), (100, 0));
+        let later = SystemTime::now() + std::time::Duration::from_secs(1);
+        assert_eq!(pos_run(&buffer, LogStart::Since(later)), (117, 2));
+    }
+
+    #[test]
+    fn test_buffer_markers() {
+        // Entries: 0..10, suppressed, restart, 10..15, suppressed
+        let buffer = LogBuffer::new();
+        let suppressed = LogEntry::Suppressed(Suppressed { lines: 1, bytes: 1 });
+        for i in 0..10 {
+            buffer.push(line(i), None);
+        }
+        buffer.push(suppressed.clone(), None);
+        buffer.push(LogEntry::Restart(1), None);
+        for i in 10..15 {
+            buffer.push(line(i), None);
+        }
+        buffer.push(suppressed, None);
+
+        // Lines are numbered and counted back from the end without the markers of suppressed lines
+        let start = buffer.start(LogStart::Offset(13));
+        assert_eq!((start.run, start.number), (1, 11));
+        let start = buffer.start(LogStart::Tail(6));
+        assert_eq!((start.pos, start.run, start.number), (9, 0, 9));
+        let start = buffer.start(LogStart::Tail(0));
+        assert_eq!((start.pos, start.run, start.number), (18, 1, 15));
+    }
+
+    #[test]
+    fn test_buffer_evict() {
+        let buffer = LogBuffer::new();
+        let start = SystemTime::now();
+        for i in 0..100 {
+            buffer.push(line(i), None);
+        }
+        let mut cursor = buffer.cursor_at(0).unwrap();
+        buffer.push(LogEntry::Restart(1), None);
+        buffer.evict();
+
+        assert!(buffer.cursor_at(0).is_none());
+        // Cursors keep reading the entries
+        assert_eq!(cursor.read_available().len(), 101);
+        assert_eq!(buffer.len(), 101);
+        assert_eq!(pos_run(&buffer, LogStart::Tail(0)), (101, 1));
+        assert_eq!(pos_run(&buffer, LogStart::Offset(50)), (50, 0));
+        // Times are only known for the first entries of the chunks, of 64 and 128 entries
+        assert_eq!(pos_run(&buffer, LogStart::Since(start)), (0, 0));
+        let later = SystemTime::now() + std::time::Duration::from_secs(1);
+        assert_eq!(pos_run(&buffer, LogStart::Since(later)), (64, 0));
+
+        // Entries pushed afterwards are dropped
+        buffer.push(line(101), None);
+        buffer.push(LogEntry::Restart(2), None);
+        assert_eq!(buffer.len(), 101);
+        assert_eq!(pos_run(&buffer, LogStart::Tail(0)), (101, 1));
+        assert!(cursor.read_available().is_empty());
+        buffer.evict();
+        assert_eq!(pos_run(&buffer, LogStart::Since(later)), (64, 0));
+    }
+
+    #[test]
+    fn test_buffer_concurrent() {
+        let buffer = Arc::new(LogBuffer::new());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let buffer = buffer.clone();
+                std::thread::spawn(move || {
+                    let mut cursor = buffer.cursor_at(0).unwrap();
+                    let mut read = 0;
+                    while read < 100_000 {
+                        if let Some(entry) = cursor.next() {
+                            assert_eq!(*entry, line(read));
+                            read += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for i in 0..100_000 {
+            buffer.push(line(i), None);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::{fmt, process::ExitStatus, sync::Arc};
+
+/// A change in the lifecycle of a process.
+/// Runs are numbered from 0, and incremented on every restart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProcessEvent {
+    /// The process was spawned, with the given PID.
+    Spawned { pid: u32 },
+    /// The stdout and stderr of the run are being captured.
+    StdoutOpened { run: u32 },
+    /// The run is being stopped, on request or for being unhealthy.
+    Stopping { run: u32 },
+    /// The run exited.
+    Exited { run: u32, status: ExitStatus },
+    /// The process was restarted, with the given PID.
+    Restarted { run: u32, pid: u32 },
+    /// The process has finished for good, with the exit status of its last run.
+    Reaped(ExitStatus),
+}
+
+/// Receives the events of a process, e.g. to publish them on a channel.
+/// It is called from the task of the process, so it should not block.
+#[derive(Clone)]
+pub struct EventSink(Arc<dyn Fn(ProcessEvent) + Send + Sync>);
+
+impl EventSink {
+    pub fn new(f: impl Fn(ProcessEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub(crate) fn send(&self, event: ProcessEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
+/// Sinks are equal if they are clones of each other.
+impl PartialEq for EventSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for EventSink {}
@@ -1,11 +1,17 @@
 use std::{
     io,
     process::{ExitStatus, Stdio},
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
 };
 
-use bytes::Bytes;
-use futures::{future::FusedFuture, FutureExt, Stream};
+use futures::{
+    future::{Fuse, FusedFuture},
+    pin_mut, Future, FutureExt, Stream,
+};
 use log::error;
 use tokio::{
     io::BufReader,
@@ -13,28 +19,138 @@ use tokio::{
     sync::{oneshot, Notify},
 };
 
+mod buffer;
+mod event;
 mod logs;
 mod ops;
+mod priority;
+mod probe;
+mod restart;
+
+pub use event::{EventSink, ProcessEvent};
+pub use logs::{
+    BatchLimits, EntryMetadata, LineMetadata, LogBatch, LogBudget, LogEntry, LogFilter, LogQuery,
+    LogRateLimit, LogStart, LogsEvicted, Output, Suppressed,
+};
+pub use priority::{create_cgroup, Priority};
+pub use probe::{Health, Probe, ProbeCheck, Probes};
+pub use restart::{RestartMode, RestartPolicy};
+
+/// Settings of a spawned process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpawnOptions {
+    pub priority: Priority,
+    pub restart: RestartPolicy,
+    pub probes: Probes,
+    pub log_rate_limit: LogRateLimit,
+    /// The budget the logs of the process are held in memory within.
+    pub log_budget: Option<LogBudget>,
+    /// Receives the lifecycle events of the process.
+    pub events: Option<EventSink>,
+}
+
+/// The command line and settings a process is (re)started with.
+struct Spec {
+    argv0: String,
+    args: Vec<String>,
+    options: SpawnOptions,
+}
+
+impl Spec {
+    fn spawn(&self) -> Result<Child, io::Error> {
+        let mut command = Command::new(&self.argv0);
+        command
+            .args(&self.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        priority::apply(&mut command, &self.options.priority)?;
+        command.spawn()
+    }
+}
 
 async fn process_task(
     mut child: Child,
+    spec: Spec,
     inner: Arc<ProcessInner>,
     stop_receiver: oneshot::Receiver<()>,
 ) {
+    // Fuse the future to be able to .await twice safely (when !is_terminated()).
+    let mut stop_receiver = stop_receiver.fuse();
+    let policy = &spec.options.restart;
+    let mut restarts = 0;
+    loop {
+        let unhealthy = probe::monitor(&spec.options.probes, &inner);
+        let (status, killed_unhealthy) =
+            run_task(&mut child, &inner, &mut stop_receiver, unhealthy).await;
+        inner.runs.write().unwrap().push(status);
+        inner.emit(ProcessEvent::Exited {
+            run: restarts,
+            status,
+        });
+        let stopped = inner.stop_sender.lock().unwrap().is_none();
+        let restart = match killed_unhealthy {
+            true => policy.within_limit(restarts),
+            false => policy.should_restart(status, restarts),
+        };
+        if stopped || !restart {
+            inner.finish(status).await;
+            inner.progress.notify_waiters();
+            return;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(policy.backoff(restarts)) => {},
+            _ = &mut stop_receiver => {
+                inner.finish(status).await;
+                inner.progress.notify_waiters();
+                return;
+            },
+        }
+        child = match spec.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("could not restart process: {:?}", e);
+                inner.finish(status).await;
+                inner.progress.notify_waiters();
+                return;
+            }
+        };
+        restarts += 1;
+        inner.restarts.store(restarts, Ordering::Relaxed);
+        inner.emit(ProcessEvent::Restarted {
+            run: restarts,
+            pid: child.id().unwrap_or_default(),
+        });
+        inner.push_log(LogEntry::Restart(restarts));
+    }
+}
+
+/// Runs a single child process until it exits.
+/// The process is stopped once `unhealthy` completes,
+/// in which case `true` is returned along with the exit status.
+async fn run_task(
+    child: &mut Child,
+    inner: &Arc<ProcessInner>,
+    stop_receiver: &mut Fuse<oneshot::Receiver<()>>,
+    unhealthy: impl Future<Output = ()>,
+) -> (ExitStatus, bool) {
     // A child process might close both stdout and stderr,
     // but remain alive. In that case, we must still try to wait
     // for the stop message.
-    // Fuse the future to be able to .await twice safely (when !is_terminated()).
-    let mut stop_receiver = stop_receiver.fuse();
+    let unhealthy = unhealthy.fuse();
+    pin_mut!(unhealthy);
 
     let stdout = BufReader::new(child.stdout.take().expect("should always be available"));
     let stderr = BufReader::new(child.stderr.take().expect("should always be available"));
+    inner.emit(ProcessEvent::StdoutOpened {
+        run: inner.restarts.load(Ordering::Relaxed),
+    });
 
     // Phase 1: copy logs from stdout/stderr, on stop message: signal the child.
     tokio::select! {
         copied = futures::future::join(
-            logs::copy(stdout, inner.clone()),
-            logs::copy(stderr, inner.clone())
+            logs::copy(stdout, inner.clone(), Output::Stdout),
+            logs::copy(stderr, inner.clone(), Output::Stderr)
         ) => {
             if let Err(e) = copied.0 {
                 error!("{:?}", e);
@@ -43,30 +159,34 @@ async fn process_task(
                 error!("{:?}", e);
             }
         },
-        _ = &mut stop_receiver => {
-            if let Err(e) = ops::stop_child(&mut child).await {
-                error!("{:?}", e);
-            }
+        _ = &mut *stop_receiver, if !stop_receiver.is_terminated() => {
+            stop_child(child, inner).await;
+        },
+        _ = &mut unhealthy => {
+            stop_child(child, inner).await;
         },
     };
 
+    // Lines suppressed at the end of the run are marked before the logs of the next run.
+    if let Some(suppressed) = inner.log_limiter.as_ref().and_then(|l| l.take_suppressed()) {
+        logs::mark_suppressed(inner, suppressed);
+        inner.progress.notify_waiters();
+    }
+
     // Phase 2: stdout/stderr have been closed,
     // wait for a stop message to arrive (if not arrived yet),
     // or on the child to finish otherwise
     loop {
         tokio::select! {
-            _ = &mut stop_receiver, if !stop_receiver.is_terminated() => {
-                if let Err(e) = ops::stop_child(&mut child).await {
-                    error!("{:?}", e);
-                }
+            _ = &mut *stop_receiver, if !stop_receiver.is_terminated() => {
+                stop_child(child, inner).await;
+            },
+            _ = &mut unhealthy, if !unhealthy.is_terminated() => {
+                stop_child(child, inner).await;
             },
             res = child.wait() => {
                 match res {
-                    Ok(s) => {
-                        inner.finish(s).await;
-                        inner.progress.notify_waiters();
-                        return;
-                    }
+                    Ok(s) => return (s, unhealthy.is_terminated()),
                     Err(e) => panic!("Unexpected error from wait(): {:?}", e),
                 }
             }
@@ -74,47 +194,129 @@ async fn process_task(
     }
 }
 
+async fn stop_child(child: &mut Child, inner: &ProcessInner) {
+    inner.emit(ProcessEvent::Stopping {
+        run: inner.restarts.load(Ordering::Relaxed),
+    });
+    if let Err(e) = ops::stop_child(child).await {
+        error!("{:?}", e);
+    }
+}
+
 pub(crate) struct ProcessInner {
+    // When the first run of the process was spawned.
+    spawned: Instant,
     exit_status: RwLock<Option<ExitStatus>>,
-    logs: RwLock<Vec<Bytes>>,
+    // Exit statuses of the runs of the process that have finished.
+    runs: RwLock<Vec<ExitStatus>>,
+    restarts: AtomicU32,
+    probe_status: RwLock<probe::ProbeStatus>,
+    logs: buffer::LogBuffer,
+    // Total size of the lines in `logs`, in bytes, until they are evicted.
+    log_bytes: AtomicUsize,
+    log_limiter: Option<logs::LogLimiter>,
+    log_budget: Option<LogBudget>,
+    // Number and total size of the lines suppressed by the rate limit or the log budget.
+    suppressed_lines: AtomicUsize,
+    suppressed_bytes: AtomicUsize,
 
     // Signals the listeners about progress being made by the process
     // (either new log messages or finishing).
     progress: Arc<Notify>,
 
     stop_sender: Mutex<Option<oneshot::Sender<()>>>,
+    events: Option<EventSink>,
 }
 
 impl ProcessInner {
-    fn new(stop_sender: oneshot::Sender<()>) -> Self {
+    fn new(stop_sender: oneshot::Sender<()>, options: &SpawnOptions) -> Self {
         Self {
+            spawned: Instant::now(),
             exit_status: Default::default(),
-            logs: Default::default(),
+            runs: Default::default(),
+            restarts: Default::default(),
+            probe_status: Default::default(),
+            logs: buffer::LogBuffer::new(),
+            log_bytes: Default::default(),
+            log_limiter: Some(options.log_rate_limit)
+                .filter(|limit| !limit.is_unlimited())
+                .map(logs::LogLimiter::new),
+            log_budget: options.log_budget.clone(),
+            suppressed_lines: Default::default(),
+            suppressed_bytes: Default::default(),
             progress: Default::default(),
             stop_sender: Mutex::new(Some(stop_sender)),
+            events: options.events.clone(),
         }
     }
     async fn finish(&self, exit_status: ExitStatus) {
         *self.exit_status.write().unwrap() = Some(exit_status);
+        self.emit(ProcessEvent::Reaped(exit_status));
+    }
+
+    fn emit(&self, event: ProcessEvent) {
+        if let Some(events) = &self.events {
+            events.send(event);
+        }
+    }
+
+    fn push_log(&self, entry: LogEntry) {
+        self.logs.push(entry, None);
+        self.progress.notify_waiters();
+    }
+
+    /// Evicts the logs, returning their bytes to the budget.
+    fn evict_logs(&self) {
+        self.logs.evict();
+        let bytes = self.log_bytes.swap(0, Ordering::Relaxed);
+        if let Some(budget) = &self.log_budget {
+            budget.release(bytes);
+        }
+    }
+}
+
+impl Drop for ProcessInner {
+    /// The logs of a process are released from the budget along with the process.
+    fn drop(&mut self) {
+        if let Some(budget) = &self.log_budget {
+            budget.release(*self.log_bytes.get_mut());
+        }
     }
 }
 
 /// Represents a single process.
+/// Clones refer to the same process.
+#[derive(Clone)]
 pub struct Process(Arc<ProcessInner>);
 
 impl Process {
     /// Spawn a new process.
     pub fn spawn<'a>(argv0: &str, argv: impl Iterator<Item = &'a str>) -> Result<Self, io::Error> {
+        Self::spawn_with(argv0, argv, &SpawnOptions::default())
+    }
+
+    /// Spawn a new process with the given options.
+    /// Errors restarting the process are logged,
+    /// and the process is then considered finished.
+    pub fn spawn_with<'a>(
+        argv0: &str,
+        argv: impl Iterator<Item = &'a str>,
+        options: &SpawnOptions,
+    ) -> Result<Self, io::Error> {
         let (stop_tx, stop_rx) = oneshot::channel();
-        let inner = Arc::new(ProcessInner::new(stop_tx));
+        let inner = Arc::new(ProcessInner::new(stop_tx, options));
         let inner_clone = inner.clone();
-        let child = Command::new(argv0)
-            .args(argv)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
+        let spec = Spec {
+            argv0: argv0.into(),
+            args: argv.map(Into::into).collect(),
+            options: options.clone(),
+        };
+        let child = spec.spawn()?;
+        inner.emit(ProcessEvent::Spawned {
+            pid: child.id().unwrap_or_default(),
+        });
 
-        tokio::spawn(process_task(child, inner, stop_rx));
+        tokio::spawn(process_task(child, spec, inner, stop_rx));
 
         Ok(Process(inner_clone))
     }
@@ -132,37 +334,129 @@ impl Process {
         let tx = self.0.stop_sender.lock().unwrap().take();
         match tx {
             Some(tx) => {
-                let notify = self.0.progress.clone();
-
                 // Ignore error: if receiver has hung up, process has already finished.
                 let _ = tx.send(());
 
-                loop {
-                    let notified = notify.notified();
-                    if let Some(e) = *self.0.exit_status.read().unwrap() {
-                        return Ok(e);
-                    }
-                    notified.await;
-                }
+                Ok(self.wait().await)
             }
             None => Err(()),
         }
     }
 
+    /// Waits for the process to finish and returns its exit status.
+    pub async fn wait(&self) -> ExitStatus {
+        loop {
+            let notified = self.0.progress.notified();
+            if let Some(e) = *self.0.exit_status.read().unwrap() {
+                return e;
+            }
+            notified.await;
+        }
+    }
+
     /// Returns a stream which yields stdout and stderr logs.
-    /// Each stream item is a single line.
+    /// Each stream item is a single line, or a marker of a restart.
     /// Each invocation of `logs()` returns an independent stream
     /// that returns a copy of the logs.
     ///
     /// When the stream returns, the process has finished
     /// and it is guaranteed that subsequent calls to `Process::status()`
     /// will return `Some(ExitStatus)`.
-    pub fn logs(&self) -> impl Stream<Item = Bytes> {
+    /// The stream is empty if the logs have been evicted.
+    pub fn logs(&self) -> impl Stream<Item = LogEntry> {
         logs::stream(self.0.clone())
     }
 
+    /// Returns a stream of the logs selected by the query,
+    /// with the lines coalesced into batches within the given limits,
+    /// for sending them over the network.
+    pub fn log_batches(
+        &self,
+        query: LogQuery,
+        limits: BatchLimits,
+    ) -> Result<impl Stream<Item = LogBatch>, LogsEvicted> {
+        logs::batches(self.0.clone(), query, limits)
+    }
+
+    /// Returns a stream of the logs like `log_batches()`, once they have been evicted,
+    /// reading them from where they have been stored.
+    /// `entries` must return every entry of the logs in order, restart markers included,
+    /// along with the metadata of the lines if it was stored.
+    pub fn stored_log_batches<S>(
+        &self,
+        entries: S,
+        query: LogQuery,
+        limits: BatchLimits,
+    ) -> impl Stream<Item = Result<LogBatch, io::Error>>
+    where
+        S: Stream<Item = Result<(LogEntry, Option<EntryMetadata>), io::Error>>,
+    {
+        logs::stored_batches(self.0.clone(), entries, query, limits)
+    }
+
+    /// Evicts the logs from memory once the process has finished, e.g. once they have been stored.
+    /// Streams of the logs that have been created keep reading them.
+    /// Returns whether they have been evicted.
+    pub fn evict_logs(&self) -> bool {
+        if self.0.exit_status.read().unwrap().is_none() {
+            return false;
+        }
+        self.0.evict_logs();
+        true
+    }
+
+    /// Returns the total size of the log lines held in memory, in bytes,
+    /// which is 0 once they have been evicted.
+    pub fn log_bytes(&self) -> usize {
+        self.0.log_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of log lines suppressed by the rate limit or the log budget so far.
+    pub fn suppressed_log_lines(&self) -> usize {
+        self.0.suppressed_lines.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total size of the log lines suppressed by the rate limit or the log budget so far,
+    /// in bytes.
+    pub fn suppressed_log_bytes(&self) -> usize {
+        self.0.suppressed_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the exit statuses of the finished runs of the process, in order.
+    /// There is more than one run if the process has been restarted.
+    pub fn runs(&self) -> Vec<ExitStatus> {
+        self.0.runs.read().unwrap().clone()
+    }
+
+    /// Returns when the process was spawned, before any restarts.
+    pub fn spawned_at(&self) -> Instant {
+        self.0.spawned
+    }
+
+    /// Returns the number of times the process has been restarted.
+    pub fn restarts(&self) -> u32 {
+        self.0.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Returns the result of the health probe of the current (or last) run,
+    /// or `None` if the process has no health probe.
+    pub fn health(&self) -> Option<Health> {
+        self.0.probe_status.read().unwrap().health
+    }
+
+    /// Returns whether the current run is ready according to the readiness probe,
+    /// or `None` if the process has no readiness probe. Finished processes are not ready.
+    pub fn ready(&self) -> Option<bool> {
+        let ready = self.0.probe_status.read().unwrap().ready;
+        match *self.0.exit_status.read().unwrap() {
+            Some(_) => ready.map(|_| false),
+            None => ready,
+        }
+    }
+
     /// Gets the `ExitStatus` of the process.
-    /// If `None` is returned, the process has not yet finished.
+    /// If `None` is returned, the process has not yet finished,
+    /// or is going to be restarted.
     pub async fn status(&self) -> Option<ExitStatus> {
         *self.0.exit_status.read().unwrap()
     }
@@ -170,14 +464,27 @@ impl Process {
 
 #[cfg(test)]
 mod test {
-    use std::{os::unix::process::ExitStatusExt, time::Duration};
+    use std::{
+        os::unix::process::ExitStatusExt,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
 
+    use bytes::Bytes;
     use futures::{pin_mut, StreamExt};
 
-    use super::Process;
+    use super::{
+        BatchLimits, EventSink, Health, LogBatch, LogBudget, LogEntry, LogFilter, LogQuery,
+        LogRateLimit, LogStart, Output, Priority, Probe, ProbeCheck, Probes, Process, ProcessEvent,
+        RestartMode, RestartPolicy, SpawnOptions, Suppressed,
+    };
 
     fn empty_args() -> impl Iterator<Item = &'static str> {
-        (&mut []).iter().cloned()
+        std::iter::empty()
+    }
+
+    fn line(l: &'static str) -> Option<LogEntry> {
+        Some(LogEntry::Line(l.into()))
     }
 
     #[should_panic]
@@ -191,9 +498,555 @@ mod test {
         let p = Process::spawn("echo", ["foo"].iter().cloned()).unwrap();
         let logs = p.logs();
         pin_mut!(logs);
-        assert_eq!(logs.next().await.as_deref(), Some(&b"foo"[..]));
+        assert_eq!(logs.next().await, line("foo"));
         assert_eq!(logs.next().await, None);
         assert_eq!(p.status().await.unwrap().code(), Some(0));
+        assert_eq!(p.log_bytes(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_process_priority() {
+        let options = SpawnOptions {
+            priority: Priority {
+                nice: Some(7),
+                cgroup: None,
+            },
+            ..Default::default()
+        };
+        // `nice` without arguments prints the current niceness
+        let p = Process::spawn_with("nice", empty_args(), &options).unwrap();
+        let logs = p.logs();
+        pin_mut!(logs);
+        assert_eq!(logs.next().await, line("7"));
+
+        let options = SpawnOptions {
+            priority: Priority {
+                nice: None,
+                cgroup: Some("/this/cgroup/does/not/exist".into()),
+            },
+            ..Default::default()
+        };
+        assert!(Process::spawn_with("true", empty_args(), &options).is_err());
+    }
+
+    fn restart_options(mode: RestartMode, max_restarts: Option<u32>) -> SpawnOptions {
+        SpawnOptions {
+            restart: RestartPolicy {
+                mode,
+                max_restarts,
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_restart_on_failure() {
+        let options = restart_options(RestartMode::OnFailure, Some(2));
+        let p = Process::spawn_with("bash", ["-c", "echo run; exit 1"].iter().cloned(), &options)
+            .unwrap();
+        let logs = p.logs();
+        pin_mut!(logs);
+        assert_eq!(logs.next().await, line("run"));
+        assert_eq!(logs.next().await, Some(LogEntry::Restart(1)));
+        assert_eq!(logs.next().await, line("run"));
+        assert_eq!(logs.next().await, Some(LogEntry::Restart(2)));
+        assert_eq!(logs.next().await, line("run"));
+        assert_eq!(logs.next().await, None);
+        assert_eq!(p.status().await.unwrap().code(), Some(1));
+        assert_eq!(p.runs().len(), 3);
+        assert_eq!(p.restarts(), 2);
+
+        // Successful runs are not restarted
+        let p = Process::spawn_with("true", empty_args(), &options).unwrap();
+        assert_eq!(p.wait().await.code(), Some(0));
+        assert_eq!(p.runs().len(), 1);
+    }
+
+    fn follow() -> LogQuery {
+        LogQuery {
+            follow: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_log_batches() {
+        let limits = BatchLimits {
+            max_bytes: 64,
+            max_delay: Duration::from_millis(50),
+        };
+        let script = "for i in $(seq 1 100); do echo line$i; done";
+        let p = Process::spawn("bash", ["-c", script].iter().cloned()).unwrap();
+        let batches: Vec<LogBatch> = p.log_batches(follow(), limits).unwrap().collect().await;
+        assert!(batches.len() < 100);
+        for batch in &batches {
+            assert!(batch.lines.iter().map(|l| l.len()).sum::<usize>() <= 64);
+        }
+        let lines: Vec<_> = batches.into_iter().flat_map(|b| b.lines).collect();
+        let expected: Vec<_> = (1..=100).map(|i| format!("line{}", i)).collect();
+        assert_eq!(lines, expected);
+
+        // Lines are not held back for longer than the delay
+        let script = "echo a; sleep 0.2; echo b";
+        let p = Process::spawn("bash", ["-c", script].iter().cloned()).unwrap();
+        let batches = p.log_batches(follow(), limits).unwrap();
+        pin_mut!(batches);
+        let first = tokio::time::timeout(Duration::from_millis(150), batches.next()).await;
+        assert_eq!(first.unwrap().unwrap().lines, vec![Bytes::from("a")]);
+        assert_eq!(batches.next().await.unwrap().lines, vec![Bytes::from("b")]);
+        assert_eq!(batches.next().await, None);
+
+        // Snapshots end with the lines captured so far
+        let p = Process::spawn("bash", ["-c", "echo a; sleep 10"].iter().cloned()).unwrap();
+        let batches = p.log_batches(follow(), limits).unwrap();
+        pin_mut!(batches);
+        assert_eq!(batches.next().await.unwrap().lines, vec![Bytes::from("a")]);
+        let batches: Vec<LogBatch> = p
+            .log_batches(LogQuery::default(), limits)
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].lines, vec![Bytes::from("a")]);
+        assert!(p.status().await.is_none());
+        p.stop().await.unwrap();
+
+        let options = restart_options(RestartMode::OnFailure, Some(1));
+        let p = Process::spawn_with("bash", ["-c", "echo run; exit 1"].iter().cloned(), &options)
+            .unwrap();
+        let batches: Vec<LogBatch> = p.log_batches(follow(), limits).unwrap().collect().await;
+        let batch = |run, lines: &[&'static str], cursor| LogBatch {
+            run,
+            lines: lines.iter().map(|&l| l.into()).collect(),
+            cursor,
+            ..Default::default()
+        };
+        assert_eq!(
+            batches,
+            vec![
+                batch(0, &["run"], 1),
+                batch(1, &[], 2),
+                batch(1, &["run"], 3)
+            ]
+        );
+
+        // Streams resume from the cursor of a batch
+        for &start in &[LogStart::Offset(2), LogStart::Tail(1)] {
+            let query = LogQuery { start, ..follow() };
+            let batches: Vec<LogBatch> = p.log_batches(query, limits).unwrap().collect().await;
+            assert_eq!(batches, vec![batch(1, &["run"], 3)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_log_filter() {
+        let p = Process::spawn("seq", ["1", "20"].iter().cloned()).unwrap();
+        p.wait().await;
+        let filtered = |regex: &str, invert, before, after| {
+            let query = LogQuery {
+                filter: Some(LogFilter {
+                    regex: regex.parse().unwrap(),
+                    invert,
+                    before,
+                    after,
+                }),
+                ..Default::default()
+            };
+            p.log_batches(query, BatchLimits::default())
+                .unwrap()
+                .flat_map(|b| futures::stream::iter(b.lines.into_iter().zip(b.line_numbers)))
+                .map(|(line, number)| (String::from_utf8(line.to_vec()).unwrap(), number))
+                .collect::<Vec<_>>()
+        };
+        let numbered =
+            |numbers: &[usize]| -> Vec<_> { numbers.iter().map(|&n| (n.to_string(), n)).collect() };
+
+        assert_eq!(
+            filtered("^1", false, 0, 0).await,
+            numbered(&[1, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19])
+        );
+        assert_eq!(
+            filtered("^.$", true, 0, 0).await,
+            numbered(&(10..=20).collect::<Vec<_>>())
+        );
+        // Context lines are included once, even if they overlap
+        assert_eq!(
+            filtered("5$", false, 1, 1).await,
+            numbered(&[4, 5, 6, 14, 15, 16])
+        );
+        assert_eq!(
+            filtered("^1[02]$", false, 1, 2).await,
+            numbered(&[9, 10, 11, 12, 13, 14])
+        );
+        assert!(filtered("x", false, 3, 3).await.is_empty());
+
+        // Lines are numbered across restarts
+        let options = restart_options(RestartMode::OnFailure, Some(1));
+        let p = Process::spawn_with(
+            "bash",
+            ["-c", "echo a; echo b; exit 1"].iter().cloned(),
+            &options,
+        )
+        .unwrap();
+        p.wait().await;
+        let query = LogQuery {
+            filter: Some(LogFilter {
+                regex: "b".parse().unwrap(),
+                invert: false,
+                before: 0,
+                after: 0,
+            }),
+            ..Default::default()
+        };
+        let batches: Vec<LogBatch> = p
+            .log_batches(query, BatchLimits::default())
+            .unwrap()
+            .collect()
+            .await;
+        let batch = |run, lines: Vec<Bytes>, line_numbers, cursor| LogBatch {
+            run,
+            lines,
+            line_numbers,
+            cursor,
+            ..Default::default()
+        };
+        assert_eq!(
+            batches,
+            vec![
+                batch(0, vec!["b".into()], vec![2], 2),
+                batch(1, vec![], vec![], 3),
+                batch(1, vec!["b".into()], vec![4], 5),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_log_metadata() {
+        let start = SystemTime::now();
+        let p = Process::spawn("bash", ["-c", "echo out; echo err >&2"].iter().cloned()).unwrap();
+        p.wait().await;
+        let query = LogQuery {
+            metadata: true,
+            ..Default::default()
+        };
+        let batches: Vec<LogBatch> = p
+            .log_batches(query, BatchLimits::default())
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.line_numbers, vec![1, 2]);
+        assert_eq!(batch.metadata.len(), 2);
+        for (line, metadata) in batch.lines.iter().zip(&batch.metadata) {
+            let output = match &line[..] {
+                b"out" => Output::Stdout,
+                _ => Output::Stderr,
+            };
+            assert_eq!(metadata.output, output);
+            assert!(start <= metadata.time && metadata.time <= SystemTime::now());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_log_rate_limit() {
+        let options = SpawnOptions {
+            log_rate_limit: LogRateLimit {
+                lines_per_sec: Some(10),
+                bytes_per_sec: None,
+            },
+            ..Default::default()
+        };
+        let p = Process::spawn_with("seq", ["1", "1000"].iter().cloned(), &options).unwrap();
+        p.wait().await;
+        let entries: Vec<LogEntry> = p.logs().collect().await;
+        // The first lines are logged, and the others are marked as suppressed
+        let mut logged = vec![];
+        let mut marked = Suppressed::default();
+        for entry in &entries {
+            match entry {
+                LogEntry::Line(l) => logged.push(String::from_utf8(l.to_vec()).unwrap()),
+                LogEntry::Suppressed(s) => {
+                    marked.lines += s.lines;
+                    marked.bytes += s.bytes;
+                }
+                LogEntry::Restart(_) => unreachable!(),
+            }
+        }
+        assert_eq!(
+            logged[..10],
+            (1..=10).map(|i| i.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(logged.len() + p.suppressed_log_lines(), 1000);
+        assert_eq!(marked.lines, p.suppressed_log_lines());
+        assert_eq!(marked.bytes, p.suppressed_log_bytes());
+        assert!(matches!(entries.last(), Some(LogEntry::Suppressed(_))));
+        // The markers are not lines of the logs
+        let bytes: usize = logged.iter().map(String::len).sum();
+        assert_eq!(p.log_bytes(), bytes);
+        let batches: Vec<LogBatch> = p
+            .log_batches(
+                LogQuery {
+                    metadata: true,
+                    ..Default::default()
+                },
+                BatchLimits::default(),
+            )
+            .unwrap()
+            .collect()
+            .await;
+        let numbers: Vec<usize> = batches
+            .iter()
+            .flat_map(|b| b.line_numbers.clone())
+            .collect();
+        assert_eq!(numbers, (1..=logged.len()).collect::<Vec<_>>());
+        let last = batches.last().unwrap();
+        assert!(last.suppressed.is_some() && !last.starts_run());
+        let marked_at = last.marker_time.unwrap();
+        assert!(batches[0].metadata[0].time <= marked_at && marked_at <= SystemTime::now());
+        assert_eq!(last.cursor, entries.len());
+    }
+
+    #[tokio::test]
+    async fn test_process_log_rate_limit_marked_in_time() {
+        let options = SpawnOptions {
+            log_rate_limit: LogRateLimit {
+                lines_per_sec: Some(10),
+                bytes_per_sec: None,
+            },
+            ..Default::default()
+        };
+        let script = "seq 1 100; sleep 3";
+        let p = Process::spawn_with("bash", ["-c", script].iter().cloned(), &options).unwrap();
+        // The lines suppressed are marked once the second is over, while the process still runs
+        let logs = p.logs();
+        pin_mut!(logs);
+        let marked = tokio::time::timeout(Duration::from_secs(2), async {
+            while let Some(entry) = logs.next().await {
+                if let LogEntry::Suppressed(suppressed) = entry {
+                    return suppressed;
+                }
+            }
+            unreachable!("the process logs for 3 seconds");
+        })
+        .await
+        .unwrap();
+        assert_eq!(marked.lines, 90);
+        p.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_process_log_budget() {
+        let budget = LogBudget::new(Some(20));
+        let options = SpawnOptions {
+            log_budget: Some(budget.clone()),
+            ..Default::default()
+        };
+        // 9 lines of 1 byte, then 5 lines of 2 bytes fit.
+        let p = Process::spawn_with("seq", ["1", "100"].iter().cloned(), &options).unwrap();
+        p.wait().await;
+        assert_eq!(p.logs().collect::<Vec<_>>().await.len(), 14);
+        assert_eq!(p.log_bytes(), 19);
+        assert_eq!(budget.used(), 19);
+        assert_eq!(p.suppressed_log_lines(), 86);
+
+        // The budget is shared, and freed once the logs are evicted.
+        let q = Process::spawn_with("seq", ["1", "100"].iter().cloned(), &options).unwrap();
+        q.wait().await;
+        assert_eq!(q.logs().collect::<Vec<_>>().await.len(), 1);
+        assert!(p.evict_logs());
+        assert_eq!(budget.used(), 1);
+        drop(q);
+        assert_eq!(budget.used(), 0);
+        let r = Process::spawn_with("seq", ["1", "100"].iter().cloned(), &options).unwrap();
+        r.wait().await;
+        assert_eq!(r.logs().collect::<Vec<_>>().await.len(), 14);
+    }
+
+    #[tokio::test]
+    async fn test_process_evict_logs() {
+        let options = restart_options(RestartMode::OnFailure, Some(1));
+        let script = "echo a; echo b; exit 1";
+        let p = Process::spawn_with("bash", ["-c", script].iter().cloned(), &options).unwrap();
+        assert!(!p.evict_logs());
+        p.wait().await;
+
+        let queries = vec![
+            LogQuery::default(),
+            LogQuery {
+                start: LogStart::Offset(3),
+                ..Default::default()
+            },
+            LogQuery {
+                start: LogStart::Tail(1),
+                ..Default::default()
+            },
+            LogQuery {
+                filter: Some(LogFilter {
+                    regex: "b".parse().unwrap(),
+                    invert: false,
+                    before: 1,
+                    after: 0,
+                }),
+                ..Default::default()
+            },
+        ];
+        let mut expected = vec![];
+        for query in &queries {
+            let batches: Vec<LogBatch> = p
+                .log_batches(query.clone(), BatchLimits::default())
+                .unwrap()
+                .collect()
+                .await;
+            expected.push(batches);
+        }
+
+        assert!(p.evict_logs());
+        assert!(p
+            .log_batches(LogQuery::default(), BatchLimits::default())
+            .is_err());
+        assert_eq!(p.logs().collect::<Vec<_>>().await, vec![]);
+        for (query, expected) in queries.into_iter().zip(expected) {
+            let line = |l: &'static str| LogEntry::Line(l.into());
+            let stored = vec![
+                line("a"),
+                line("b"),
+                LogEntry::Restart(1),
+                line("a"),
+                line("b"),
+            ]
+            .into_iter()
+            .map(|e| Ok((e, None)));
+            let batches: Vec<LogBatch> = p
+                .stored_log_batches(futures::stream::iter(stored), query, BatchLimits::default())
+                .map(Result::unwrap)
+                .collect()
+                .await;
+            assert_eq!(batches, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_restart_stop() {
+        let options = restart_options(RestartMode::Always, None);
+        let p = Process::spawn_with("sleep", ["10"].iter().cloned(), &options).unwrap();
+        assert_eq!(
+            p.stop().await.unwrap().signal().unwrap(),
+            nix::sys::signal::Signal::SIGTERM as i32
+        );
+        // Stopped processes are not restarted
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(p.runs().len(), 1);
+    }
+
+    fn event_sink() -> (EventSink, Arc<Mutex<Vec<ProcessEvent>>>) {
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        let sink = EventSink::new(move |e| events_clone.lock().unwrap().push(e));
+        (sink, events)
+    }
+
+    #[tokio::test]
+    async fn test_process_events() {
+        let (sink, events) = event_sink();
+        let options = SpawnOptions {
+            events: Some(sink),
+            ..restart_options(RestartMode::OnFailure, Some(1))
+        };
+        let p = Process::spawn_with("bash", ["-c", "exit 1"].iter().cloned(), &options).unwrap();
+        let status = p.wait().await;
+        let events = events.lock().unwrap().clone();
+        assert_eq!(events.len(), 7);
+        assert!(matches!(events[0], ProcessEvent::Spawned { .. }));
+        assert_eq!(events[1], ProcessEvent::StdoutOpened { run: 0 });
+        assert_eq!(events[2], ProcessEvent::Exited { run: 0, status });
+        assert!(matches!(events[3], ProcessEvent::Restarted { run: 1, .. }));
+        assert_eq!(events[4], ProcessEvent::StdoutOpened { run: 1 });
+        assert_eq!(events[5], ProcessEvent::Exited { run: 1, status });
+        assert_eq!(events[6], ProcessEvent::Reaped(status));
+
+        let (sink, events) = event_sink();
+        let options = SpawnOptions {
+            events: Some(sink),
+            ..Default::default()
+        };
+        let p = Process::spawn_with("sleep", ["10"].iter().cloned(), &options).unwrap();
+        let status = p.stop().await.unwrap();
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events[1..],
+            [
+                ProcessEvent::StdoutOpened { run: 0 },
+                ProcessEvent::Stopping { run: 0 },
+                ProcessEvent::Exited { run: 0, status },
+                ProcessEvent::Reaped(status),
+            ]
+        );
+    }
+
+    fn probe(check: ProbeCheck) -> Option<Probe> {
+        Some(Probe {
+            interval: Duration::from_millis(50),
+            failure_threshold: 2,
+            ..Probe::new(check)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_process_readiness_probe() {
+        let options = SpawnOptions {
+            probes: Probes {
+                health: probe(ProbeCheck::Exec(vec!["true".into()])),
+                readiness: probe(ProbeCheck::LogLine("^ready$".parse().unwrap())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let script = "echo starting; sleep 0.3; echo ready; sleep 10";
+        let p = Process::spawn_with("bash", ["-c", script].iter().cloned(), &options).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(p.health(), Some(Health::Healthy));
+        assert_eq!(p.ready(), Some(false));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(p.ready(), Some(true));
+        p.stop().await.unwrap();
+        assert_eq!(p.ready(), Some(false));
+
+        let p = Process::spawn("true", empty_args()).unwrap();
+        assert_eq!(p.health(), None);
+        assert_eq!(p.ready(), None);
+    }
+
+    #[tokio::test]
+    async fn test_process_restart_unhealthy() {
+        let options = SpawnOptions {
+            probes: Probes {
+                health: probe(ProbeCheck::Tcp("127.0.0.1:1".into())),
+                restart_unhealthy: true,
+                ..Default::default()
+            },
+            ..restart_options(RestartMode::Never, Some(1))
+        };
+        let p = Process::spawn_with("sleep", ["10"].iter().cloned(), &options).unwrap();
+        let status = tokio::time::timeout(Duration::from_secs(5), p.wait())
+            .await
+            .expect("unhealthy process should have been killed");
+        assert_eq!(
+            status.signal().unwrap(),
+            nix::sys::signal::Signal::SIGTERM as i32
+        );
+        assert_eq!(p.restarts(), 1);
+        assert_eq!(p.health(), Some(Health::Unhealthy));
+    }
+
+    #[tokio::test]
+    async fn test_process_wait() {
+        let p = Process::spawn("bash", ["-c", "sleep 1; exit 3"].iter().cloned()).unwrap();
+        assert_eq!(p.status().await, None);
+        assert_eq!(p.wait().await.code(), Some(3));
+        assert_eq!(p.clone().status().await.unwrap().code(), Some(3));
     }
 
     #[tokio::test]
@@ -208,9 +1061,9 @@ mod test {
         let p = Process::spawn("bash", ["-c", script].iter().cloned()).unwrap();
         let logs = p.logs();
         pin_mut!(logs);
-        assert_eq!(logs.next().await.as_deref(), Some(&b"hello"[..]));
-        assert_eq!(logs.next().await.as_deref(), Some(&b"beautiful"[..]));
-        assert_eq!(logs.next().await.as_deref(), Some(&b"world"[..]));
+        assert_eq!(logs.next().await, line("hello"));
+        assert_eq!(logs.next().await, line("beautiful"));
+        assert_eq!(logs.next().await, line("world"));
         assert_eq!(logs.next().await, None);
     }
 
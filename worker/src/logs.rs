@@ -1,51 +1,499 @@
-use std::io::Error as IoError;
-use std::sync::Arc;
+use std::collections::{vec_deque, VecDeque};
+use std::convert::Infallible;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, SystemTime};
 
 use bytes::Bytes;
-use futures::Stream;
-use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use futures::{pin_mut, stream, Stream, StreamExt};
+use regex::bytes::Regex;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    time::{self, Instant},
+};
 
-use super::ProcessInner;
+use super::{
+    buffer::{Cursor, Start},
+    ProcessInner,
+};
 
-pub(crate) async fn copy<R: AsyncBufRead + Unpin>(
-    reader: R,
+/// Number of stored lines read before they are batched.
+const STORED_CHUNK: usize = 1024;
+
+/// Duration of the windows of the rate limit.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// An entry of the logs of a process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LogEntry {
+    /// A line of stdout or stderr, without the line terminator.
+    Line(Bytes),
+    /// The process has been restarted for the given time, e.g. `Restart(1)` for the first time.
+    /// Lines after this entry are output of the new run.
+    Restart(u32),
+    /// Lines were suppressed by the rate limit, since the previous such entry or the start of the run.
+    /// Unlike lines, these entries have no line number and do not count as bytes of logs.
+    Suppressed(Suppressed),
+}
+
+/// The output of a process that a line was written to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+/// When and where a line of the logs was captured.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LineMetadata {
+    pub time: SystemTime,
+    pub output: Output,
+}
+
+/// When an entry of the logs was logged, and where if it is a line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EntryMetadata {
+    pub time: SystemTime,
+    /// None for entries other than lines.
+    pub output: Option<Output>,
+}
+
+impl EntryMetadata {
+    /// Returns the metadata of the entry as a line, if it is one.
+    pub fn line(self) -> Option<LineMetadata> {
+        self.output.map(|output| LineMetadata {
+            time: self.time,
+            output,
+        })
+    }
+}
+
+/// Limits on how fast a process logs, over stdout and stderr together.
+/// Lines beyond the limits within a second are suppressed,
+/// and a `LogEntry::Suppressed` marking how many were is logged once the second is over.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LogRateLimit {
+    /// Lines per second, unlimited if not given.
+    pub lines_per_sec: Option<u64>,
+    /// Total size of the lines per second, in bytes, unlimited if not given.
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl LogRateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.lines_per_sec.is_none() && self.bytes_per_sec.is_none()
+    }
+}
+
+/// A number of bytes of logs that processes may hold in memory together,
+/// e.g. the processes of the same user.
+/// Lines that do not fit are dropped and counted as suppressed,
+/// and the bytes of logs are returned to the budget once they are evicted.
+/// Clones refer to the same budget.
+#[derive(Clone, Debug)]
+pub struct LogBudget {
+    used: Arc<AtomicU64>,
+    /// Unlimited if not given, in which case the budget only counts the bytes.
+    limit: Option<u64>,
+}
+
+impl LogBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            used: Default::default(),
+            limit,
+        }
+    }
+
+    /// Returns the bytes of logs held in memory by the processes of the budget.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Takes the bytes from the budget, if they fit.
+    pub(crate) fn reserve(&self, bytes: usize) -> bool {
+        let limit = self.limit.unwrap_or(u64::MAX);
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used + bytes as u64).filter(|&used| used <= limit)
+            })
+            .is_ok()
+    }
+
+    /// Returns the bytes to the budget.
+    pub(crate) fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Budgets are equal if they are clones of each other.
+impl PartialEq for LogBudget {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.used, &other.used)
+    }
+}
+
+impl Eq for LogBudget {}
+
+/// Number and total size of lines that were suppressed by the rate limit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Suppressed {
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// What the rate limit decided about a line.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Admission {
+    pub admitted: bool,
+    /// The lines suppressed in the previous window, which have yet to be marked,
+    /// if the line is the first one of a new window.
+    pub unmarked: Option<Suppressed>,
+    /// The end of the window, when the lines suppressed in it are to be marked,
+    /// if the line is the first one suppressed in it.
+    pub mark_at: Option<Instant>,
+}
+
+/// The lines logged in the current second.
+struct Window {
+    start: Instant,
+    lines: u64,
+    bytes: u64,
+    suppressed: Suppressed,
+}
+
+/// Enforces a rate limit over windows of a second.
+pub(crate) struct LogLimiter {
+    limit: LogRateLimit,
+    window: Mutex<Window>,
+}
+
+impl LogLimiter {
+    pub fn new(limit: LogRateLimit) -> Self {
+        Self {
+            limit,
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                lines: 0,
+                bytes: 0,
+                suppressed: Suppressed::default(),
+            }),
+        }
+    }
+
+    /// Decides whether a line of the given size is within the limit.
+    pub fn admit(&self, bytes: usize, now: Instant) -> Admission {
+        let mut window = self.window.lock().unwrap();
+        let mut admission = Admission::default();
+        if now.duration_since(window.start) >= WINDOW {
+            admission.unmarked = Some(mem::take(&mut window.suppressed)).filter(|s| s.lines > 0);
+            window.start = now;
+            window.lines = 0;
+            window.bytes = 0;
+        }
+        let within = |limit: Option<u64>, used: u64| limit.is_none_or(|limit| used <= limit);
+        admission.admitted = within(self.limit.lines_per_sec, window.lines + 1)
+            && within(self.limit.bytes_per_sec, window.bytes + bytes as u64);
+        if admission.admitted {
+            window.lines += 1;
+            window.bytes += bytes as u64;
+        } else {
+            if window.suppressed.lines == 0 {
+                admission.mark_at = Some(window.start + WINDOW);
+            }
+            window.suppressed.lines += 1;
+            window.suppressed.bytes += bytes;
+        }
+        admission
+    }
+
+    /// Returns the lines suppressed in the current window, which have yet to be marked.
+    pub fn take_suppressed(&self) -> Option<Suppressed> {
+        let mut window = self.window.lock().unwrap();
+        Some(mem::take(&mut window.suppressed)).filter(|s| s.lines > 0)
+    }
+
+    /// Marks the lines suppressed in the current window if it has ended by `now`.
+    /// The marker is logged while holding the window, so that it can not be logged
+    /// after the lines suppressed at the end of the run have been taken.
+    fn mark_ended(&self, process: &ProcessInner, now: Instant) {
+        let mut window = self.window.lock().unwrap();
+        if now.duration_since(window.start) < WINDOW || window.suppressed.lines == 0 {
+            return;
+        }
+        mark_suppressed(process, mem::take(&mut window.suppressed));
+    }
+}
+
+/// Logs the entry marking suppressed lines.
+pub(crate) fn mark_suppressed(process: &ProcessInner, suppressed: Suppressed) {
+    process.logs.push(LogEntry::Suppressed(suppressed), None);
+}
+
+/// Marks the lines suppressed in a window once it has ended,
+/// in case the process logs nothing afterwards.
+fn mark_at(process: &Arc<ProcessInner>, at: Instant) {
+    let process = Arc::downgrade(process);
+    tokio::spawn(async move {
+        time::sleep_until(at).await;
+        if let Some(process) = process.upgrade() {
+            if let Some(limiter) = &process.log_limiter {
+                limiter.mark_ended(&process, Instant::now());
+                process.progress.notify_waiters();
+            }
+        }
+    });
+}
+
+/// Copies the lines of stdout or stderr to the logs of the process,
+/// within its rate limit and log budget. Lines must be valid UTF-8.
+pub(crate) async fn copy<R: AsyncRead + Unpin>(
+    mut reader: BufReader<R>,
     process: Arc<ProcessInner>,
+    output: Output,
 ) -> Result<(), IoError> {
-    let mut lines = reader.lines();
-    // TODO: re-locks each line, not too efficient
-    while let Some(line) = lines.next_line().await? {
-        process.logs.write().unwrap().push(Bytes::from(line));
-        process.progress.notify_waiters();
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        if let Err(e) = std::str::from_utf8(&line) {
+            return Err(IoError::new(ErrorKind::InvalidData, e));
+        }
+        let admitted = match &process.log_limiter {
+            None => true,
+            Some(limiter) => {
+                let admission = limiter.admit(line.len(), Instant::now());
+                if let Some(suppressed) = admission.unmarked {
+                    mark_suppressed(&process, suppressed);
+                }
+                if let Some(at) = admission.mark_at {
+                    mark_at(&process, at);
+                }
+                admission.admitted
+            }
+        };
+        let admitted = admitted
+            && process
+                .log_budget
+                .as_ref()
+                .is_none_or(|budget| budget.reserve(line.len()));
+        if admitted {
+            process.log_bytes.fetch_add(line.len(), Ordering::Relaxed);
+            process
+                .logs
+                .push(LogEntry::Line(Bytes::copy_from_slice(&line)), Some(output));
+        } else {
+            process.suppressed_lines.fetch_add(1, Ordering::Relaxed);
+            process
+                .suppressed_bytes
+                .fetch_add(line.len(), Ordering::Relaxed);
+        }
+        // Followers are woken up once per read from the pipe rather than once per line:
+        // only when no complete line is left in the buffer.
+        if !reader.buffer().contains(&b'\n') {
+            process.progress.notify_waiters();
+        }
     }
-    Ok(())
 }
 
-pub(crate) fn stream(process: Arc<ProcessInner>) -> impl Stream<Item = Bytes> {
+/// Limits on the batches returned by `Process::log_batches`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BatchLimits {
+    /// Total size of the lines in a batch, in bytes.
+    /// A single line that is larger than this is sent in a batch of its own.
+    pub max_bytes: usize,
+    /// How long a batch that is not full waits for more lines before it is sent.
+    pub max_delay: Duration,
+}
+
+impl Default for BatchLimits {
+    /// 64 KiB and 5 milliseconds.
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024,
+            max_delay: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Where a stream of logs starts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogStart {
+    /// At a position in the logs, e.g. the cursor of the last batch received, to resume from it.
+    /// Positions past the end start at the end.
+    Offset(usize),
+    /// The given number of lines before the end.
+    Tail(usize),
+    /// At the first line or restart logged at or after the given time.
+    Since(SystemTime),
+}
+
+impl Default for LogStart {
+    /// The start of the logs.
+    fn default() -> Self {
+        LogStart::Offset(0)
+    }
+}
+
+/// Selects the lines of a stream of logs, like `grep`.
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    /// Lines matching the regex are selected.
+    pub regex: Regex,
+    /// Select the lines that do not match instead.
+    pub invert: bool,
+    /// Number of lines before each selected line that are included.
+    pub before: usize,
+    /// Number of lines after each selected line that are included.
+    pub after: usize,
+}
+
+/// Which logs a stream returns.
+#[derive(Clone, Debug, Default)]
+pub struct LogQuery {
+    pub start: LogStart,
+    /// Keep returning the logs as they are produced, until the process finishes.
+    /// Otherwise, the stream ends with the logs captured by the time it is created.
+    pub follow: bool,
+    /// Only return the selected lines, along with their line numbers.
+    pub filter: Option<LogFilter>,
+    /// Return the numbers and metadata of the lines, e.g. to export them.
+    pub metadata: bool,
+}
+
+/// A line of a stream, with its position in the logs and its number.
+type Numbered = (usize, usize, Bytes, Option<LineMetadata>);
+
+/// The state of a filter over the lines of a stream.
+struct Grep {
+    filter: Option<LogFilter>,
+    // Lines that have not been included yet, for the context of the next selected line.
+    lines: VecDeque<Numbered>,
+    // Number of lines after the last selected line that are still included.
+    after: usize,
+}
+
+impl Grep {
+    fn new(filter: Option<LogFilter>) -> Self {
+        Self {
+            filter,
+            lines: VecDeque::new(),
+            after: 0,
+        }
+    }
+
+    /// Returns the lines included when the next line is read:
+    /// the line and the context before it if it is selected or after a selected line, none otherwise.
+    fn push(&mut self, line: Numbered) -> vec_deque::Drain<'_, Numbered> {
+        let (selected, before, after) = match &self.filter {
+            None => (true, 0, 0),
+            Some(f) => (f.regex.is_match(&line.2) != f.invert, f.before, f.after),
+        };
+        self.lines.push_back(line);
+        if selected {
+            self.after = after;
+        } else if self.after > 0 {
+            self.after -= 1;
+        } else {
+            if self.lines.len() > before {
+                self.lines.pop_front();
+            }
+            return self.lines.drain(..0);
+        }
+        self.lines.drain(..)
+    }
+
+    /// Context does not extend over restarts.
+    fn restart(&mut self) {
+        self.lines.clear();
+        self.after = 0;
+    }
+}
+
+/// Lines of a single run of a process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LogBatch {
+    /// The run the lines belong to: 0 for the first run, incremented on every restart.
+    pub run: u32,
+    /// A batch without lines, nor suppressed lines, marks the start of a new run.
+    pub lines: Vec<Bytes>,
+    /// The numbers of the lines if the stream has a filter or asks for metadata, starting at 1
+    /// and counting the lines of every run.
+    pub line_numbers: Vec<usize>,
+    /// The metadata of the lines if the stream asks for it.
+    /// Lines read from where they are stored have metadata only if it was stored with them.
+    pub metadata: Vec<LineMetadata>,
+    /// The position in the logs after the batch, from which a stream resumes with `LogStart::Offset`.
+    /// Positions count lines, the starts of new runs and the markers of suppressed lines.
+    pub cursor: usize,
+    /// Lines suppressed by the rate limit after the lines of the batch, which ends the batch.
+    pub suppressed: Option<Suppressed>,
+    /// When the run started, or the lines were suppressed,
+    /// if the batch marks either and the stream asks for metadata.
+    pub marker_time: Option<SystemTime>,
+}
+
+impl LogBatch {
+    /// Whether the batch marks the start of a new run.
+    pub fn starts_run(&self) -> bool {
+        self.lines.is_empty() && self.suppressed.is_none()
+    }
+}
+
+/// The logs of a process have been evicted from memory, and must be read from where they are stored.
+#[derive(Debug, thiserror::Error)]
+#[error("The logs have been evicted from memory")]
+pub struct LogsEvicted;
+
+/// An entry of the logs, with its metadata if it is known.
+type Record = (LogEntry, Option<EntryMetadata>);
+
+/// Returns a stream of all the entries that are available whenever it is polled,
+/// starting at the cursor, which is at the given position.
+/// Unless it follows the logs, it ends with the entries appended by the time it is created.
+fn chunks(
+    process: Arc<ProcessInner>,
+    mut cursor: Cursor,
+    pos: usize,
+    follow: bool,
+) -> impl Stream<Item = Vec<Record>> {
     let notify = process.progress.clone();
-    let mut pos = 0;
+    let snapshot = match follow {
+        true => None,
+        false => Some(process.logs.len() - pos),
+    };
     async_stream::stream! {
+        if let Some(len) = snapshot {
+            let mut chunk = cursor.read_available();
+            chunk.truncate(len);
+            if !chunk.is_empty() {
+                yield chunk;
+            }
+            return;
+        }
         loop {
             let notified = notify.notified();
-            let line = {
-                // TODO: read multiple lines here for efficiency.
-                // We use `bytes::Bytes` and it is internally ref-counted,
-                // so perhaps clone `Bytes` objects to a stack buffer,
-                // unlock quickly, then yield each object outside of the lock.
-                let logs = process.logs.read().unwrap();
-                if pos < logs.len() {
-                    let line = logs[pos].clone();
-                    pos += 1;
-                    Some(line)
-                } else {
-                    None
-                }
-            };
-            if let Some(l) = line {
-                yield l;
-            }
+            // No entries are added once the process has finished,
+            // so if it has finished before reading, everything is read.
             let process_finished = process.exit_status.read().unwrap().is_some();
-            let has_read_all = pos == process.logs.read().unwrap().len();
-            if process_finished && has_read_all {
+            let chunk = cursor.read_available();
+            let has_read_all = chunk.is_empty();
+            if !has_read_all {
+                yield chunk;
+            }
+            if process_finished {
                 return;
             }
             if has_read_all {
@@ -54,3 +502,209 @@ pub(crate) fn stream(process: Arc<ProcessInner>) -> impl Stream<Item = Bytes> {
         }
     }
 }
+
+/// Returns a stream of the entries, which is empty if they have been evicted.
+pub(crate) fn stream(process: Arc<ProcessInner>) -> impl Stream<Item = LogEntry> {
+    let cursor = process.logs.cursor_at(0);
+    stream::iter(cursor)
+        .flat_map(move |cursor| chunks(process.clone(), cursor, 0, true))
+        .flat_map(stream::iter)
+        .map(|(entry, _)| entry)
+}
+
+pub(crate) fn batches(
+    process: Arc<ProcessInner>,
+    query: LogQuery,
+    limits: BatchLimits,
+) -> Result<impl Stream<Item = LogBatch>, LogsEvicted> {
+    let start = process.logs.start(query.start);
+    let cursor = process.logs.cursor_at(start.pos).ok_or(LogsEvicted)?;
+    let chunks = chunks(process, cursor, start.pos, query.follow).map(Ok::<_, Infallible>);
+    Ok(
+        batch(chunks, start, query, limits).map(|batch| match batch {
+            Ok(batch) => batch,
+            Err(e) => match e {},
+        }),
+    )
+}
+
+/// Returns a stream of the logs like `batches`, but reading the entries from where they are stored,
+/// given all of them in order.
+pub(crate) fn stored_batches<S>(
+    process: Arc<ProcessInner>,
+    entries: S,
+    query: LogQuery,
+    limits: BatchLimits,
+) -> impl Stream<Item = Result<LogBatch, IoError>>
+where
+    S: Stream<Item = Result<Record, IoError>>,
+{
+    let start = process.logs.start(query.start);
+    let chunks = async_stream::try_stream! {
+        pin_mut!(entries);
+        let mut pos = 0;
+        let mut chunk = vec![];
+        while let Some(record) = entries.next().await {
+            let record = record?;
+            if pos >= start.pos {
+                chunk.push(record);
+            }
+            pos += 1;
+            if chunk.len() >= STORED_CHUNK {
+                yield mem::take(&mut chunk);
+            }
+        }
+        if !chunk.is_empty() {
+            yield chunk;
+        }
+    };
+    batch(chunks, start, query, limits)
+}
+
+/// Coalesces the lines of a stream of entries, starting at the given position,
+/// into batches within the limits.
+fn batch<C, E>(
+    chunks: C,
+    start: Start,
+    query: LogQuery,
+    limits: BatchLimits,
+) -> impl Stream<Item = Result<LogBatch, E>>
+where
+    C: Stream<Item = Result<Vec<Record>, E>>,
+    E: Unpin,
+{
+    let numbered = query.filter.is_some() || query.metadata;
+    let with_metadata = query.metadata;
+    let mut grep = Grep::new(query.filter);
+    async_stream::stream! {
+        pin_mut!(chunks);
+        let Start { mut pos, run, mut number } = start;
+        let mut batch = LogBatch { run, ..Default::default() };
+        let mut bytes = 0;
+        // When the current batch is sent, unless it fills up first.
+        let mut deadline = None;
+        loop {
+            let chunk = match deadline {
+                None => chunks.next().await,
+                Some(at) => match time::timeout_at(at, chunks.next()).await {
+                    Ok(chunk) => chunk,
+                    Err(_) => {
+                        let run = batch.run;
+                        yield Ok(mem::replace(&mut batch, LogBatch { run, ..Default::default() }));
+                        bytes = 0;
+                        deadline = None;
+                        continue;
+                    }
+                },
+            };
+            let chunk = match chunk {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    yield Err(e);
+                    return;
+                }
+                None => {
+                    if !batch.lines.is_empty() {
+                        yield Ok(batch);
+                    }
+                    return;
+                }
+            };
+            for (entry, metadata) in chunk {
+                let marker_time = metadata.filter(|_| with_metadata).map(|m| m.time);
+                match entry {
+                    LogEntry::Line(line) => {
+                        let metadata = metadata.and_then(EntryMetadata::line);
+                        pos += 1;
+                        number += 1;
+                        for (pos, number, line, metadata) in grep.push((pos, number, line, metadata)) {
+                            if bytes + line.len() > limits.max_bytes && !batch.lines.is_empty() {
+                                let run = batch.run;
+                                yield Ok(mem::replace(&mut batch, LogBatch { run, ..Default::default() }));
+                                bytes = 0;
+                            }
+                            bytes += line.len();
+                            batch.lines.push(line);
+                            if numbered {
+                                batch.line_numbers.push(number);
+                            }
+                            if let Some(metadata) = metadata.filter(|_| with_metadata) {
+                                batch.metadata.push(metadata);
+                            }
+                            batch.cursor = pos;
+                        }
+                    }
+                    LogEntry::Suppressed(suppressed) => {
+                        pos += 1;
+                        let run = batch.run;
+                        batch.cursor = pos;
+                        batch.suppressed = Some(suppressed);
+                        batch.marker_time = marker_time;
+                        yield Ok(mem::replace(&mut batch, LogBatch { run, ..Default::default() }));
+                        bytes = 0;
+                    }
+                    LogEntry::Restart(run) => {
+                        if !batch.lines.is_empty() {
+                            yield Ok(mem::replace(&mut batch, LogBatch::default()));
+                        }
+                        bytes = 0;
+                        batch.run = run;
+                        pos += 1;
+                        grep.restart();
+                        yield Ok(LogBatch { run, cursor: pos, marker_time, ..Default::default() });
+                    }
+                }
+            }
+            if bytes >= limits.max_bytes {
+                let run = batch.run;
+                yield Ok(mem::replace(&mut batch, LogBatch { run, ..Default::default() }));
+                bytes = 0;
+            }
+            deadline = match batch.lines.is_empty() {
+                true => None,
+                false => deadline.or_else(|| Some(Instant::now() + limits.max_delay)),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_limiter() {
+        let limiter = LogLimiter::new(LogRateLimit {
+            lines_per_sec: Some(3),
+            bytes_per_sec: Some(10),
+        });
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let admit = |bytes, millis| {
+            let admission = limiter.admit(bytes, at(millis));
+            (admission.admitted, admission.unmarked)
+        };
+        assert_eq!(admit(1, 0), (true, None));
+        assert_eq!(admit(8, 100), (true, None));
+        // Over the limit of bytes, then of lines
+        assert_eq!(admit(2, 200), (false, None));
+        assert_eq!(admit(1, 300), (true, None));
+        assert_eq!(admit(1, 400), (false, None));
+        // The next window marks the lines suppressed in the previous one
+        let suppressed = Suppressed { lines: 2, bytes: 3 };
+        assert_eq!(admit(5, 1000), (true, Some(suppressed)));
+        // The first line suppressed in a window tells when the window ends
+        let admission = limiter.admit(6, at(1100));
+        assert_eq!(admission.mark_at, Some(at(2000)));
+        assert_eq!(limiter.admit(6, at(1200)).mark_at, None);
+        assert_eq!(
+            limiter.take_suppressed(),
+            Some(Suppressed {
+                lines: 2,
+                bytes: 12
+            })
+        );
+        assert_eq!(limiter.take_suppressed(), None);
+        assert_eq!(admit(1, 2500), (true, None));
+    }
+}
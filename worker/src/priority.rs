@@ -0,0 +1,65 @@
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use nix::libc;
+use tokio::process::Command;
+
+/// The CPU scheduling priority of a process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Priority {
+    /// Niceness of the process (see `setpriority(2)`).
+    /// Lower values than that of the current process require privileges.
+    /// If not given, the niceness is inherited.
+    pub nice: Option<i32>,
+    /// A cgroup (v2) directory to place the process in,
+    /// e.g. one created with `create_cgroup`.
+    pub cgroup: Option<PathBuf>,
+}
+
+/// Creates a cgroup (v2) directory, if it does not exist,
+/// and sets its CPU weight (1 to 10000, 100 being the default of cgroups).
+/// The parent cgroup must have the `cpu` controller enabled for its children.
+pub fn create_cgroup(path: &Path, cpu_weight: u64) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+    fs::write(path.join("cpu.weight"), cpu_weight.to_string())
+}
+
+/// Makes the command apply the priority to the child before executing it.
+pub(crate) fn apply(command: &mut Command, priority: &Priority) -> io::Result<()> {
+    let nice = priority.nice;
+    // Allocated upfront: allocating in the child after `fork` is not safe.
+    let procs = match &priority.cgroup {
+        Some(cgroup) => Some(CString::new(
+            cgroup.join("cgroup.procs").as_os_str().as_bytes(),
+        )?),
+        None => None,
+    };
+    // SAFETY: the closure only makes async-signal-safe system calls.
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(procs) = &procs {
+                // Writing "0" moves the writing process itself.
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                libc::close(fd);
+                if written != 1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::{process::Stdio, time::Duration};
+
+use futures::future;
+use regex::bytes::Regex;
+use tokio::{net::TcpStream, process::Command};
+
+use crate::{buffer::Cursor, LogEntry, ProcessInner};
+
+/// What a probe checks.
+#[derive(Clone, Debug)]
+pub enum ProbeCheck {
+    /// Executes the command, which must exit successfully.
+    Exec(Vec<String>),
+    /// Connects to the TCP address, e.g. `localhost:8080`.
+    Tcp(String),
+    /// Passes once a line matching the regex has been logged by the current run.
+    LogLine(Regex),
+}
+
+impl PartialEq for ProbeCheck {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ProbeCheck::Exec(a), ProbeCheck::Exec(b)) => a == b,
+            (ProbeCheck::Tcp(a), ProbeCheck::Tcp(b)) => a == b,
+            (ProbeCheck::LogLine(a), ProbeCheck::LogLine(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ProbeCheck {}
+
+/// A check run periodically while the process is running.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Probe {
+    pub check: ProbeCheck,
+    /// Delay between checks, starting when the process is (re)started.
+    pub interval: Duration,
+    /// Checks that take longer than this fail.
+    pub timeout: Duration,
+    /// Number of consecutive failed checks after which the probe fails.
+    pub failure_threshold: u32,
+}
+
+impl Probe {
+    /// A probe with the default interval (10 seconds), timeout (1 second) and threshold (3).
+    pub fn new(check: ProbeCheck) -> Self {
+        Self {
+            check,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(1),
+            failure_threshold: 3,
+        }
+    }
+}
+
+/// The probes of a process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Probes {
+    /// Decides whether the process is healthy.
+    pub health: Option<Probe>,
+    /// Decides whether the process is ready, e.g. to serve requests.
+    pub readiness: Option<Probe>,
+    /// Whether a run of the process that has become unhealthy is killed and restarted,
+    /// regardless of the restart mode, though within the restart limit.
+    pub restart_unhealthy: bool,
+}
+
+/// The result of the health probe of a process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Health {
+    /// The probe has not passed or failed yet in the current run.
+    Unknown,
+    Healthy,
+    Unhealthy,
+}
+
+/// The results of the probes of the current run of a process,
+/// `None` for probes that are not configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ProbeStatus {
+    pub health: Option<Health>,
+    pub ready: Option<bool>,
+}
+
+/// Runs the checks of a probe during a single run of a process.
+struct Checker<'a> {
+    probe: &'a Probe,
+    // Position in the logs up to which lines have been matched.
+    cursor: Cursor,
+    matched: bool,
+}
+
+impl<'a> Checker<'a> {
+    fn new(probe: &'a Probe, inner: &'a ProcessInner) -> Self {
+        // Log lines are matched from the start of the run.
+        let cursor = inner
+            .logs
+            .cursor_at(inner.logs.len())
+            .expect("logs are only evicted once the process has finished");
+        Self {
+            probe,
+            cursor,
+            matched: false,
+        }
+    }
+
+    async fn check(&mut self) -> bool {
+        let timeout = self.probe.timeout;
+        let result = match &self.probe.check {
+            ProbeCheck::Exec(argv) => {
+                let child = Command::new(&argv[0])
+                    .args(&argv[1..])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn();
+                match child {
+                    Ok(mut child) => tokio::time::timeout(timeout, child.wait())
+                        .await
+                        .map(|s| s.is_ok_and(|s| s.success())),
+                    Err(_) => Ok(false),
+                }
+            }
+            ProbeCheck::Tcp(addr) => tokio::time::timeout(timeout, TcpStream::connect(addr))
+                .await
+                .map(|s| s.is_ok()),
+            ProbeCheck::LogLine(regex) => {
+                while let Some(entry) = self.cursor.next() {
+                    if let LogEntry::Line(line) = entry {
+                        self.matched |= regex.is_match(line);
+                    }
+                }
+                Ok(self.matched)
+            }
+        };
+        result.unwrap_or(false)
+    }
+
+    /// Checks periodically, calling `update` with whether the probe passes
+    /// whenever that changes: it passes on a successful check,
+    /// and fails after `failure_threshold` consecutive failed checks.
+    /// Returns once the probe fails, if `until_failure` is set, never otherwise.
+    async fn run(&mut self, until_failure: bool, mut update: impl FnMut(bool)) {
+        let mut failures = 0;
+        loop {
+            tokio::time::sleep(self.probe.interval).await;
+            if self.check().await {
+                failures = 0;
+                update(true);
+                continue;
+            }
+            failures += 1;
+            if failures == self.probe.failure_threshold.max(1) {
+                update(false);
+                if until_failure {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Runs the probes of a single run of a process, recording the results in the process.
+/// Returns once the run should be killed for being unhealthy, never otherwise.
+pub(crate) async fn monitor(probes: &Probes, inner: &ProcessInner) {
+    let status = &inner.probe_status;
+    *status.write().unwrap() = ProbeStatus {
+        health: probes.health.as_ref().map(|_| Health::Unknown),
+        ready: probes.readiness.as_ref().map(|_| false),
+    };
+    let health = async {
+        if let Some(probe) = &probes.health {
+            Checker::new(probe, inner)
+                .run(probes.restart_unhealthy, |passed| {
+                    status.write().unwrap().health = Some(match passed {
+                        true => Health::Healthy,
+                        false => Health::Unhealthy,
+                    })
+                })
+                .await;
+            if probes.restart_unhealthy {
+                return;
+            }
+        }
+        future::pending().await
+    };
+    let readiness = async {
+        if let Some(probe) = &probes.readiness {
+            Checker::new(probe, inner)
+                .run(false, |passed| status.write().unwrap().ready = Some(passed))
+                .await;
+        }
+        future::pending::<()>().await
+    };
+    future::select(Box::pin(health), Box::pin(readiness)).await;
+}
@@ -0,0 +1,87 @@
+use std::{process::ExitStatus, time::Duration};
+
+/// When a process is restarted after it exits.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RestartMode {
+    #[default]
+    Never,
+    /// Restart if the process exits with a non-zero code or is killed by a signal.
+    OnFailure,
+    Always,
+}
+
+/// Decides whether and when a process is restarted after it exits.
+/// Processes that have been stopped are never restarted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// Maximum number of restarts. If not given, the process is restarted indefinitely.
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart, doubled after every restart.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between restarts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::Never,
+            max_restarts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Whether a process that has been restarted `restarts` times
+    /// should be restarted after exiting with the given status.
+    pub(crate) fn should_restart(&self, status: ExitStatus, restarts: u32) -> bool {
+        let mode = match self.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => !status.success(),
+            RestartMode::Always => true,
+        };
+        mode && self.within_limit(restarts)
+    }
+
+    /// Whether a process that has been restarted `restarts` times may be restarted again.
+    pub(crate) fn within_limit(&self, restarts: u32) -> bool {
+        self.max_restarts.is_none_or(|max| restarts < max)
+    }
+
+    /// The delay before restarting a process that has been restarted `restarts` times.
+    pub(crate) fn backoff(&self, restarts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(restarts);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |b| b.min(self.max_backoff))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    #[test]
+    fn test_restart_policy() {
+        let policy = RestartPolicy {
+            mode: RestartMode::OnFailure,
+            max_restarts: Some(3),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+        let failed = ExitStatus::from_raw(1 << 8);
+        assert!(policy.should_restart(failed, 0));
+        assert!(!policy.should_restart(failed, 3));
+        assert!(!policy.should_restart(ExitStatus::from_raw(0), 0));
+
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff(3), Duration::from_secs(5));
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+    }
+}